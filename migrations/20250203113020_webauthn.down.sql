@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS "webauthn_challenges";
+
+DROP TABLE IF EXISTS "webauthn_credentials";
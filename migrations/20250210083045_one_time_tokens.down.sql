@@ -0,0 +1,26 @@
+-- Add down migration script here
+ALTER TABLE users
+    ADD COLUMN verification_token VARCHAR(255),
+    ADD COLUMN token_expires_at TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE "magic_link_tokens" (
+    id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX magic_link_tokens_user_id_idx ON magic_link_tokens (user_id);
+
+INSERT INTO magic_link_tokens (user_id, token_hash, expires_at, used_at, created_at)
+SELECT user_id, token_hash, expires_at, used_at, created_at
+FROM one_time_tokens
+WHERE purpose = 'magic_link';
+
+-- Only token hashes are stored, so pending verification and reset tokens
+-- cannot be restored onto users.
+DROP TABLE IF EXISTS "one_time_tokens";
+
+DROP TYPE IF EXISTS token_purpose;
@@ -0,0 +1,45 @@
+-- Add up migration script here
+CREATE TYPE token_purpose AS ENUM ('email_verification', 'password_reset', 'magic_link');
+
+CREATE TABLE "one_time_tokens" (
+    id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose token_purpose NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX one_time_tokens_user_id_purpose_idx ON one_time_tokens (user_id, purpose);
+CREATE INDEX one_time_tokens_expires_at_idx ON one_time_tokens (expires_at);
+
+-- Pending tokens on users were shared by verification and password reset,
+-- and the column does not record which one issued them. Verifying an email
+-- cleared it, so verified users can only have been waiting on a reset.
+-- Reset tokens lived for 30 minutes and verification tokens for 24 hours,
+-- so an unverified user's token with more than 30 minutes left must be a
+-- verification token. The remaining ones could be either and are dropped:
+-- those users can still log in and request a new password reset, but stay
+-- unverified since there is no way to resend a verification email.
+INSERT INTO one_time_tokens (user_id, purpose, token_hash, expires_at)
+SELECT
+    id,
+    CASE WHEN verified THEN 'password_reset'::token_purpose ELSE 'email_verification'::token_purpose END,
+    encode(sha256(verification_token::bytea), 'hex'),
+    token_expires_at
+FROM users
+WHERE verification_token IS NOT NULL
+    AND token_expires_at > NOW()
+    AND (verified OR token_expires_at > NOW() + INTERVAL '30 minutes');
+
+INSERT INTO one_time_tokens (user_id, purpose, token_hash, expires_at, used_at, created_at)
+SELECT user_id, 'magic_link', token_hash, expires_at, used_at, created_at
+FROM magic_link_tokens
+WHERE used_at IS NULL AND expires_at > NOW();
+
+DROP TABLE "magic_link_tokens";
+
+ALTER TABLE users
+    DROP COLUMN verification_token,
+    DROP COLUMN token_expires_at;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS "login_lockouts";
+
+DROP TABLE IF EXISTS "login_attempts";
@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE "login_attempts" (
+    id BIGSERIAL PRIMARY KEY,
+    throttle_key VARCHAR(320) NOT NULL,
+    attempted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_attempts_throttle_key_idx ON login_attempts (throttle_key, attempted_at);
+
+CREATE TABLE "login_lockouts" (
+    throttle_key VARCHAR(320) NOT NULL PRIMARY KEY,
+    locked_until TIMESTAMP WITH TIME ZONE NOT NULL,
+    lockout_count INTEGER NOT NULL DEFAULT 1,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
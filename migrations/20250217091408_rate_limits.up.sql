@@ -0,0 +1,7 @@
+-- Add up migration script here
+CREATE TABLE "rate_limits" (
+    rate_key VARCHAR(400) NOT NULL PRIMARY KEY,
+    tat TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX rate_limits_tat_idx ON rate_limits (tat);
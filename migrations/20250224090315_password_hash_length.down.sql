@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE password_history ALTER COLUMN password_hash TYPE VARCHAR(100);
+ALTER TABLE users ALTER COLUMN password TYPE VARCHAR(100);
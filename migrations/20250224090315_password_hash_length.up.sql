@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- Hashes with a pepper key id or higher costs no longer fit in 100 characters.
+ALTER TABLE users ALTER COLUMN password TYPE VARCHAR(255);
+ALTER TABLE password_history ALTER COLUMN password_hash TYPE VARCHAR(255);
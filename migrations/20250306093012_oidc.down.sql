@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE oauth_authorization_codes
+    DROP COLUMN auth_time,
+    DROP COLUMN nonce;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE oauth_authorization_codes
+    ADD COLUMN nonce VARCHAR(255),
+    ADD COLUMN auth_time TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE oauth_clients
+    DROP COLUMN service_account_id;
+
+ALTER TABLE users
+    DROP COLUMN service_account;
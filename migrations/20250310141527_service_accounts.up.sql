@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE users
+    ADD COLUMN service_account BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE oauth_clients
+    ADD COLUMN service_account_id UUID UNIQUE REFERENCES users(id) ON DELETE CASCADE;
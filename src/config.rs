@@ -2,21 +2,368 @@
 pub struct Config {
     pub database_url: String,
     pub jwt_secret: String,
+    pub jwt_algorithm: String,
+    pub jwt_key_id: String,
+    pub jwt_private_key_path: Option<String>,
+    pub jwt_public_key_path: Option<String>,
+    pub jwt_verification_keys: Option<String>,
     pub jwt_maxage: i64,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub jwt_trust_claims: bool,
+    pub refresh_token_maxage: i64,
+    pub totp_issuer: String,
+    pub webauthn_rp_id: String,
+    pub webauthn_rp_name: String,
+    pub webauthn_origin: String,
+    /// `LOGIN_THROTTLE_BACKEND`, `RATE_LIMIT_BACKEND` and
+    /// `REVOCATION_BACKEND` pick where their state lives. All three default
+    /// to `postgres`, which is shared between instances and survives
+    /// restarts; `memory` keeps it in the process.
+    pub login_throttle_backend: String,
+    pub login_attempt_window: i64,
+    pub login_max_attempts_per_ip: i64,
+    pub login_max_failures_per_email: i64,
+    pub login_lockout_seconds: i64,
+    pub login_lockout_max_seconds: i64,
+    pub mfa_max_attempts: i64,
+    pub rate_limit_backend: String,
+    pub revocation_backend: String,
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    pub password_require_lowercase: bool,
+    pub password_require_uppercase: bool,
+    pub password_require_digit: bool,
+    pub password_require_symbol: bool,
+    pub password_min_strength: u8,
+    pub password_forbid_personal_info: bool,
+    pub breached_passwords_filter: Option<String>,
+    pub password_history_size: i64,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub password_pepper: Option<String>,
+    pub password_pepper_id: String,
+    pub social_providers: Vec<SocialProviderConfig>,
+    pub ldap_backend: String,
+    pub ldap_order: String,
+    pub ldap_url: String,
+    pub ldap_starttls: bool,
+    pub ldap_timeout: u64,
+    pub ldap_bind_dn: Option<String>,
+    pub ldap_bind_password: Option<String>,
+    pub ldap_user_dn_template: Option<String>,
+    pub ldap_base_dn: String,
+    pub ldap_user_filter: String,
+    pub ldap_email_attribute: String,
+    pub ldap_name_attribute: String,
+    pub ldap_group_attribute: String,
+    pub ldap_group_base_dn: Option<String>,
+    pub ldap_group_filter: String,
+    pub ldap_admin_groups: Vec<String>,
+    pub ldap_allowed_groups: Vec<String>,
+    pub ldap_memory_directory: Option<String>,
+    pub saml_sp_entity_id: String,
+    pub saml_idp_entity_id: Option<String>,
+    pub saml_idp_sso_url: Option<String>,
+    pub saml_idp_certificate_path: Option<String>,
+    pub saml_email_attribute: String,
+    pub saml_name_attribute: String,
+    pub saml_role_attribute: String,
+    pub saml_admin_values: Vec<String>,
+    pub saml_allow_idp_initiated: bool,
+    /// Where this service is reachable from browsers and relying parties,
+    /// without a trailing slash.
+    pub public_url: String,
+    /// Where the frontend with the login and consent pages is served.
+    pub frontend_url: String,
     pub port: u16,
 }
 
+/// Splits a semicolon-separated list. Group DNs contain commas themselves.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// An external OAuth 2.0 / OpenID Connect identity provider users can sign
+/// in with. Claim names default to the OIDC ones and can be overridden for
+/// plain OAuth 2.0 providers whose user endpoint uses other fields.
+///
+/// Setting an issuer and JWKS URL marks the provider as OpenID Connect: a
+/// nonce is sent with each sign-in and the callback requires an ID token
+/// signed by the provider, issued to this client, for that nonce.
+#[derive(Debug, Clone)]
+pub struct SocialProviderConfig {
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    pub scopes: String,
+    pub subject_claim: String,
+    pub email_claim: String,
+    pub email_verified_claim: String,
+    pub name_claim: String,
+    pub issuer: Option<String>,
+    pub jwks_url: Option<String>,
+}
+
+impl SocialProviderConfig {
+    /// Reads the `SOCIAL_<NAME>_*` variables of the provider `name`.
+    fn from_env(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid social provider name: {}", name).into());
+        }
+
+        let prefix = format!("SOCIAL_{}_", name.to_ascii_uppercase().replace('-', "_"));
+        let required = |key: &str| {
+            std::env::var(format!("{}{}", prefix, key))
+                .map_err(|_| format!("{}{} must be set", prefix, key))
+        };
+        let optional = |key: &str, default: &str| {
+            std::env::var(format!("{}{}", prefix, key))
+                .unwrap_or_else(|_| default.to_string())
+        };
+
+        let issuer = std::env::var(format!("{}ISSUER", prefix)).ok();
+        let jwks_url = std::env::var(format!("{}JWKS_URL", prefix)).ok();
+
+        if issuer.is_some() != jwks_url.is_some() {
+            return Err(format!("{}ISSUER and {}JWKS_URL must be set together", prefix, prefix).into());
+        }
+
+        Ok(Self {
+            name: name.to_ascii_lowercase(),
+            client_id: required("CLIENT_ID")?,
+            client_secret: required("CLIENT_SECRET")?,
+            authorize_url: required("AUTHORIZE_URL")?,
+            token_url: required("TOKEN_URL")?,
+            userinfo_url: required("USERINFO_URL")?,
+            scopes: optional("SCOPES", "openid email profile"),
+            subject_claim: optional("SUBJECT_CLAIM", "sub"),
+            email_claim: optional("EMAIL_CLAIM", "email"),
+            email_verified_claim: optional("EMAIL_VERIFIED_CLAIM", "email_verified"),
+            name_claim: optional("NAME_CLAIM", "name"),
+            issuer,
+            jwks_url,
+        })
+    }
+
+    pub fn is_oidc(&self) -> bool {
+        self.issuer.is_some()
+    }
+}
+
 impl Config {
     pub fn init() -> Result<Self, Box<dyn std::error::Error>> {
         let database_url = std::env::var("DATABASE_URL")?;
         let jwt_secret = std::env::var("JWT_SECRET")?;
+        let jwt_algorithm = std::env::var("JWT_ALGORITHM")
+            .unwrap_or_else(|_| "HS256".to_string());
+        let jwt_key_id = std::env::var("JWT_KEY_ID")
+            .unwrap_or_else(|_| "default".to_string());
+        let jwt_private_key_path = std::env::var("JWT_PRIVATE_KEY_PATH").ok();
+        let jwt_public_key_path = std::env::var("JWT_PUBLIC_KEY_PATH").ok();
+        let jwt_verification_keys = std::env::var("JWT_VERIFICATION_KEYS").ok();
         let jwt_maxage = std::env::var("JWT_MAXAGE")?;
+        let jwt_issuer = std::env::var("JWT_ISSUER")
+            .unwrap_or_else(|_| "auth-validator".to_string());
+        let jwt_audience = std::env::var("JWT_AUDIENCE")
+            .unwrap_or_else(|_| "auth-validator".to_string());
+        let jwt_trust_claims = std::env::var("JWT_TRUST_CLAIMS")
+            .unwrap_or_else(|_| "false".to_string());
+        let refresh_token_maxage = std::env::var("REFRESH_TOKEN_MAXAGE")
+            .unwrap_or_else(|_| "43200".to_string());
+        let totp_issuer = std::env::var("TOTP_ISSUER")
+            .unwrap_or_else(|_| "Auth Validator".to_string());
+        let webauthn_rp_id = std::env::var("WEBAUTHN_RP_ID")
+            .unwrap_or_else(|_| "localhost".to_string());
+        let webauthn_rp_name = std::env::var("WEBAUTHN_RP_NAME")
+            .unwrap_or_else(|_| "Auth Validator".to_string());
+        let webauthn_origin = std::env::var("WEBAUTHN_ORIGIN")
+            .unwrap_or_else(|_| "http://localhost:3000".to_string());
+        let login_throttle_backend = std::env::var("LOGIN_THROTTLE_BACKEND")
+            .unwrap_or_else(|_| "postgres".to_string());
+        let login_attempt_window = std::env::var("LOGIN_ATTEMPT_WINDOW")
+            .unwrap_or_else(|_| "900".to_string());
+        let login_max_attempts_per_ip = std::env::var("LOGIN_MAX_ATTEMPTS_PER_IP")
+            .unwrap_or_else(|_| "50".to_string());
+        let login_max_failures_per_email = std::env::var("LOGIN_MAX_FAILURES_PER_EMAIL")
+            .unwrap_or_else(|_| "5".to_string());
+        let login_lockout_seconds = std::env::var("LOGIN_LOCKOUT_SECONDS")
+            .unwrap_or_else(|_| "60".to_string());
+        let login_lockout_max_seconds = std::env::var("LOGIN_LOCKOUT_MAX_SECONDS")
+            .unwrap_or_else(|_| "3600".to_string());
+        let mfa_max_attempts = std::env::var("MFA_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "5".to_string());
+        let rate_limit_backend = std::env::var("RATE_LIMIT_BACKEND")
+            .unwrap_or_else(|_| "postgres".to_string());
+        let revocation_backend = std::env::var("REVOCATION_BACKEND")
+            .unwrap_or_else(|_| "postgres".to_string());
+        let password_min_length = std::env::var("PASSWORD_MIN_LENGTH")
+            .unwrap_or_else(|_| "8".to_string());
+        let password_max_length = std::env::var("PASSWORD_MAX_LENGTH")
+            .unwrap_or_else(|_| "64".to_string());
+        let password_require_lowercase = std::env::var("PASSWORD_REQUIRE_LOWERCASE")
+            .unwrap_or_else(|_| "false".to_string());
+        let password_require_uppercase = std::env::var("PASSWORD_REQUIRE_UPPERCASE")
+            .unwrap_or_else(|_| "false".to_string());
+        let password_require_digit = std::env::var("PASSWORD_REQUIRE_DIGIT")
+            .unwrap_or_else(|_| "false".to_string());
+        let password_require_symbol = std::env::var("PASSWORD_REQUIRE_SYMBOL")
+            .unwrap_or_else(|_| "false".to_string());
+        let password_min_strength = std::env::var("PASSWORD_MIN_STRENGTH")
+            .unwrap_or_else(|_| "2".to_string());
+        let password_forbid_personal_info = std::env::var("PASSWORD_FORBID_PERSONAL_INFO")
+            .unwrap_or_else(|_| "true".to_string());
+        let breached_passwords_filter = std::env::var("BREACHED_PASSWORDS_FILTER").ok();
+        let password_history_size = std::env::var("PASSWORD_HISTORY_SIZE")
+            .unwrap_or_else(|_| "5".to_string());
+        let argon2_memory_kib = std::env::var("ARGON2_MEMORY_KIB")
+            .unwrap_or_else(|_| "19456".to_string());
+        let argon2_iterations = std::env::var("ARGON2_ITERATIONS")
+            .unwrap_or_else(|_| "2".to_string());
+        let argon2_parallelism = std::env::var("ARGON2_PARALLELISM")
+            .unwrap_or_else(|_| "1".to_string());
+        let password_pepper = std::env::var("PASSWORD_PEPPER").ok();
+        let password_pepper_id = std::env::var("PASSWORD_PEPPER_ID")
+            .unwrap_or_else(|_| "1".to_string());
+        let social_providers = std::env::var("SOCIAL_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(SocialProviderConfig::from_env)
+            .collect::<Result<Vec<_>, _>>()?;
+        let ldap_backend = std::env::var("LDAP_BACKEND")
+            .unwrap_or_else(|_| "none".to_string());
+        let ldap_order = std::env::var("LDAP_ORDER")
+            .unwrap_or_else(|_| "after".to_string());
+        let ldap_url = std::env::var("LDAP_URL")
+            .unwrap_or_else(|_| "ldap://localhost:389".to_string());
+        let ldap_starttls = std::env::var("LDAP_STARTTLS")
+            .unwrap_or_else(|_| "false".to_string());
+        let ldap_timeout = std::env::var("LDAP_TIMEOUT")
+            .unwrap_or_else(|_| "5".to_string());
+        let ldap_bind_dn = std::env::var("LDAP_BIND_DN").ok();
+        let ldap_bind_password = std::env::var("LDAP_BIND_PASSWORD").ok();
+        let ldap_user_dn_template = std::env::var("LDAP_USER_DN_TEMPLATE").ok();
+        let ldap_base_dn = std::env::var("LDAP_BASE_DN")
+            .unwrap_or_default();
+        let ldap_user_filter = std::env::var("LDAP_USER_FILTER")
+            .unwrap_or_else(|_| "(mail={username})".to_string());
+        let ldap_email_attribute = std::env::var("LDAP_EMAIL_ATTRIBUTE")
+            .unwrap_or_else(|_| "mail".to_string());
+        let ldap_name_attribute = std::env::var("LDAP_NAME_ATTRIBUTE")
+            .unwrap_or_else(|_| "cn".to_string());
+        let ldap_group_attribute = std::env::var("LDAP_GROUP_ATTRIBUTE")
+            .unwrap_or_else(|_| "memberOf".to_string());
+        let ldap_group_base_dn = std::env::var("LDAP_GROUP_BASE_DN").ok();
+        let ldap_group_filter = std::env::var("LDAP_GROUP_FILTER")
+            .unwrap_or_else(|_| "(member={dn})".to_string());
+        let ldap_admin_groups = std::env::var("LDAP_ADMIN_GROUPS")
+            .unwrap_or_default();
+        let ldap_allowed_groups = std::env::var("LDAP_ALLOWED_GROUPS")
+            .unwrap_or_default();
+        let ldap_memory_directory = std::env::var("LDAP_MEMORY_DIRECTORY").ok();
+        let saml_sp_entity_id = std::env::var("SAML_SP_ENTITY_ID")
+            .unwrap_or_else(|_| "http://localhost:8000/api/saml/metadata".to_string());
+        let saml_idp_entity_id = std::env::var("SAML_IDP_ENTITY_ID").ok();
+        let saml_idp_sso_url = std::env::var("SAML_IDP_SSO_URL").ok();
+        let saml_idp_certificate_path = std::env::var("SAML_IDP_CERTIFICATE_PATH").ok();
+        let saml_email_attribute = std::env::var("SAML_EMAIL_ATTRIBUTE")
+            .unwrap_or_else(|_| "email".to_string());
+        let saml_name_attribute = std::env::var("SAML_NAME_ATTRIBUTE")
+            .unwrap_or_else(|_| "name".to_string());
+        let saml_role_attribute = std::env::var("SAML_ROLE_ATTRIBUTE")
+            .unwrap_or_else(|_| "role".to_string());
+        let saml_admin_values = std::env::var("SAML_ADMIN_VALUES")
+            .unwrap_or_default();
+        let saml_allow_idp_initiated = std::env::var("SAML_ALLOW_IDP_INITIATED")
+            .unwrap_or_else(|_| "false".to_string());
+        let public_url = std::env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| "http://localhost:8000".to_string());
+        let frontend_url = std::env::var("FRONTEND_URL")
+            .unwrap_or_else(|_| "http://localhost:5173".to_string());
         let port = std::env::var("PORT")?;
 
         let config = Self {
             database_url,
             jwt_secret,
+            jwt_algorithm,
+            jwt_key_id,
+            jwt_private_key_path,
+            jwt_public_key_path,
+            jwt_verification_keys,
             jwt_maxage: jwt_maxage.parse::<i64>()?,
+            jwt_issuer,
+            jwt_audience,
+            jwt_trust_claims: jwt_trust_claims.parse::<bool>()?,
+            refresh_token_maxage: refresh_token_maxage.parse::<i64>()?,
+            totp_issuer,
+            webauthn_rp_id,
+            webauthn_rp_name,
+            webauthn_origin,
+            login_throttle_backend,
+            login_attempt_window: login_attempt_window.parse::<i64>()?,
+            login_max_attempts_per_ip: login_max_attempts_per_ip.parse::<i64>()?,
+            login_max_failures_per_email: login_max_failures_per_email.parse::<i64>()?,
+            login_lockout_seconds: login_lockout_seconds.parse::<i64>()?,
+            login_lockout_max_seconds: login_lockout_max_seconds.parse::<i64>()?,
+            mfa_max_attempts: mfa_max_attempts.parse::<i64>()?,
+            rate_limit_backend,
+            revocation_backend,
+            password_min_length: password_min_length.parse::<usize>()?,
+            password_max_length: password_max_length.parse::<usize>()?,
+            password_require_lowercase: password_require_lowercase.parse::<bool>()?,
+            password_require_uppercase: password_require_uppercase.parse::<bool>()?,
+            password_require_digit: password_require_digit.parse::<bool>()?,
+            password_require_symbol: password_require_symbol.parse::<bool>()?,
+            password_min_strength: password_min_strength.parse::<u8>()?,
+            password_forbid_personal_info: password_forbid_personal_info.parse::<bool>()?,
+            breached_passwords_filter,
+            password_history_size: password_history_size.parse::<i64>()?,
+            argon2_memory_kib: argon2_memory_kib.parse::<u32>()?,
+            argon2_iterations: argon2_iterations.parse::<u32>()?,
+            argon2_parallelism: argon2_parallelism.parse::<u32>()?,
+            password_pepper,
+            password_pepper_id,
+            social_providers,
+            ldap_backend,
+            ldap_order,
+            ldap_url,
+            ldap_starttls: ldap_starttls.parse::<bool>()?,
+            ldap_timeout: ldap_timeout.parse::<u64>()?,
+            ldap_bind_dn,
+            ldap_bind_password,
+            ldap_user_dn_template,
+            ldap_base_dn,
+            ldap_user_filter,
+            ldap_email_attribute,
+            ldap_name_attribute,
+            ldap_group_attribute,
+            ldap_group_base_dn,
+            ldap_group_filter,
+            ldap_admin_groups: split_list(&ldap_admin_groups),
+            ldap_allowed_groups: split_list(&ldap_allowed_groups),
+            ldap_memory_directory,
+            saml_sp_entity_id,
+            saml_idp_entity_id,
+            saml_idp_sso_url,
+            saml_idp_certificate_path,
+            saml_email_attribute,
+            saml_name_attribute,
+            saml_role_attribute,
+            saml_admin_values: split_list(&saml_admin_values),
+            saml_allow_idp_initiated: saml_allow_idp_initiated.parse::<bool>()?,
+            public_url: public_url.trim_end_matches('/').to_string(),
+            frontend_url: frontend_url.trim_end_matches('/').to_string(),
             port: port.parse::<u16>()?,
         };
 
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json
+};
+use chrono::{Duration, Utc};
+use validator::Validate;
+
+use crate::{
+    database::AccessTokenExt,
+    dtos::{
+        AccessTokenCreatedResponseDto,
+        AccessTokenListResponseDto,
+        CreateAccessTokenDto,
+        FilterAccessTokenDto,
+        Response
+    },
+    error::{
+        ErrorMessage,
+        HttpError
+    },
+    middleware::JWTAuthMiddleware,
+    utils::token,
+    AppState
+};
+
+pub async fn get_access_tokens(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    let access_tokens = app_state.db_client
+        .get_user_access_tokens(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = AccessTokenListResponseDto {
+        status: "success".to_string(),
+        tokens: FilterAccessTokenDto::filter_access_tokens(&access_tokens),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn create_access_token(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<CreateAccessTokenDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let mut scopes = body.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    if let Some(scope) = scopes.iter().find(|scope| !token::ACCESS_TOKEN_SCOPES.contains(&scope.as_str())) {
+        return Err(HttpError::bad_request(ErrorMessage::UnknownScope(scope.to_string()).to_string()));
+    }
+
+    let (access_token, token_prefix) = token::generate_access_token();
+    let expires_at = Utc::now() + Duration::days(body.expires_in_days);
+
+    let saved = app_state.db_client
+        .save_access_token(
+            user.user.id,
+            &body.name,
+            &token_prefix,
+            &token::hash_token(&access_token),
+            &scopes,
+            expires_at,
+        )
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = AccessTokenCreatedResponseDto {
+        status: "success".to_string(),
+        token: access_token,
+        access_token: FilterAccessTokenDto::filter_access_token(&saved),
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn revoke_access_token(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Path(token_id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, HttpError> {
+    let revoked = app_state.db_client
+        .revoke_access_token(user.user.id, token_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !revoked {
+        return Err(HttpError::new("Access token not found".to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Access token revoked successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use crate::test_support;
+
+    async fn create_token(router: &axum::Router, session: &str, scopes: Value) -> Value {
+        let request = test_support::json_request(
+            Method::POST,
+            "/api/users/me/tokens",
+            Some(session),
+            json!({ "name": "ci", "scopes": scopes, "expiresInDays": 30 }),
+        );
+        let response = test_support::send(router, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        test_support::body_json(response).await
+    }
+
+    async fn status(router: &axum::Router, method: Method, uri: &str, token: &str, body: Value) -> StatusCode {
+        let request = test_support::json_request(method, uri, Some(token), body);
+
+        test_support::send(router, request).await.status()
+    }
+
+    #[sqlx::test]
+    async fn limits_access_tokens_to_their_scopes(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "scoped@example.com", "violet lantern orbit").await;
+        let session = test_support::login(&router, "scoped@example.com", "violet lantern orbit").await;
+        let created = create_token(&router, &session, json!(["profile:read"])).await;
+        let token = created["token"].as_str().unwrap();
+
+        assert_eq!(status(&router, Method::GET, "/api/users/me", token, json!({})).await, StatusCode::OK);
+        assert_eq!(
+            status(&router, Method::PUT, "/api/users/name", token, json!({ "name": "Renamed" })).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&router, Method::GET, "/api/users/me/tokens", token, json!({})).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[sqlx::test]
+    async fn refuses_revoked_access_tokens(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "revoked@example.com", "violet lantern orbit").await;
+        let session = test_support::login(&router, "revoked@example.com", "violet lantern orbit").await;
+        let created = create_token(&router, &session, json!(["profile:read"])).await;
+        let token = created["token"].as_str().unwrap();
+        let token_id = created["accessToken"]["id"].as_str().unwrap();
+
+        let uri = format!("/api/users/me/tokens/{token_id}");
+        assert_eq!(status(&router, Method::DELETE, &uri, &session, json!({})).await, StatusCode::OK);
+        assert_eq!(status(&router, Method::GET, "/api/users/me", token, json!({})).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn refuses_unknown_scopes(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "unknown@example.com", "violet lantern orbit").await;
+        let session = test_support::login(&router, "unknown@example.com", "violet lantern orbit").await;
+        let request = test_support::json_request(
+            Method::POST,
+            "/api/users/me/tokens",
+            Some(&session),
+            json!({ "name": "ci", "scopes": ["everything"], "expiresInDays": 30 }),
+        );
+
+        assert_eq!(test_support::send(&router, request).await.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,31 +1,53 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+use axum::extract::rejection::JsonRejection;
+use axum::extract::ConnectInfo;
 use axum::extract::Query;
+use axum::extract::Request;
 use axum::http::header;
 use axum::http::HeaderMap;
+use axum::http::HeaderValue;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Redirect;
 use axum::Extension;
 use axum::Json;
 use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::cookie::CookieJar;
 use validator::Validate;
-use chrono::{Utc, Duration};
+use chrono::{DateTime, Utc, Duration};
 
+use crate::database::IdentityExt;
+use crate::database::OneTimeTokenExt;
+use crate::database::PasswordHistoryExt;
+use crate::database::RefreshTokenExt;
+use crate::database::SessionExt;
 use crate::database::UserExt;
 use crate::dtos::ForgotPasswordRequestDto;
 use crate::dtos::LoginUserDto;
+use crate::dtos::MagicLinkQueryDto;
+use crate::dtos::MagicLinkRequestDto;
+use crate::dtos::MfaChallengeResponseDto;
+use crate::dtos::MfaLoginDto;
+use crate::dtos::RefreshTokenDto;
 use crate::dtos::ResetPasswordDto;
 use crate::dtos::Response;
 use crate::dtos::UserLoginResponseDto;
 use crate::dtos::VerifyEmailQueryDto;
 use crate::email::mails::send_forgot_password_email;
+use crate::email::mails::send_magic_link_email;
 use crate::email::mails::send_verification_email;
 use crate::email::mails::send_welcome_email;
+use crate::controller::two_factor::two_factor_enabled;
+use crate::controller::two_factor::verify_second_factor;
 use crate::error::ErrorMessage;
 use crate::error::HttpError;
-use crate::utils::password;
+use crate::middleware::JWTAuthMiddleware;
+use crate::models::{Session, TokenPurpose, User, UserRole};
+use crate::utils::ldap;
 use crate::utils::token;
+use crate::utils::token::TokenClaims;
 use crate::{dtos::RegisterUserDto, AppState};
 
 
@@ -36,18 +58,19 @@ pub async fn register(
     body.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
-    let verification_token = uuid::Uuid::new_v4().to_string();
-    let expires_at = Utc::now() + Duration::hours(24);
+    app_state.password_policy.check(&body.password, &[&body.name, &body.email])?;
 
-    let hashed_password = password::hash_password(&body.password)
+    let hashed_password = app_state.password_hashing.hash_password(&body.password)
         .map_err(|e|HttpError::server_error(e.to_string()))?;
 
     let result = app_state.db_client
-        .save_user(&body.name, &body.email, &hashed_password, &verification_token, expires_at)
+        .save_user(&body.name, &body.email, &hashed_password)
         .await;
 
     match result {
-        Ok(_user) => {
+        Ok(user) => {
+            let verification_token = issue_one_time_token(&app_state, user.id, TokenPurpose::EmailVerification).await?;
+
             let send_email_result = send_verification_email(&body.email, &body.name, &verification_token).await;
 
             if let Err(e) = send_email_result {
@@ -74,117 +97,716 @@ pub async fn register(
 
 pub async fn login(
     Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(body): Json<LoginUserDto>
 ) -> Result<impl IntoResponse, HttpError> {
     body.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let throttle = &app_state.login_throttle;
+
+    throttle.check_ip(addr.ip()).await?;
+    throttle.check_account(&body.email).await?;
     
+    let directory_first = app_state.env.ldap_order == "before";
+
+    let mut user = None;
+
+    if directory_first {
+        user = directory_login(&app_state, &body.email, &body.password).await?;
+    }
+
+    if user.is_none() {
+        user = local_login(&app_state, &body.email, &body.password).await?;
+    }
+
+    if user.is_none() && !directory_first {
+        user = directory_login(&app_state, &body.email, &body.password).await?;
+    }
+
+    let Some(user) = user else {
+        throttle.record_failure(&body.email).await?;
+
+        return Err(HttpError::unauthorized(ErrorMessage::WrongCredentials.to_string()));
+    };
+
+    // Failures are only cleared once the second factor passes too, so
+    // fresh MFA challenges can't be used to reset the account's count.
+    if two_factor_enabled(&app_state, user.id).await? {
+        let mfa_token = token::create_mfa_token(
+            &user.id.to_string(),
+            &app_state.key_ring,
+            &app_state.env
+        )
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let response = MfaChallengeResponseDto {
+            status: "mfa_required".to_string(),
+            mfa_token,
+        };
+
+        return Ok(Json(response).into_response());
+    }
+
+    throttle.record_success(&body.email).await?;
+
+    create_login_session(&app_state, &user, &headers, addr).await
+}
+
+/// Checks the password against the user's local hash.
+async fn local_login(app_state: &AppState, email: &str, password: &str) -> Result<Option<User>, HttpError> {
     let result = app_state.db_client
-        .get_user(None, None, Some(&body.email), None)
+        .get_user(None, None, Some(email))
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::WrongCredentials.to_string()))?;
+    let Some(user) = result else {
+        return Ok(None);
+    };
+
+    // Users provisioned from a directory or identity provider have no local password.
+    if user.password.is_empty() {
+        return Ok(None);
+    }
 
-    let password_valid = password::compare(&body.password, &user.password)
+    let password_valid = app_state.password_hashing.compare(password, &user.password)
         .map_err(|_| HttpError::bad_request(ErrorMessage::WrongCredentials.to_string()))?;
 
-    if password_valid {
-        let token = token::create_token(
-            &user.id.to_string(), 
-            &app_state.env.jwt_secret.as_bytes(), 
-            app_state.env.jwt_maxage
-        )
+    if !password_valid {
+        return Ok(None);
+    }
+
+    rehash_if_outdated(app_state, &user, password).await;
+
+    Ok(Some(user))
+}
+
+/// Checks the credentials against the LDAP directory, if one is configured,
+/// provisioning the user on their first login and syncing their role from
+/// their groups. An unreachable directory is logged and treated as a miss
+/// so local accounts can still log in.
+async fn directory_login(app_state: &AppState, email: &str, password: &str) -> Result<Option<User>, HttpError> {
+    let Some(directory) = &app_state.directory else {
+        return Ok(None);
+    };
+
+    let directory_user = match directory.authenticate(email, password).await {
+        Ok(Some(directory_user)) if ldap::is_allowed(&directory_user, &app_state.env) => directory_user,
+        Ok(_) => return Ok(None),
+        Err(e) => {
+            eprintln!("LDAP authentication failed: {}", e);
+            return Ok(None);
+        }
+    };
+
+    let role = ldap::map_role(&directory_user, &app_state.env);
+    let user = provision_user(
+        app_state,
+        ldap::PROVIDER,
+        &directory_user.dn,
+        &directory_user.email,
+        &directory_user.name,
+        role,
+    ).await?;
+
+    Ok(Some(user))
+}
+
+/// Finds or creates the user for an account a trusted directory or SAML
+/// IdP vouched for, linked through an identity keyed by `provider` and
+/// `subject`. Provisioned users are verified and have no local password.
+/// `role`, when the source maps one, replaces the user's role.
+pub async fn provision_user(
+    app_state: &AppState,
+    provider: &str,
+    subject: &str,
+    email: &str,
+    name: &str,
+    role: Option<UserRole>,
+) -> Result<User, HttpError> {
+    let identity = app_state.db_client
+        .get_identity(provider, subject)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        let cookie_duration = time::Duration::minutes(app_state.env.jwt_maxage * 60);
-        let cookie = Cookie::build(("token", token.clone()))
-            .path("/")
-            .max_age(cookie_duration)
-            .http_only(true)
-            .build();
+    let user = match identity {
+        Some(identity) => {
+            app_state.db_client
+                .touch_identity(identity.id, Some(email))
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        let response = axum::response::Json(UserLoginResponseDto {
-            status: "success".to_string(),
-            token,
-        });
+            app_state.db_client
+                .get_user(Some(identity.user_id), None, None)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+                .ok_or_else(|| HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?
+        },
+        None => {
+            let result = app_state.db_client
+                .get_user(None, None, Some(email))
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        let mut headers = HeaderMap::new();
+            match result {
+                Some(user) => link_provisioned_user(app_state, user, provider, subject, email).await?,
+                None => {
+                    let name: String = name.chars().take(100).collect();
 
-        headers.append(
-            header::SET_COOKIE,
-            cookie.to_string().parse().unwrap(), 
-        );
+                    let user = app_state.db_client
+                        .save_user(name, email.to_string(), String::new())
+                        .await
+                        .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        let mut response = response.into_response();
-        response.headers_mut().extend(headers);
+                    app_state.db_client
+                        .verify_user(user.id)
+                        .await
+                        .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        Ok(response)
-    } else {
-        Err(HttpError::unauthorized(ErrorMessage::WrongCredentials.to_string()))
+                    app_state.db_client
+                        .save_identity(user.id, provider, subject, Some(email))
+                        .await
+                        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+                    User { verified: true, ..user }
+                }
+            }
+        },
+    };
+
+    match role {
+        Some(role) if role != user.role => app_state.db_client
+            .update_user_role(user.id, role)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string())),
+        _ => Ok(user),
     }
 }
 
-pub async fn verify_email(
-    Query(query_params): Query<VerifyEmailQueryDto>,
+/// Links an existing user with the same email to `provider`. Only accounts
+/// nobody can sign in to yet are linked this way: ones with a local password,
+/// another linked identity or a service account are refused, since the
+/// provider vouching for the email does not prove it is the same person. A
+/// user already linked to the provider under another subject, as with
+/// transient SAML NameIDs, stays linked.
+async fn link_provisioned_user(
+    app_state: &AppState,
+    user: User,
+    provider: &str,
+    subject: &str,
+    email: &str,
+) -> Result<User, HttpError> {
+    let identities = app_state.db_client
+        .get_user_identities(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if let Some(identity) = identities.iter().find(|identity| identity.provider == provider) {
+        app_state.db_client
+            .touch_identity(identity.id, Some(email))
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        return Ok(user);
+    }
+
+    if user.service_account || !user.password.is_empty() || !identities.is_empty() {
+        return Err(HttpError::unquie_constraint_violation(ErrorMessage::ExternalAccountExists.to_string()));
+    }
+
+    app_state.db_client
+        .save_identity(user.id, provider, subject, Some(email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(user)
+}
+
+/// Upgrades a hash made with outdated Argon2 settings or pepper. Failures
+/// are logged rather than failing the login.
+async fn rehash_if_outdated(app_state: &AppState, user: &User, password: &str) {
+    let hashing = &app_state.password_hashing;
+
+    if !hashing.needs_rehash(&user.password) {
+        return;
+    }
+
+    let result = match hashing.hash_password(password) {
+        Ok(new_hash) => app_state.db_client
+            .rehash_user_password(user.id, &user.password, &new_hash)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to rehash password: {}", e);
+    }
+}
+
+pub async fn login_mfa(
     Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(body): Json<MfaLoginDto>
 ) -> Result<impl IntoResponse, HttpError> {
-    query_params.validate()
+    body.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
+    let throttle = &app_state.login_throttle;
+
+    throttle.check_ip(addr.ip()).await?;
+
+    let claims = token::decode_mfa_token(&body.mfa_token, &app_state.key_ring, &app_state.env)?;
+
+    let user_id = uuid::Uuid::parse_str(&claims.sub)
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
     let result = app_state.db_client
-        .get_user(None, None, None, Some(&query_params.token))
+        .get_user(Some(user_id), None, None)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    throttle.check_challenge(&claims.jti).await?;
+    throttle.check_account(&user.email).await?;
+
+    let issued_at = DateTime::from_timestamp(claims.iat, 0).unwrap_or_else(Utc::now);
+    let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+
+    // Wrong codes count toward the account lockout as well as the
+    // challenge's own attempt limit.
+    if !verify_second_factor(&app_state, user.id, &body.code).await? {
+        throttle.record_failure(&user.email).await?;
+        throttle.record_challenge_failure(&claims.jti, issued_at, expires_at).await?;
+
+        return Err(HttpError::unauthorized(ErrorMessage::InvalidTwoFactorCode.to_string()));
+    }
+
+    throttle.spend_challenge(&claims.jti, expires_at).await?;
+    throttle.record_success(&user.email).await?;
+
+    create_login_session(&app_state, &user, &headers, addr).await
+}
+
+pub async fn refresh(
+    cookie_jar: CookieJar,
+    Extension(app_state): Extension<Arc<AppState>>,
+    body: Result<Json<RefreshTokenDto>, JsonRejection>,
+) -> Result<impl IntoResponse, HttpError> {
+    let refresh_token = cookie_jar
+        .get("refresh_token")
+        .map(|cookie| cookie.value().to_string())
+        .or_else(|| body.ok().map(|Json(body)| body.refresh_token))
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::TokenNotProvided.to_string()))?;
+
+    let result = app_state.db_client
+        .get_refresh_token(&token::hash_token(&refresh_token))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let stored = result.ok_or(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    if stored.revoked_at.is_some() {
+        app_state.db_client
+            .revoke_session(stored.user_id, stored.family_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        return Err(HttpError::unauthorized(ErrorMessage::RefreshTokenReused.to_string()));
+    }
 
-    if let Some(expires_at) = user.token_expires_at {
-        if Utc::now() > expires_at {
-            return Err(HttpError::bad_request("Verification token expired".to_string()))?;
-        } 
-    } else {
-        return Err(HttpError::bad_request("Invalid verification token".to_string()))?;
+    if Utc::now() > stored.expires_at {
+        return Err(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()));
     }
 
+    let session = app_state.db_client
+        .touch_session(stored.family_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::SessionRevoked.to_string()))?;
+
+    let user = app_state.db_client
+        .get_user(Some(stored.user_id), None, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    let new_refresh_token = token::generate_opaque_token();
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.refresh_token_maxage);
+
+    let rotated = app_state.db_client
+        .rotate_refresh_token(stored.id, &token::hash_token(&new_refresh_token), expires_at)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if rotated.is_none() {
+        app_state.db_client
+            .revoke_session(stored.user_id, stored.family_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        return Err(HttpError::unauthorized(ErrorMessage::RefreshTokenReused.to_string()));
+    }
+
+    login_response(&app_state, &user, session.id, new_refresh_token)
+}
+
+pub async fn logout(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
     app_state.db_client
-        .verifed_token(&query_params.token)
+        .revoke_session(user.user.id, user.session_id)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let send_welcome_email_result = send_welcome_email(&user.email, &user.name).await;
-    
-    if let Err(e) = send_welcome_email_result {
-        eprintln!("Failed to send welcome email: {}", e);
+    Ok(logout_response("You have been logged out."))
+}
+
+pub async fn logout_all(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    app_state.db_client
+        .revoke_user_sessions(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(logout_response("You have been logged out of all sessions."))
+}
+
+/// Forward-auth target for nginx `auth_request` and Traefik ForwardAuth.
+/// Identifies the caller to the upstream app through `X-User-*` headers.
+/// The role comes from the database like in `role_check`; with
+/// `JWT_TRUST_CLAIMS` enabled only the claims are known, so the role is
+/// taken from the token and the email is omitted.
+pub async fn check(req: Request) -> Result<impl IntoResponse, HttpError> {
+    let claims = req
+        .extensions()
+        .get::<TokenClaims>()
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::UserNotAuthenticated.to_string()))?;
+    let auth = req.extensions().get::<JWTAuthMiddleware>();
+
+    let mut headers = HeaderMap::new();
+
+    let identity = [
+        ("x-user-id", Some(claims.sub.as_str())),
+        ("x-user-email", auth.as_ref().map(|auth| auth.user.email.as_str())),
+        ("x-user-role", Some(auth.map_or(claims.role.as_str(), |auth| auth.user.role.to_str()))),
+    ];
+
+    for (name, value) in identity {
+        if let Some(value) = value {
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+            headers.insert(name, value);
+        }
     }
 
-    let token = token::create_token(
-        &user.id.to_string(),
-        &app_state.env.jwt_secret.as_bytes(),
-        app_state.env.jwt_maxage
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok((StatusCode::OK, headers))
+}
+
+/// Starts a new session for `user` and returns the same cookie and JSON
+/// response as a password login.
+pub async fn create_login_session(
+    app_state: &AppState,
+    user: &User,
+    headers: &HeaderMap,
+    addr: SocketAddr,
+) -> Result<axum::response::Response, HttpError> {
+    let session = start_session(app_state, user, headers, addr).await?;
+    let refresh_token = issue_refresh_token(app_state, user.id, session.id).await?;
+
+    login_response(app_state, user, session.id, refresh_token)
+}
+
+async fn start_session(
+    app_state: &AppState,
+    user: &User,
+    headers: &HeaderMap,
+    addr: SocketAddr,
+) -> Result<Session, HttpError> {
+    // Service accounts authenticate with client_credentials only.
+    if user.service_account {
+        return Err(HttpError::unauthorized(ErrorMessage::WrongCredentials.to_string()));
+    }
+
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.refresh_token_maxage);
+
+    app_state.db_client
+        .save_session(user.id, user_agent, Some(&addr.ip().to_string()), expires_at)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+async fn issue_refresh_token(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+    family_id: uuid::Uuid,
+) -> Result<String, HttpError> {
+    let refresh_token = token::generate_opaque_token();
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.refresh_token_maxage);
+
+    app_state.db_client
+        .save_refresh_token(user_id, family_id, &token::hash_token(&refresh_token), expires_at)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(refresh_token)
+}
+
+/// Issues a single-use token for `purpose`, returning the raw value to send to the user.
+async fn issue_one_time_token(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+    purpose: TokenPurpose,
+) -> Result<String, HttpError> {
+    let one_time_token = token::generate_opaque_token();
+    let expires_at = Utc::now() + purpose.max_age();
+
+    app_state.db_client
+        .save_one_time_token(user_id, purpose, &token::hash_token(&one_time_token), expires_at)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(one_time_token)
+}
+
+fn login_response(
+    app_state: &AppState,
+    user: &User,
+    session_id: uuid::Uuid,
+    refresh_token: String,
+) -> Result<axum::response::Response, HttpError> {
+    let token = access_token(app_state, user, session_id)?;
+    let headers = session_cookies(app_state, &token, &refresh_token);
+
+    let response = axum::response::Json(UserLoginResponseDto {
+        status: "success".to_string(),
+        token,
+        refresh_token,
+    });
+
+    let mut response = response.into_response();
+    response.headers_mut().extend(headers);
+
+    Ok(response)
+}
+
+fn access_token(
+    app_state: &AppState,
+    user: &User,
+    session_id: uuid::Uuid,
+) -> Result<String, HttpError> {
+    token::create_token(
+        user,
+        &session_id.to_string(),
+        token::DEFAULT_SCOPE,
+        &app_state.key_ring,
+        &app_state.env
     )
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
-    
+    .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+fn session_cookies(
+    app_state: &AppState,
+    token: &str,
+    refresh_token: &str,
+) -> HeaderMap {
     let cookie_duration = time::Duration::minutes(app_state.env.jwt_maxage * 60);
-    let cookie = Cookie::build(("token", token.clone()))
+    let cookie = Cookie::build(("token", token.to_string()))
         .path("/")
         .max_age(cookie_duration)
         .http_only(true)
         .build();
-        
+
+    let refresh_cookie_duration = time::Duration::minutes(app_state.env.refresh_token_maxage);
+    let refresh_cookie = Cookie::build(("refresh_token", refresh_token.to_string()))
+        .path("/api/auth")
+        .max_age(refresh_cookie_duration)
+        .http_only(true)
+        .build();
+
     let mut headers = HeaderMap::new();
+
     headers.append(
         header::SET_COOKIE,
         cookie.to_string().parse().unwrap(),
     );
-    
-    let frontend_url = format!("http://localhost:5173/settings");
+    headers.append(
+        header::SET_COOKIE,
+        refresh_cookie.to_string().parse().unwrap(),
+    );
 
-    let redirect = Redirect::to(&frontend_url);
+    headers
+}
+
+fn logout_response(message: &str) -> axum::response::Response {
+    let cookie = Cookie::build(("token", ""))
+        .path("/")
+        .max_age(time::Duration::ZERO)
+        .http_only(true)
+        .build();
+
+    let refresh_cookie = Cookie::build(("refresh_token", ""))
+        .path("/api/auth")
+        .max_age(time::Duration::ZERO)
+        .http_only(true)
+        .build();
+
+    let mut headers = HeaderMap::new();
 
-    let mut response = redirect.into_response();
+    headers.append(
+        header::SET_COOKIE,
+        cookie.to_string().parse().unwrap(),
+    );
+    headers.append(
+        header::SET_COOKIE,
+        refresh_cookie.to_string().parse().unwrap(),
+    );
+
+    let mut response = Json(Response {
+        status: "success",
+        message: message.to_string(),
+    }).into_response();
     response.headers_mut().extend(headers);
 
+    response
+}
+
+pub async fn verify_email(
+    Query(query_params): Query<VerifyEmailQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let one_time_token = app_state.db_client
+        .consume_one_time_token(&token::hash_token(&query_params.token), TokenPurpose::EmailVerification)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    let result = app_state.db_client
+        .get_user(Some(one_time_token.user_id), None, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    app_state.db_client
+        .verify_user(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if let Err(e) = send_welcome_email(&user.email, &user.name).await {
+        eprintln!("Failed to send welcome email: {}", e);
+    }
+
+    let user = User { verified: true, ..user };
+
+    login_redirect(&app_state, &user, &headers, addr).await
+}
+
+pub async fn request_magic_link(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<MagicLinkRequestDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let result = app_state.db_client
+        .get_user(None, None, Some(&body.email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Respond the same way whether or not the account exists.
+    if let Some(user) = result {
+        let magic_token = issue_one_time_token(&app_state, user.id, TokenPurpose::MagicLink).await?;
+
+        let login_link = format!("http://localhost:8000/api/auth/magic-link/verify?token={}", &magic_token);
+
+        if let Err(e) = send_magic_link_email(&user.email, &user.name, &login_link).await {
+            eprintln!("Failed to send magic link email: {}", e);
+            return Err(HttpError::server_error("Failed to send email".to_string()));
+        }
+    }
+
+    let response = Response {
+        message: "If an account exists for this email, a login link has been sent.".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn verify_magic_link(
+    Query(query_params): Query<MagicLinkQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let magic_link = app_state.db_client
+        .consume_one_time_token(&token::hash_token(&query_params.token), TokenPurpose::MagicLink)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    let result = app_state.db_client
+        .get_user(Some(magic_link.user_id), None, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    login_redirect(&app_state, &user, &headers, addr).await
+}
+
+/// Finishes a browser sign-in that didn't involve the password, such as a
+/// magic link or an external identity provider, by redirecting to the
+/// frontend with a new session.
+pub async fn login_redirect(
+    app_state: &AppState,
+    user: &User,
+    headers: &HeaderMap,
+    addr: SocketAddr,
+) -> Result<axum::response::Response, HttpError> {
+    // These only prove access to the mailbox or external account, so a second factor is still required.
+    if two_factor_enabled(app_state, user.id).await? {
+        let mfa_token = token::create_mfa_token(
+            &user.id.to_string(),
+            &app_state.key_ring,
+            &app_state.env
+        )
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let frontend_url = format!("http://localhost:5173/login/mfa?mfa_token={}", mfa_token);
+
+        return Ok(Redirect::to(&frontend_url).into_response());
+    }
+
+    let session = start_session(app_state, user, headers, addr).await?;
+    let refresh_token = issue_refresh_token(app_state, user.id, session.id).await?;
+    let token = access_token(app_state, user, session.id)?;
+
+    let frontend_url = "http://localhost:5173/settings".to_string();
+
+    let mut response = Redirect::to(&frontend_url).into_response();
+    response.headers_mut().extend(session_cookies(app_state, &token, &refresh_token));
+
     Ok(response)
 }
 
@@ -196,23 +818,15 @@ pub async fn forgot_password(
        .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
     let result = app_state.db_client
-            .get_user(None, None, Some(&body.email), None)
+            .get_user(None, None, Some(&body.email))
             .await
             .map_err(|e| HttpError::server_error(e.to_string()))?;
 
     let user = result.ok_or(HttpError::bad_request("Email not found!".to_string()))?;
 
-    let verification_token = uuid::Uuid::new_v4().to_string();
-    let expires_at = Utc::now() + Duration::minutes(30);
-
-    let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
-
-    app_state.db_client
-        .add_verifed_token(user_id, &verification_token, expires_at)
-        .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let reset_token = issue_one_time_token(&app_state, user.id, TokenPurpose::PasswordReset).await?;
 
-    let reset_link = format!("http://localhost:5173/reset-password?token={}", &verification_token);
+    let reset_link = format!("http://localhost:5173/reset-password?token={}", &reset_token);
 
     let email_sent = send_forgot_password_email(&user.email, &reset_link, &user.name).await;
 
@@ -236,32 +850,42 @@ pub async fn reset_password(
     body.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
+    let token_hash = token::hash_token(&body.token);
+
+    let one_time_token = app_state.db_client
+        .get_one_time_token(&token_hash, TokenPurpose::PasswordReset)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request("Invalid or expired token".to_string()))?;
+
     let result = app_state.db_client
-        .get_user(None, None, None, Some(&body.token))
+        .get_user(Some(one_time_token.user_id), None, None)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
     let user = result.ok_or(HttpError::bad_request("Invalid or expired token".to_string()))?;
 
-    if let Some(expires_at) = user.token_expires_at {
-        if Utc::now() > expires_at {
-            return Err(HttpError::bad_request("Verification token has expired".to_string()))?;
-        }
-    } else {
-        return Err(HttpError::bad_request("Invalid verification token".to_string()))?;
-    }
-    let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
+    app_state.password_policy.check(&body.new_password, &[&user.name, &user.email])?;
 
-    let hash_password = password::hash_password(&body.new_password)
+    let history = app_state.db_client
+        .get_password_history(user.id, app_state.password_policy.history_size)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    app_state.password_policy.check_reuse(&body.new_password, &user.password, &history, &app_state.password_hashing)?;
+
+    let hash_password = app_state.password_hashing.hash_password(&body.new_password)
             .map_err(|e| HttpError::server_error(e.to_string()))?;
 
+    // Redeem the token only once the new password is accepted, and only once.
     app_state.db_client
-        .update_user_password(user_id.clone(), hash_password)
+        .consume_one_time_token(&token_hash, TokenPurpose::PasswordReset)
         .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request("Invalid or expired token".to_string()))?;
 
     app_state.db_client
-        .verifed_token(&body.token)
+        .update_user_password(user.id, hash_password, app_state.password_policy.history_size)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -271,4 +895,458 @@ pub async fn reset_password(
     };
 
     Ok(Json(response))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, Method, StatusCode};
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::provision_user;
+    use crate::{
+        config::Config,
+        database::{IdentityExt, OneTimeTokenExt, TwoFactorExt, UserExt},
+        error::{ErrorMessage, HttpError},
+        models::{TokenPurpose, UserRole},
+        test_support::{self, body_json, json_request, send},
+        utils::token
+    };
+
+    #[sqlx::test]
+    async fn rotates_refresh_tokens(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "refresh@example.com", "password1").await;
+        let login = test_support::login_body(&router, "refresh@example.com", "password1").await;
+        let first = login["refresh_token"].as_str().unwrap();
+
+        let request = json_request(Method::POST, "/api/auth/refresh", None, json!({ "refresh_token": first }));
+        let response = send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body_json(response).await;
+        let second = body["refresh_token"].as_str().unwrap();
+        assert_ne!(second, first);
+        assert!(body["token"].is_string());
+
+        let request = json_request(Method::POST, "/api/auth/refresh", None, json!({ "refresh_token": second }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn reusing_a_refresh_token_revokes_its_family(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "reuse@example.com", "password1").await;
+        let login = test_support::login_body(&router, "reuse@example.com", "password1").await;
+        let first = login["refresh_token"].as_str().unwrap();
+
+        let request = json_request(Method::POST, "/api/auth/refresh", None, json!({ "refresh_token": first }));
+        let second = body_json(send(&router, request).await).await["refresh_token"].as_str().unwrap().to_string();
+
+        let request = json_request(Method::POST, "/api/auth/refresh", None, json!({ "refresh_token": first }));
+        let response = send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body_json(response).await["message"], ErrorMessage::RefreshTokenReused.to_string());
+
+        // The token the legitimate client holds is revoked along with the family.
+        let request = json_request(Method::POST, "/api/auth/refresh", None, json!({ "refresh_token": second }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::UNAUTHORIZED);
+
+        let request = json_request(Method::POST, "/api/auth/refresh", None, json!({ "refresh_token": "unknown" }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn logout_revokes_only_the_current_session(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "sessions@example.com", "password1").await;
+        let laptop = test_support::login(&router, "sessions@example.com", "password1").await;
+        let phone = test_support::login(&router, "sessions@example.com", "password1").await;
+
+        let request = json_request(Method::GET, "/api/users/me/sessions", Some(&laptop), json!({}));
+        let sessions = body_json(send(&router, request).await).await["sessions"].clone();
+        assert_eq!(sessions.as_array().unwrap().len(), 2);
+        assert_eq!(sessions.as_array().unwrap().iter().filter(|session| session["current"] == true).count(), 1);
+
+        let request = json_request(Method::POST, "/api/auth/logout", Some(&laptop), json!({}));
+        assert_eq!(send(&router, request).await.status(), StatusCode::OK);
+
+        let response = send(&router, json_request(Method::GET, "/api/users/me", Some(&laptop), json!({}))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body_json(response).await["message"], ErrorMessage::SessionRevoked.to_string());
+
+        let response = send(&router, json_request(Method::GET, "/api/users/me", Some(&phone), json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn revokes_other_sessions(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "revoke@example.com", "password1").await;
+        let laptop = test_support::login(&router, "revoke@example.com", "password1").await;
+        let phone = test_support::login(&router, "revoke@example.com", "password1").await;
+
+        let request = json_request(Method::GET, "/api/users/me/sessions", Some(&laptop), json!({}));
+        let sessions = body_json(send(&router, request).await).await["sessions"].clone();
+        let phone_session = sessions.as_array().unwrap().iter().find(|session| session["current"] == false).unwrap();
+
+        let uri = format!("/api/users/me/sessions/{}", phone_session["id"].as_str().unwrap());
+        assert_eq!(send(&router, json_request(Method::DELETE, &uri, Some(&laptop), json!({}))).await.status(), StatusCode::OK);
+        assert_eq!(send(&router, json_request(Method::DELETE, &uri, Some(&laptop), json!({}))).await.status(), StatusCode::NOT_FOUND);
+
+        let response = send(&router, json_request(Method::GET, "/api/users/me", Some(&phone), json!({}))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let other = test_support::login(&router, "revoke@example.com", "password1").await;
+        let request = json_request(Method::POST, "/api/auth/logout-all", Some(&laptop), json!({}));
+        assert_eq!(send(&router, request).await.status(), StatusCode::OK);
+
+        for token in [&laptop, &other] {
+            let response = send(&router, json_request(Method::GET, "/api/users/me", Some(token), json!({}))).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[sqlx::test]
+    async fn magic_links_log_in_once(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "magic@example.com", "password1").await;
+
+        let magic_token = token::generate_opaque_token();
+        app_state.db_client
+            .save_one_time_token(user.id, TokenPurpose::MagicLink, &token::hash_token(&magic_token), Utc::now() + Duration::minutes(15))
+            .await
+            .unwrap();
+
+        let uri = format!("/api/auth/magic-link/verify?token={}", magic_token);
+        let response = send(&router, json_request(Method::GET, &uri, None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let cookies = response.headers().get_all(header::SET_COOKIE).iter()
+            .map(|cookie| cookie.to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert!(cookies.iter().any(|cookie| cookie.starts_with("token=")));
+        assert!(cookies.iter().any(|cookie| cookie.starts_with("refresh_token=")));
+
+        let response = send(&router, json_request(Method::GET, &uri, None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn refuses_expired_magic_links(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "expired@example.com", "password1").await;
+
+        let magic_token = token::generate_opaque_token();
+        app_state.db_client
+            .save_one_time_token(user.id, TokenPurpose::MagicLink, &token::hash_token(&magic_token), Utc::now() - Duration::minutes(1))
+            .await
+            .unwrap();
+
+        let uri = format!("/api/auth/magic-link/verify?token={}", magic_token);
+        let response = send(&router, json_request(Method::GET, &uri, None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn verification_links_log_in_through_the_second_factor(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let verify = async |email: &str| {
+            let user = app_state.db_client.save_user("Test User", email, "").await.unwrap();
+            let verification_token = token::generate_opaque_token();
+            app_state.db_client
+                .save_one_time_token(user.id, TokenPurpose::EmailVerification, &token::hash_token(&verification_token), Utc::now() + Duration::hours(1))
+                .await
+                .unwrap();
+
+            (user, format!("/api/auth/verify?token={}", verification_token))
+        };
+
+        let (_, uri) = verify("plain@example.com").await;
+        let response = send(&router, json_request(Method::GET, &uri, None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let cookies = response.headers().get_all(header::SET_COOKIE).iter()
+            .map(|cookie| cookie.to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert!(cookies.iter().any(|cookie| cookie.starts_with("token=")));
+        assert!(cookies.iter().any(|cookie| cookie.starts_with("refresh_token=")));
+
+        let (user, uri) = verify("totp@example.com").await;
+        app_state.db_client.save_totp_secret(user.id, "JBSWY3DPEHPK3PXP").await.unwrap();
+        app_state.db_client.enable_totp(user.id, 0, &[]).await.unwrap();
+
+        let response = send(&router, json_request(Method::GET, &uri, None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(response.headers()[header::LOCATION].to_str().unwrap().contains("/login/mfa?mfa_token="));
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+        let user = app_state.db_client.get_user(Some(user.id), None, None).await.unwrap().unwrap();
+        assert!(user.verified);
+    }
+
+    #[sqlx::test]
+    async fn reset_tokens_change_the_password_once(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "reset@example.com", "password1").await;
+
+        let reset_token = token::generate_opaque_token();
+        app_state.db_client
+            .save_one_time_token(user.id, TokenPurpose::PasswordReset, &token::hash_token(&reset_token), Utc::now() + Duration::minutes(30))
+            .await
+            .unwrap();
+
+        // A reset token does not work as a login link.
+        let uri = format!("/api/auth/magic-link/verify?token={}", reset_token);
+        let response = send(&router, json_request(Method::GET, &uri, None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let reset = json!({
+            "token": reset_token,
+            "new_password": "Lantern-orbit-42",
+            "new_password_confirm": "Lantern-orbit-42",
+        });
+        let response = send(&router, json_request(Method::POST, "/api/auth/reset-password", None, reset.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(&router, json_request(Method::POST, "/api/auth/reset-password", None, reset)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert!(test_support::login_body(&router, "reset@example.com", "Lantern-orbit-42").await["token"].is_string());
+    }
+
+    #[sqlx::test]
+    async fn issuing_a_token_replaces_the_previous_one(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let db = &app_state.db_client;
+
+        let user = test_support::create_user(&app_state, "replace@example.com", "password1").await;
+        let expires_at = Utc::now() + Duration::minutes(30);
+
+        let first = token::hash_token("first");
+        let second = token::hash_token("second");
+        db.save_one_time_token(user.id, TokenPurpose::PasswordReset, &first, expires_at).await.unwrap();
+        db.save_one_time_token(user.id, TokenPurpose::PasswordReset, &second, expires_at).await.unwrap();
+        db.save_one_time_token(user.id, TokenPurpose::MagicLink, &token::hash_token("link"), expires_at).await.unwrap();
+
+        assert!(db.consume_one_time_token(&first, TokenPurpose::PasswordReset).await.unwrap().is_none());
+        assert!(db.consume_one_time_token(&second, TokenPurpose::MagicLink).await.unwrap().is_none());
+        assert!(db.consume_one_time_token(&second, TokenPurpose::PasswordReset).await.unwrap().is_some());
+        assert!(db.consume_one_time_token(&token::hash_token("link"), TokenPurpose::MagicLink).await.unwrap().is_some());
+    }
+
+    #[sqlx::test]
+    async fn rehashes_outdated_passwords_on_login(pool: PgPool) {
+        let config = test_support::config();
+        let outdated = Config { argon2_memory_kib: 1024, argon2_iterations: 1, ..config.clone() };
+        let current = Config { argon2_memory_kib: 2048, argon2_iterations: 1, ..config };
+
+        let old_state = test_support::app_state(outdated, pool.clone());
+        let user = test_support::create_user(&old_state, "rehash@example.com", "violet lantern orbit").await;
+        assert!(user.password.contains("m=1024"));
+
+        let app_state = test_support::app_state(current, pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::login(&router, "rehash@example.com", "violet lantern orbit").await;
+
+        let stored = app_state.db_client.get_user(Some(user.id), None, None).await.unwrap().unwrap();
+        assert!(stored.password.contains("m=2048"));
+        assert!(!app_state.password_hashing.needs_rehash(&stored.password));
+        test_support::login(&router, "rehash@example.com", "violet lantern orbit").await;
+    }
+
+    #[sqlx::test]
+    async fn check_identifies_the_caller_to_the_proxy(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "proxied@example.com", "violet lantern orbit").await;
+        let token = test_support::login(&router, "proxied@example.com", "violet lantern orbit").await;
+
+        let response = send(&router, json_request(Method::GET, "/api/auth/check", Some(&token), json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-user-id"], user.id.to_string().as_str());
+        assert_eq!(response.headers()["x-user-email"], "proxied@example.com");
+        assert_eq!(response.headers()["x-user-role"], "user");
+
+        let response = send(&router, json_request(Method::GET, "/api/auth/check", None, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn check_enforces_the_required_role(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "member@example.com", "violet lantern orbit").await;
+        let token = test_support::login(&router, "member@example.com", "violet lantern orbit").await;
+
+        for (uri, status) in [
+            ("/api/auth/check?role=admin", StatusCode::FORBIDDEN),
+            ("/api/auth/check?role=admin,user", StatusCode::OK),
+            ("/api/auth/check?role=owner", StatusCode::BAD_REQUEST),
+        ] {
+            let response = send(&router, json_request(Method::GET, uri, Some(&token), json!({}))).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn check_refuses_a_header_that_contradicts_or_blanks_the_required_role(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "member@example.com", "violet lantern orbit").await;
+        let token = test_support::login(&router, "member@example.com", "violet lantern orbit").await;
+
+        for (uri, header, status) in [
+            ("/api/auth/check?role=admin", "user", StatusCode::BAD_REQUEST),
+            ("/api/auth/check?role=admin", "", StatusCode::BAD_REQUEST),
+            ("/api/auth/check?role=admin", " admin", StatusCode::FORBIDDEN),
+            ("/api/auth/check", " , ", StatusCode::BAD_REQUEST),
+            ("/api/auth/check?role=", "", StatusCode::BAD_REQUEST),
+        ] {
+            let mut request = json_request(Method::GET, uri, Some(&token), json!({}));
+            request.headers_mut().insert("x-required-role", header.parse().unwrap());
+
+            let response = send(&router, request).await;
+            assert_eq!(response.status(), status, "{uri} with {header:?}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn check_reports_the_current_role_rather_than_the_token_claim(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "check@example.com", "password1").await;
+        let token = test_support::login(&router, "check@example.com", "password1").await;
+
+        let check = async |required_role: &str| {
+            let mut request = json_request(Method::GET, "/api/auth/check", Some(&token), json!({}));
+            request.headers_mut().insert("x-required-role", required_role.parse().unwrap());
+
+            send(&router, request).await
+        };
+
+        let response = check("user").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-user-role"], "user");
+
+        app_state.db_client.update_user_role(user.id, UserRole::Admin).await.unwrap();
+
+        let response = check("admin").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-user-role"], "admin");
+        assert_eq!(response.headers()["x-user-email"], "check@example.com");
+    }
+
+    #[sqlx::test]
+    async fn provisions_directory_users_on_first_login(pool: PgPool) {
+        let directory = std::env::temp_dir().join(format!("directory-{}.json", uuid::Uuid::new_v4()));
+        let entries = json!([{
+            "username": "alice@corp.com",
+            "password": "wonderland",
+            "email": "alice@corp.com",
+            "name": "Alice",
+            "groups": ["cn=admins,ou=groups,dc=corp,dc=com"],
+        }]);
+        std::fs::write(&directory, entries.to_string()).unwrap();
+
+        let config = Config {
+            ldap_backend: "memory".to_string(),
+            ldap_memory_directory: Some(directory.to_string_lossy().into_owned()),
+            ldap_admin_groups: vec!["cn=admins,ou=groups,dc=corp,dc=com".to_string()],
+            ..test_support::config()
+        };
+        let app_state = test_support::app_state(config, pool);
+        let router = test_support::router(app_state.clone());
+        std::fs::remove_file(&directory).unwrap();
+
+        let login = test_support::login_body(&router, "alice@corp.com", "wonderland").await;
+        assert!(login["token"].is_string());
+
+        let user = app_state.db_client.get_user(None, None, Some("alice@corp.com")).await.unwrap().unwrap();
+        assert!(user.verified && user.password.is_empty());
+        assert_eq!(user.role, UserRole::Admin);
+
+        let request = json_request(Method::POST, "/api/auth/login", None, json!({ "email": "alice@corp.com", "password": "looking-glass" }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn provisions_and_links_accounts_through_identities(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+
+        let user = provision_user(&app_state, "ldap", "uid=alice", "alice@corp.com", "Alice", Some(UserRole::Admin)).await.unwrap();
+        assert!(user.verified && user.password.is_empty());
+        assert_eq!(user.role, UserRole::Admin);
+
+        // The provider keeps the role of the account it created in sync.
+        let again = provision_user(&app_state, "ldap", "uid=alice", "alice@corp.com", "Alice", Some(UserRole::User)).await.unwrap();
+        assert_eq!(again.id, user.id);
+        assert_eq!(again.role, UserRole::User);
+
+        // A new subject from the same provider, like a transient NameID,
+        // stays linked to the account.
+        let renamed = provision_user(&app_state, "ldap", "uid=alice2", "alice@corp.com", "Alice", None).await.unwrap();
+        assert_eq!(renamed.id, user.id);
+
+        // An account without a password or identity is linked by email.
+        let invited = app_state.db_client.save_user("Bob", "bob@corp.com", "").await.unwrap();
+        let linked = provision_user(&app_state, "saml", "bob-name-id", "bob@corp.com", "Bob", None).await.unwrap();
+        assert_eq!(linked.id, invited.id);
+
+        let identities = app_state.db_client.get_user_identities(invited.id).await.unwrap();
+        assert_eq!(identities.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn refuses_to_take_over_local_accounts(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let user = test_support::create_user(&app_state, "local@corp.com", "password1").await;
+
+        let refused = |result: Result<_, HttpError>| {
+            let error = result.unwrap_err();
+            error.status == StatusCode::CONFLICT && error.message == ErrorMessage::ExternalAccountExists.to_string()
+        };
+
+        assert!(refused(provision_user(&app_state, "ldap", "uid=local", "local@corp.com", "Local", Some(UserRole::Admin)).await));
+        assert!(refused(provision_user(&app_state, "saml", "local", "local@corp.com", "Local", Some(UserRole::Admin)).await));
+
+        let user = app_state.db_client.get_user(Some(user.id), None, None).await.unwrap().unwrap();
+        assert_eq!(user.role, UserRole::User);
+        assert!(app_state.db_client.get_user_identities(user.id).await.unwrap().is_empty());
+
+        // A password alongside a social identity.
+        let both = test_support::create_user(&app_state, "both@corp.com", "password1").await;
+        app_state.db_client.save_identity(both.id, "github", "1", Some("both@corp.com")).await.unwrap();
+        assert!(refused(provision_user(&app_state, "ldap", "uid=both", "both@corp.com", "Both", None).await));
+
+        // A social sign-in only.
+        let social = app_state.db_client.save_user("Social", "social@corp.com", "").await.unwrap();
+        app_state.db_client.save_identity(social.id, "github", "2", Some("social@corp.com")).await.unwrap();
+        assert!(refused(provision_user(&app_state, "saml", "social", "social@corp.com", "Social", None).await));
+
+        for user in [both, social] {
+            assert_eq!(app_state.db_client.get_user_identities(user.id).await.unwrap().len(), 1);
+        }
+    }
+}
@@ -1,2 +1,10 @@
+pub mod access_token;
 pub mod auth;
+pub mod oauth;
+pub mod passkey;
+pub mod saml;
+pub mod service_account;
+pub mod social;
+pub mod two_factor;
 pub mod user;
+pub mod well_known;
@@ -0,0 +1,1334 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, OriginalUri, Path, Query},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect},
+    Form,
+    Json
+};
+use axum_extra::extract::cookie::CookieJar;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use validator::Validate;
+
+use crate::{
+    database::{OAuthExt, SessionExt, UserExt},
+    dtos::{
+        AuthorizeQueryDto,
+        ConsentDecisionDto,
+        FilterOAuthClientDto,
+        FilterUserDto,
+        IntrospectionResponseDto,
+        OAuthClientListResponseDto,
+        OAuthClientResponseDto,
+        OAuthTokenResponseDto,
+        RegisterOAuthClientDto,
+        Response,
+        TokenLookupDto,
+        TokenRequestDto,
+        UserInfoResponseDto
+    },
+    error::{
+        ErrorMessage,
+        HttpError,
+        OAuthError
+    },
+    middleware::{authenticate_session, inspect_token, JWTAuthMiddleware},
+    models::{OAuthAuthorizationCode, OAuthClient},
+    utils::{oauth, token::{self, ConsentRequest, TokenClaims}},
+    AppState
+};
+
+/// Starts an authorization code flow. Problems with the client or redirect
+/// URI are shown to the user; anything else is sent back to the client.
+pub async fn authorize(
+    Extension(app_state): Extension<Arc<AppState>>,
+    cookie_jar: CookieJar,
+    OriginalUri(original_uri): OriginalUri,
+    Query(query): Query<AuthorizeQueryDto>,
+) -> Result<Redirect, HttpError> {
+    let client_id = query.client_id
+        .as_deref()
+        .ok_or_else(|| HttpError::bad_request("client_id is required".to_string()))?;
+
+    let client = app_state.db_client
+        .get_oauth_client(client_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::bad_request("Unknown OAuth client".to_string()))?;
+
+    let redirect_uri = match query.redirect_uri.as_deref() {
+        Some(redirect_uri) if client.redirect_uris.iter().any(|uri| uri == redirect_uri) => redirect_uri,
+        _ => return Err(HttpError::bad_request("redirect_uri is not registered for this client".to_string())),
+    };
+
+    let state = query.state.as_deref();
+    let error_redirect = |error: &str, description: &str| {
+        Redirect::to(&oauth::redirect_uri_with(redirect_uri, &[
+            ("error", Some(error)),
+            ("error_description", Some(description)),
+            ("state", state),
+        ]))
+    };
+
+    if let Err(e) = query.validate() {
+        return Ok(error_redirect("invalid_request", &e.to_string()));
+    }
+
+    if query.response_type.as_deref() != Some("code") {
+        return Ok(error_redirect("unsupported_response_type", "Only the code response type is supported"));
+    }
+
+    let code_challenge = match query.code_challenge.as_deref() {
+        Some(code_challenge) if oauth::is_valid_code_challenge(code_challenge) => code_challenge,
+        _ => return Ok(error_redirect("invalid_request", "A valid code_challenge is required")),
+    };
+
+    if query.code_challenge_method.as_deref() != Some(oauth::PKCE_METHOD) {
+        return Ok(error_redirect("invalid_request", "code_challenge_method must be S256"));
+    }
+
+    let mut scopes = oauth::parse_scope(query.scope.as_deref());
+    if scopes.is_empty() {
+        scopes = client.scopes.clone();
+    }
+
+    if scopes.iter().any(|scope| !client.scopes.contains(scope)) {
+        return Ok(error_redirect("invalid_scope", "The requested scope is not allowed for this client"));
+    }
+
+    if scopes.iter().any(|scope| scope == oauth::OPENID_SCOPE) && !app_state.key_ring.is_asymmetric() {
+        return Ok(error_redirect("invalid_scope", &ErrorMessage::OpenIdNotConfigured.to_string()));
+    }
+
+    let scope = scopes.join(" ");
+
+    let session = match cookie_jar.get("token") {
+        Some(cookie) => authenticate_session(&app_state, cookie.value()).await.ok(),
+        None => None,
+    };
+
+    let Some(session) = session else {
+        let return_to = format!("{}{}", app_state.env.public_url, original_uri);
+
+        return Ok(Redirect::to(&oauth::redirect_uri_with(
+            &format!("{}/login", app_state.env.frontend_url),
+            &[("redirect_to", Some(&return_to))],
+        )));
+    };
+
+    let nonce = query.nonce.as_deref();
+
+    let consent = app_state.db_client
+        .get_oauth_consent(session.user.id, client.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if consent.is_some_and(|consent| scopes.iter().all(|scope| consent.scopes.contains(scope))) {
+        let code = issue_authorization_code(&app_state, &client, &session, redirect_uri, &scope, code_challenge, nonce).await?;
+
+        return Ok(Redirect::to(&oauth::redirect_uri_with(redirect_uri, &[
+            ("code", Some(&code)),
+            ("state", state),
+        ])));
+    }
+
+    let consent_token = token::create_consent_token(
+        &session.user.id.to_string(),
+        ConsentRequest {
+            client_id: &client.client_id,
+            redirect_uri,
+            scope: &scope,
+            state,
+            code_challenge,
+            nonce,
+        },
+        &app_state.key_ring,
+        &app_state.env,
+    ).map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(Redirect::to(&oauth::redirect_uri_with(
+        &format!("{}/oauth/consent", app_state.env.frontend_url),
+        &[
+            ("consent_token", Some(&consent_token)),
+            ("client_name", Some(&client.name)),
+            ("scope", Some(&scope)),
+        ],
+    )))
+}
+
+/// Receives the user's decision from the consent page and redirects back
+/// to the client with a code or an `access_denied` error.
+pub async fn authorize_decision(
+    Extension(app_state): Extension<Arc<AppState>>,
+    cookie_jar: CookieJar,
+    Form(body): Form<ConsentDecisionDto>,
+) -> Result<Redirect, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let token = cookie_jar
+        .get("token")
+        .map(|cookie| cookie.value().to_string())
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::TokenNotProvided.to_string()))?;
+
+    let session = authenticate_session(&app_state, &token).await?;
+
+    let claims = token::decode_consent_token(&body.consent_token, &app_state.key_ring, &app_state.env)
+        .map_err(|_| HttpError::bad_request(ErrorMessage::InvalidToken.to_string()))?;
+
+    if claims.sub != session.user.id.to_string() {
+        return Err(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()));
+    }
+
+    let client = app_state.db_client
+        .get_oauth_client(&claims.client_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::bad_request("Unknown OAuth client".to_string()))?;
+
+    if !client.redirect_uris.contains(&claims.redirect_uri) {
+        return Err(HttpError::bad_request("redirect_uri is not registered for this client".to_string()));
+    }
+
+    let scopes = oauth::parse_scope(Some(&claims.scope));
+    let state = claims.state.as_deref();
+
+    if body.decision != "approve" || scopes.iter().any(|scope| !client.scopes.contains(scope)) {
+        return Ok(Redirect::to(&oauth::redirect_uri_with(&claims.redirect_uri, &[
+            ("error", Some("access_denied")),
+            ("error_description", Some("The user denied the request")),
+            ("state", state),
+        ])));
+    }
+
+    app_state.db_client
+        .save_oauth_consent(session.user.id, client.id, &scopes)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let code = issue_authorization_code(
+        &app_state,
+        &client,
+        &session,
+        &claims.redirect_uri,
+        &claims.scope,
+        &claims.code_challenge,
+        claims.nonce.as_deref(),
+    ).await?;
+
+    Ok(Redirect::to(&oauth::redirect_uri_with(&claims.redirect_uri, &[
+        ("code", Some(&code)),
+        ("state", state),
+    ])))
+}
+
+async fn issue_authorization_code(
+    app_state: &AppState,
+    client: &OAuthClient,
+    session: &JWTAuthMiddleware,
+    redirect_uri: &str,
+    scope: &str,
+    code_challenge: &str,
+    nonce: Option<&str>,
+) -> Result<String, HttpError> {
+    let code = token::generate_opaque_token();
+
+    // The login session started when the user last entered credentials,
+    // which is what `auth_time` reports.
+    let auth_time = app_state.db_client
+        .get_user_sessions(session.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .into_iter()
+        .find(|login| login.id == session.session_id)
+        .and_then(|login| login.created_at);
+
+    let authorization_code = OAuthAuthorizationCode {
+        id: uuid::Uuid::new_v4(),
+        code_hash: token::hash_token(&code),
+        client_id: client.id,
+        user_id: session.user.id,
+        grant_id: uuid::Uuid::new_v4(),
+        redirect_uri: redirect_uri.to_string(),
+        scope: scope.to_string(),
+        code_challenge: code_challenge.to_string(),
+        code_challenge_method: oauth::PKCE_METHOD.to_string(),
+        nonce: nonce.map(str::to_string),
+        auth_time,
+        expires_at: Utc::now() + Duration::minutes(oauth::AUTHORIZATION_CODE_MAXAGE),
+        used_at: None,
+        created_at: None,
+    };
+
+    app_state.db_client
+        .save_authorization_code(&authorization_code)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(code)
+}
+
+/// Token endpoint for the `authorization_code` and `refresh_token` grants,
+/// and `client_credentials` for service accounts.
+pub async fn token(
+    Extension(app_state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(body): Form<TokenRequestDto>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let client = authenticate_client(&app_state, &headers, body.client_id.as_deref(), body.client_secret.as_deref()).await?;
+
+    let grant_type = body.grant_type
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("grant_type is required"))?;
+
+    // Service account clients act only as themselves, and other clients
+    // only on behalf of users.
+    let service_account_grant = grant_type == "client_credentials";
+    if service_account_grant != client.service_account_id.is_some() {
+        return Err(OAuthError::new("unauthorized_client", "This client may not use this grant type"));
+    }
+
+    match grant_type {
+        "authorization_code" => authorization_code_grant(&app_state, &client, &body).await,
+        "refresh_token" => refresh_token_grant(&app_state, &client, &body).await,
+        "client_credentials" => client_credentials_grant(&app_state, &client, &body).await,
+        _ => Err(OAuthError::new("unsupported_grant_type", "Only authorization_code, refresh_token and client_credentials are supported")),
+    }
+}
+
+/// Identifies the client from HTTP Basic credentials or the form body.
+/// Confidential clients must present their secret; public clients must not.
+async fn authenticate_client(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    body_client_id: Option<&str>,
+    body_client_secret: Option<&str>,
+) -> Result<OAuthClient, OAuthError> {
+    let basic = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .map(|credentials| {
+            let credentials = STANDARD.decode(credentials.trim())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or_else(|| OAuthError::invalid_client("Malformed Basic credentials"))?;
+
+            let (client_id, client_secret) = credentials
+                .split_once(':')
+                .ok_or_else(|| OAuthError::invalid_client("Malformed Basic credentials"))?;
+
+            Ok::<_, OAuthError>((form_decode(client_id), form_decode(client_secret)))
+        })
+        .transpose()?;
+
+    let (client_id, client_secret) = match basic {
+        Some(_) if body_client_secret.is_some() => {
+            return Err(OAuthError::invalid_request("Use only one client authentication method"));
+        },
+        Some((client_id, client_secret)) => (client_id, Some(client_secret)),
+        None => {
+            let client_id = body_client_id
+                .map(str::to_string)
+                .ok_or_else(|| OAuthError::invalid_client("Client authentication is required"))?;
+
+            (client_id, body_client_secret.map(str::to_string))
+        },
+    };
+
+    let client = app_state.db_client
+        .get_oauth_client(&client_id)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .ok_or_else(|| OAuthError::invalid_client("Client authentication failed"))?;
+
+    let authenticated = match (&client.client_secret_hash, client_secret) {
+        (Some(secret_hash), Some(secret)) => token::hash_token(&secret) == *secret_hash,
+        (None, None) => true,
+        _ => false,
+    };
+
+    if !authenticated {
+        return Err(OAuthError::invalid_client("Client authentication failed"));
+    }
+
+    Ok(client)
+}
+
+/// Basic credentials are form-urlencoded before being joined (RFC 6749
+/// section 2.3.1).
+fn form_decode(value: &str) -> String {
+    url::form_urlencoded::parse(value.as_bytes())
+        .next()
+        .map(|(decoded, _)| decoded.into_owned())
+        .unwrap_or_default()
+}
+
+async fn authorization_code_grant(
+    app_state: &AppState,
+    client: &OAuthClient,
+    body: &TokenRequestDto,
+) -> Result<axum::response::Response, OAuthError> {
+    let (Some(code), Some(redirect_uri), Some(code_verifier)) = (&body.code, &body.redirect_uri, &body.code_verifier) else {
+        return Err(OAuthError::invalid_request("code, redirect_uri and code_verifier are required"));
+    };
+
+    let code_hash = token::hash_token(code);
+
+    let consumed = app_state.db_client
+        .consume_authorization_code(&code_hash)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    let Some(authorization_code) = consumed else {
+        // A code presented twice may have been intercepted, so everything
+        // issued from it is revoked.
+        let existing = app_state.db_client
+            .get_authorization_code(&code_hash)
+            .await
+            .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+        if let Some(existing) = existing.filter(|code| code.used_at.is_some()) {
+            app_state.db_client
+                .revoke_oauth_grant(existing.grant_id)
+                .await
+                .map_err(|e| OAuthError::server_error(e.to_string()))?;
+        }
+
+        return Err(OAuthError::invalid_grant("The authorization code is invalid, expired or already used"));
+    };
+
+    if authorization_code.client_id != client.id {
+        return Err(OAuthError::invalid_grant("The authorization code was issued to another client"));
+    }
+
+    if authorization_code.redirect_uri != *redirect_uri {
+        return Err(OAuthError::invalid_grant("redirect_uri does not match the authorization request"));
+    }
+
+    if !oauth::verify_pkce(code_verifier, &authorization_code.code_challenge) {
+        return Err(OAuthError::invalid_grant("code_verifier does not match the code challenge"));
+    }
+
+    let refresh_token = token::generate_opaque_token();
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.refresh_token_maxage);
+
+    app_state.db_client
+        .save_oauth_refresh_token(
+            client.id,
+            authorization_code.user_id,
+            authorization_code.grant_id,
+            &token::hash_token(&refresh_token),
+            &authorization_code.scope,
+            expires_at,
+        )
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    token_response(
+        app_state,
+        client,
+        authorization_code.user_id,
+        authorization_code.grant_id,
+        &authorization_code.scope,
+        refresh_token,
+        Some(&authorization_code),
+    ).await
+}
+
+async fn refresh_token_grant(
+    app_state: &AppState,
+    client: &OAuthClient,
+    body: &TokenRequestDto,
+) -> Result<axum::response::Response, OAuthError> {
+    let refresh_token = body.refresh_token
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("refresh_token is required"))?;
+
+    let current = app_state.db_client
+        .get_oauth_refresh_token(&token::hash_token(refresh_token))
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .filter(|current| current.client_id == client.id)
+        .ok_or_else(|| OAuthError::invalid_grant("The refresh token is invalid"))?;
+
+    if current.revoked_at.is_some() {
+        app_state.db_client
+            .revoke_oauth_grant(current.grant_id)
+            .await
+            .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+        return Err(OAuthError::invalid_grant(ErrorMessage::RefreshTokenReused.to_string()));
+    }
+
+    if current.expires_at <= Utc::now() {
+        return Err(OAuthError::invalid_grant("The refresh token has expired"));
+    }
+
+    // A refresh may narrow the grant's scope but never widen it.
+    let granted = oauth::parse_scope(Some(&current.scope));
+    let requested = oauth::parse_scope(body.scope.as_deref());
+
+    if requested.iter().any(|scope| !granted.contains(scope)) {
+        return Err(OAuthError::invalid_scope("The requested scope exceeds the original grant"));
+    }
+
+    let scope = if requested.is_empty() { current.scope.clone() } else { requested.join(" ") };
+
+    let new_refresh_token = token::generate_opaque_token();
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.refresh_token_maxage);
+
+    let rotated = app_state.db_client
+        .rotate_oauth_refresh_token(current.id, &token::hash_token(&new_refresh_token), &scope, expires_at)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    if rotated.is_none() {
+        app_state.db_client
+            .revoke_oauth_grant(current.grant_id)
+            .await
+            .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+        return Err(OAuthError::invalid_grant(ErrorMessage::RefreshTokenReused.to_string()));
+    }
+
+    token_response(app_state, client, current.user_id, current.grant_id, &scope, new_refresh_token, None).await
+}
+
+async fn client_credentials_grant(
+    app_state: &AppState,
+    client: &OAuthClient,
+    body: &TokenRequestDto,
+) -> Result<axum::response::Response, OAuthError> {
+    let service_account_id = client.service_account_id
+        .ok_or_else(|| OAuthError::new("unauthorized_client", "This client is not a service account"))?;
+
+    let requested = oauth::parse_scope(body.scope.as_deref());
+
+    if requested.iter().any(|scope| !client.scopes.contains(scope)) {
+        return Err(OAuthError::invalid_scope("The requested scope is not allowed for this service account"));
+    }
+
+    let scope = if requested.is_empty() { client.scopes.join(" ") } else { requested.join(" ") };
+
+    let user = app_state.db_client
+        .get_user(Some(service_account_id), None, None)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .ok_or_else(|| OAuthError::invalid_client(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    let access_token = token::create_service_account_token(
+        &user,
+        &client.client_id,
+        &scope,
+        &app_state.key_ring,
+        &app_state.env,
+    ).map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    let response = OAuthTokenResponseDto {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: app_state.env.jwt_maxage * 60,
+        refresh_token: None,
+        scope,
+        id_token: None,
+    };
+
+    let mut response = Json(response).into_response();
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response.headers_mut().insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+
+    Ok(response)
+}
+
+async fn token_response(
+    app_state: &AppState,
+    client: &OAuthClient,
+    user_id: uuid::Uuid,
+    grant_id: uuid::Uuid,
+    scope: &str,
+    refresh_token: String,
+    authorization_code: Option<&OAuthAuthorizationCode>,
+) -> Result<axum::response::Response, OAuthError> {
+    let user = app_state.db_client
+        .get_user(Some(user_id), None, None)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .ok_or_else(|| OAuthError::invalid_grant(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    let access_token = token::create_oauth_token(
+        &user,
+        &grant_id.to_string(),
+        &client.client_id,
+        scope,
+        &app_state.key_ring,
+        &app_state.env,
+    ).map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    // ID tokens are only issued from the code exchange, where the nonce
+    // and authentication time of the sign-in are known.
+    let id_token = match authorization_code {
+        Some(code) if oauth::parse_scope(Some(scope)).iter().any(|scope| scope == oauth::OPENID_SCOPE) => {
+            let id_token = token::create_id_token(
+                &user,
+                &client.client_id,
+                scope,
+                code.nonce.as_deref(),
+                code.auth_time,
+                &app_state.key_ring,
+                &app_state.env,
+            ).map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+            Some(id_token)
+        },
+        _ => None,
+    };
+
+    let response = OAuthTokenResponseDto {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: app_state.env.jwt_maxage * 60,
+        refresh_token: Some(refresh_token),
+        scope: scope.to_string(),
+        id_token,
+    };
+
+    let mut response = Json(response).into_response();
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response.headers_mut().insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+
+    Ok(response)
+}
+
+/// RFC 7662 token introspection for resource servers such as the API
+/// gateway. Any confidential client may introspect access tokens; refresh
+/// tokens are only reported to the client they were issued to.
+pub async fn introspect(
+    Extension(app_state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(body): Form<TokenLookupDto>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let client = authenticate_client(&app_state, &headers, body.client_id.as_deref(), body.client_secret.as_deref()).await?;
+
+    if !client.is_confidential() {
+        return Err(OAuthError::invalid_client("Public clients cannot introspect tokens"));
+    }
+
+    let token = body.token
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("token is required"))?;
+
+    let response = introspect_token(&app_state, &client, token).await?;
+
+    let mut response = Json(response).into_response();
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok(response)
+}
+
+async fn introspect_token(
+    app_state: &AppState,
+    client: &OAuthClient,
+    token: &str,
+) -> Result<IntrospectionResponseDto, OAuthError> {
+    // Login, service account and personal access tokens.
+    match inspect_token(app_state, token).await {
+        Ok(auth) => {
+            let token_type = if auth.access_token_id.is_some() { "personal_access_token" } else { "Bearer" };
+
+            return Ok(IntrospectionResponseDto {
+                iat: Some(auth.claims.iat),
+                ..IntrospectionResponseDto::active(
+                    &auth.user,
+                    token_type,
+                    &auth.claims.scope,
+                    auth.claims.client_id.as_deref(),
+                    auth.claims.exp,
+                )
+            });
+        },
+        Err(e) if e.status == StatusCode::INTERNAL_SERVER_ERROR => {
+            return Err(OAuthError::server_error(e.message));
+        },
+        Err(_) => {},
+    }
+
+    // Access tokens issued to OAuth clients.
+    match verify_oauth_token(app_state, token).await {
+        Ok(claims) => {
+            let user = uuid::Uuid::parse_str(&claims.sub).ok();
+            let user = match user {
+                Some(user_id) => app_state.db_client
+                    .get_user(Some(user_id), None, None)
+                    .await
+                    .map_err(|e| OAuthError::server_error(e.to_string()))?,
+                None => None,
+            };
+
+            return Ok(match user {
+                Some(user) => IntrospectionResponseDto {
+                    iat: Some(claims.iat),
+                    ..IntrospectionResponseDto::active(&user, "Bearer", &claims.scope, Some(&claims.aud), claims.exp)
+                },
+                None => IntrospectionResponseDto::inactive(),
+            });
+        },
+        Err(e) if e.status == StatusCode::INTERNAL_SERVER_ERROR => return Err(e),
+        Err(_) => {},
+    }
+
+    // Refresh tokens, only for the client holding them.
+    let refresh_token = app_state.db_client
+        .get_oauth_refresh_token(&token::hash_token(token))
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .filter(|refresh_token| {
+            refresh_token.client_id == client.id
+                && refresh_token.revoked_at.is_none()
+                && refresh_token.expires_at > Utc::now()
+        });
+
+    let Some(refresh_token) = refresh_token else {
+        return Ok(IntrospectionResponseDto::inactive());
+    };
+
+    let user = app_state.db_client
+        .get_user(Some(refresh_token.user_id), None, None)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    Ok(match user {
+        Some(user) => IntrospectionResponseDto {
+            iat: refresh_token.created_at.map(|created_at| created_at.timestamp()),
+            ..IntrospectionResponseDto::active(
+                &user,
+                "refresh_token",
+                &refresh_token.scope,
+                Some(&client.client_id),
+                refresh_token.expires_at.timestamp(),
+            )
+        },
+        None => IntrospectionResponseDto::inactive(),
+    })
+}
+
+/// RFC 7009 token revocation. Clients can revoke the refresh tokens and
+/// access tokens issued to them; revoking a refresh token ends its whole
+/// grant. Unknown tokens are not an error.
+pub async fn revoke(
+    Extension(app_state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(body): Form<TokenLookupDto>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let client = authenticate_client(&app_state, &headers, body.client_id.as_deref(), body.client_secret.as_deref()).await?;
+
+    let token = body.token
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("token is required"))?;
+
+    let refresh_token = app_state.db_client
+        .get_oauth_refresh_token(&token::hash_token(token))
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .filter(|refresh_token| refresh_token.client_id == client.id);
+
+    if let Some(refresh_token) = refresh_token {
+        app_state.db_client
+            .revoke_oauth_grant(refresh_token.grant_id)
+            .await
+            .map_err(|e| OAuthError::server_error(e.to_string()))?;
+    } else if let Ok(claims) = token::decode_oauth_token(token, &app_state.key_ring, &app_state.env) {
+        let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+
+        if claims.client_id.as_deref() == Some(client.client_id.as_str()) {
+            app_state.revocation_store
+                .revoke(&claims.jti, expires_at)
+                .await
+                .map_err(|e| OAuthError::server_error(e.to_string()))?;
+        }
+    }
+
+    Ok(([(header::CACHE_CONTROL, "no-store")], StatusCode::OK))
+}
+
+/// OpenID Connect UserInfo endpoint, authenticated with an access token
+/// from `/oauth/token` that was granted the `openid` scope.
+pub async fn userinfo(
+    Extension(app_state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, OAuthError> {
+    let access_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| OAuthError::invalid_token(ErrorMessage::TokenNotProvided.to_string()))?;
+
+    if !app_state.key_ring.is_asymmetric() {
+        return Err(OAuthError {
+            status: StatusCode::NOT_FOUND,
+            ..OAuthError::invalid_request(ErrorMessage::OpenIdNotConfigured.to_string())
+        });
+    }
+
+    let claims = verify_oauth_token(&app_state, access_token).await?;
+    let scopes = oauth::parse_scope(Some(&claims.scope));
+
+    if !scopes.iter().any(|scope| scope == oauth::OPENID_SCOPE) {
+        return Err(OAuthError::insufficient_scope("The openid scope is required"));
+    }
+
+    let user_id = uuid::Uuid::parse_str(&claims.sub)
+        .map_err(|_| OAuthError::invalid_token(ErrorMessage::InvalidToken.to_string()))?;
+
+    let user = app_state.db_client
+        .get_user(Some(user_id), None, None)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?
+        .ok_or_else(|| OAuthError::invalid_token(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    let response = UserInfoResponseDto::from_user(&FilterUserDto::filter_user(&user), &scopes);
+
+    let mut response = Json(response).into_response();
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok(response)
+}
+
+/// Accepts an access token only if it was issued to a registered client
+/// and neither it nor its grant has been revoked.
+async fn verify_oauth_token(
+    app_state: &AppState,
+    access_token: &str,
+) -> Result<TokenClaims, OAuthError> {
+    let claims = token::decode_oauth_token(access_token, &app_state.key_ring, &app_state.env)
+        .map_err(|_| OAuthError::invalid_token(ErrorMessage::InvalidToken.to_string()))?;
+
+    let revoked = app_state.revocation_store
+        .is_revoked(&claims.jti)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    if revoked {
+        return Err(OAuthError::invalid_token(ErrorMessage::TokenRevoked.to_string()));
+    }
+
+    let client = app_state.db_client
+        .get_oauth_client(&claims.aud)
+        .await
+        .map_err(|e| OAuthError::server_error(e.to_string()))?;
+
+    let grant_id = uuid::Uuid::parse_str(&claims.sid)
+        .map_err(|_| OAuthError::invalid_token(ErrorMessage::InvalidToken.to_string()))?;
+
+    let active = match client {
+        Some(_) => app_state.db_client
+            .is_oauth_grant_active(grant_id)
+            .await
+            .map_err(|e| OAuthError::server_error(e.to_string()))?,
+        None => false,
+    };
+
+    if !active {
+        return Err(OAuthError::invalid_token(ErrorMessage::InvalidToken.to_string()));
+    }
+
+    Ok(claims)
+}
+
+pub async fn register_client(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<RegisterOAuthClientDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let invalid_redirect_uris: Vec<String> = body.redirect_uris
+        .iter()
+        .filter_map(|redirect_uri| oauth::validate_redirect_uri(redirect_uri).err())
+        .collect();
+
+    if !invalid_redirect_uris.is_empty() {
+        return Err(HttpError::bad_request("Invalid redirect URIs".to_string())
+            .with_errors(invalid_redirect_uris));
+    }
+
+    let mut scopes = body.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    if let Some(scope) = scopes.iter().find(|scope| !oauth::SUPPORTED_SCOPES.contains(&scope.as_str())) {
+        return Err(HttpError::bad_request(ErrorMessage::UnknownScope(scope.to_string()).to_string()));
+    }
+
+    if scopes.is_empty() {
+        scopes = oauth::SUPPORTED_SCOPES.iter().map(|scope| scope.to_string()).collect();
+    }
+
+    let client_secret = body.confidential.then(token::generate_opaque_token);
+    let client_secret_hash = client_secret.as_deref().map(token::hash_token);
+
+    let client = app_state.db_client
+        .save_oauth_client(
+            &oauth::generate_client_id(),
+            client_secret_hash.as_deref(),
+            &body.name,
+            &body.redirect_uris,
+            &scopes,
+            user.user.id,
+        )
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = OAuthClientResponseDto {
+        status: "success".to_string(),
+        client: FilterOAuthClientDto::filter_client(&client),
+        client_secret,
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn get_clients(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let clients = app_state.db_client
+        .get_oauth_clients()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = OAuthClientListResponseDto {
+        status: "success".to_string(),
+        clients: FilterOAuthClientDto::filter_clients(&clients),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn delete_client(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_oauth_client(id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("OAuth client not found".to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "OAuth client deleted successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+        Router
+    };
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+    use sqlx::PgPool;
+    use url::Url;
+
+    use super::*;
+    use crate::{config::Config, test_support};
+
+    const REDIRECT_URI: &str = "https://app.example.com/callback";
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    struct Fixture {
+        app_state: Arc<AppState>,
+        router: Router,
+        session: String,
+        client_id: String,
+    }
+
+    /// A logged-in user who has already consented to a public client.
+    async fn fixture(pool: PgPool) -> Fixture {
+        fixture_with(test_support::es256_app_state(test_support::config(), pool)).await
+    }
+
+    async fn fixture_with(app_state: Arc<AppState>) -> Fixture {
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "oauth@example.com", "violet lantern orbit").await;
+        let session = test_support::login(&router, "oauth@example.com", "violet lantern orbit").await;
+        let scopes: Vec<String> = oauth::SUPPORTED_SCOPES.iter().map(|scope| scope.to_string()).collect();
+
+        let client = app_state.db_client
+            .save_oauth_client(&oauth::generate_client_id(), None, "Test App", &[REDIRECT_URI.to_string()], &scopes, user.id)
+            .await
+            .unwrap();
+
+        app_state.db_client.save_oauth_consent(user.id, client.id, &scopes).await.unwrap();
+
+        Fixture { app_state, router, session, client_id: client.client_id }
+    }
+
+    async fn authorize(fixture: &Fixture, redirect_uri: &str) -> axum::response::Response {
+        authorize_with(fixture, redirect_uri, "profile", None).await
+    }
+
+    async fn authorize_with(
+        fixture: &Fixture,
+        redirect_uri: &str,
+        scope: &str,
+        nonce: Option<&str>,
+    ) -> axum::response::Response {
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(VERIFIER.as_bytes()));
+        let uri = oauth::redirect_uri_with("http://localhost/api/oauth/authorize", &[
+            ("response_type", Some("code")),
+            ("client_id", Some(&fixture.client_id)),
+            ("redirect_uri", Some(redirect_uri)),
+            ("scope", Some(scope)),
+            ("code_challenge", Some(&challenge)),
+            ("code_challenge_method", Some("S256")),
+            ("nonce", nonce),
+        ]);
+        let uri = Url::parse(&uri).unwrap();
+
+        let request = Request::builder()
+            .uri(format!("{}?{}", uri.path(), uri.query().unwrap()))
+            .header(header::COOKIE, format!("token={}", fixture.session))
+            .body(Body::empty())
+            .unwrap();
+
+        test_support::send(&fixture.router, request).await
+    }
+
+    async fn authorization_code(fixture: &Fixture) -> String {
+        authorization_code_with(fixture, "profile", None).await
+    }
+
+    async fn authorization_code_with(fixture: &Fixture, scope: &str, nonce: Option<&str>) -> String {
+        let response = authorize_with(fixture, REDIRECT_URI, scope, nonce).await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+
+        Url::parse(location).unwrap()
+            .query_pairs()
+            .find(|(name, _)| name == "code")
+            .map(|(_, code)| code.into_owned())
+            .unwrap()
+    }
+
+    async fn token_request(fixture: &Fixture, params: &[(&str, &str)]) -> (StatusCode, Value) {
+        let params = [&[("client_id", fixture.client_id.as_str())], params].concat();
+
+        post_form(fixture, "/api/oauth/token", &params).await
+    }
+
+    async fn post_form(fixture: &Fixture, uri: &str, params: &[(&str, &str)]) -> (StatusCode, Value) {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .finish();
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = test_support::send(&fixture.router, request).await;
+        let status = response.status();
+
+        (status, test_support::body_json(response).await)
+    }
+
+    async fn exchange(fixture: &Fixture, code: &str, code_verifier: &str) -> (StatusCode, Value) {
+        token_request(fixture, &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", code_verifier),
+        ]).await
+    }
+
+    async fn refresh(fixture: &Fixture, refresh_token: &str) -> (StatusCode, Value) {
+        token_request(fixture, &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ]).await
+    }
+
+    #[sqlx::test]
+    async fn sends_users_to_the_configured_login_and_consent_pages(pool: PgPool) {
+        let config = Config {
+            public_url: "https://auth.example.com".to_string(),
+            frontend_url: "https://accounts.example.com".to_string(),
+            ..test_support::config()
+        };
+        let fixture = fixture_with(test_support::es256_app_state(config, pool)).await;
+
+        let signed_out = Fixture { session: String::new(), ..fixture };
+        let response = authorize(&signed_out, REDIRECT_URI).await;
+        let location = Url::parse(response.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+        assert_eq!(&location[..url::Position::AfterPath], "https://accounts.example.com/login");
+
+        let (_, return_to) = location.query_pairs().find(|(name, _)| name == "redirect_to").unwrap();
+        assert!(return_to.starts_with("https://auth.example.com/api/oauth/authorize?"));
+
+        test_support::create_user(&signed_out.app_state, "new@example.com", "violet lantern orbit").await;
+        let session = test_support::login(&signed_out.router, "new@example.com", "violet lantern orbit").await;
+
+        let new_user = Fixture { session, ..signed_out };
+        let response = authorize(&new_user, REDIRECT_URI).await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with("https://accounts.example.com/oauth/consent?consent_token="));
+    }
+
+    #[sqlx::test]
+    async fn refuses_redirect_uris_that_are_not_an_exact_match(pool: PgPool) {
+        let fixture = fixture(pool).await;
+
+        for redirect_uri in [
+            "https://app.example.com/callback/",
+            "https://app.example.com/callback?next=/admin",
+            "https://app.example.com.evil.test/callback",
+        ] {
+            assert_eq!(authorize(&fixture, redirect_uri).await.status(), StatusCode::BAD_REQUEST);
+        }
+
+        assert_eq!(authorize(&fixture, REDIRECT_URI).await.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test]
+    async fn refuses_a_code_verifier_that_does_not_match_the_challenge(pool: PgPool) {
+        let fixture = fixture(pool).await;
+        let code = authorization_code(&fixture).await;
+
+        let (status, body) = exchange(&fixture, &code, &"x".repeat(43)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+    }
+
+    #[sqlx::test]
+    async fn reusing_a_code_revokes_the_grant(pool: PgPool) {
+        let fixture = fixture(pool).await;
+        let code = authorization_code(&fixture).await;
+
+        let (status, body) = exchange(&fixture, &code, VERIFIER).await;
+        assert_eq!(status, StatusCode::OK);
+        let refresh_token = body["refresh_token"].as_str().unwrap();
+
+        let (status, body) = exchange(&fixture, &code, VERIFIER).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        let (status, _) = refresh(&fixture, refresh_token).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn rotates_refresh_tokens(pool: PgPool) {
+        let fixture = fixture(pool).await;
+        let code = authorization_code(&fixture).await;
+
+        let (_, body) = exchange(&fixture, &code, VERIFIER).await;
+        let first = body["refresh_token"].as_str().unwrap().to_string();
+
+        let (status, body) = refresh(&fixture, &first).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["scope"], json!("profile"));
+        let second = body["refresh_token"].as_str().unwrap().to_string();
+        assert_ne!(first, second);
+
+        // Presenting the rotated token again looks like theft, so the whole
+        // grant goes.
+        let (status, body) = refresh(&fixture, &first).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        let (status, _) = refresh(&fixture, &second).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn issues_id_tokens_for_openid_sign_ins(pool: PgPool) {
+        let fixture = fixture(pool).await;
+        let code = authorization_code_with(&fixture, "openid email", Some("n-0S6_WzA2Mj")).await;
+
+        let (status, body) = exchange(&fixture, &code, VERIFIER).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let payload = body["id_token"].as_str().unwrap().split('.').nth(1).unwrap();
+        let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        assert_eq!(claims["aud"], json!(fixture.client_id));
+        assert_eq!(claims["nonce"], "n-0S6_WzA2Mj");
+        assert_eq!(claims["email"], "oauth@example.com");
+        assert!(claims["auth_time"].is_i64());
+        assert!(claims.get("name").is_none());
+
+        // Refreshing keeps the access token going but issues no new ID token.
+        let (_, body) = refresh(&fixture, body["refresh_token"].as_str().unwrap()).await;
+        assert!(body.get("id_token").is_none());
+    }
+
+    #[sqlx::test]
+    async fn userinfo_returns_the_claims_of_the_granted_scopes(pool: PgPool) {
+        let fixture = fixture(pool).await;
+
+        for (scope, status) in [("openid email", StatusCode::OK), ("profile", StatusCode::FORBIDDEN)] {
+            let code = authorization_code_with(&fixture, scope, None).await;
+            let (_, body) = exchange(&fixture, &code, VERIFIER).await;
+            let access_token = body["access_token"].as_str().unwrap();
+
+            let request = test_support::json_request(Method::GET, "/api/oauth/userinfo", Some(access_token), json!({}));
+            let response = test_support::send(&fixture.router, request).await;
+            assert_eq!(response.status(), status);
+
+            if status == StatusCode::OK {
+                let body = test_support::body_json(response).await;
+                assert_eq!(body["email"], "oauth@example.com");
+                assert_eq!(body["email_verified"], true);
+                assert!(body.get("name").is_none());
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn refuses_openid_connect_with_an_hs256_key(pool: PgPool) {
+        let fixture = fixture_with(test_support::app_state(test_support::config(), pool)).await;
+
+        let response = authorize_with(&fixture, REDIRECT_URI, "openid email", None).await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with(REDIRECT_URI));
+        assert!(location.contains("error=invalid_scope"));
+
+        let request = test_support::json_request(Method::GET, "/.well-known/openid-configuration", None, json!({}));
+        assert_eq!(test_support::send(&fixture.router, request).await.status(), StatusCode::NOT_FOUND);
+
+        let code = authorization_code(&fixture).await;
+        let (_, body) = exchange(&fixture, &code, VERIFIER).await;
+
+        let request = test_support::json_request(Method::GET, "/api/oauth/userinfo", body["access_token"].as_str(), json!({}));
+        assert_eq!(test_support::send(&fixture.router, request).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn refuses_nonces_longer_than_255_characters(pool: PgPool) {
+        let fixture = fixture(pool).await;
+
+        let response = authorize_with(&fixture, REDIRECT_URI, "openid", Some(&"n".repeat(256))).await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.contains("error=invalid_request"));
+
+        let response = authorize_with(&fixture, REDIRECT_URI, "openid", Some(&"n".repeat(255))).await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.contains("code="));
+    }
+
+    #[sqlx::test]
+    async fn publishes_discovery_endpoints_under_the_public_url(pool: PgPool) {
+        let config = Config {
+            public_url: "https://auth.example.com".to_string(),
+            jwt_issuer: "https://auth.example.com".to_string(),
+            ..test_support::config()
+        };
+        let fixture = fixture_with(test_support::es256_app_state(config, pool)).await;
+
+        let request = test_support::json_request(Method::GET, "/.well-known/openid-configuration", None, json!({}));
+        let body = test_support::body_json(test_support::send(&fixture.router, request).await).await;
+        assert_eq!(body["issuer"], "https://auth.example.com");
+        assert_eq!(body["authorization_endpoint"], "https://auth.example.com/api/oauth/authorize");
+        assert_eq!(body["jwks_uri"], "https://auth.example.com/.well-known/jwks.json");
+        assert_eq!(body["id_token_signing_alg_values_supported"], json!(["ES256"]));
+    }
+
+    /// Registers a confidential client, standing in for a resource server.
+    async fn resource_server(fixture: &Fixture) -> (String, String) {
+        let secret = token::generate_opaque_token();
+        let owner = fixture.app_state.db_client
+            .get_user(None, None, Some("oauth@example.com"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let client = fixture.app_state.db_client
+            .save_oauth_client(
+                &oauth::generate_client_id(),
+                Some(&token::hash_token(&secret)),
+                "Gateway",
+                &[REDIRECT_URI.to_string()],
+                &["profile".to_string()],
+                owner.id,
+            )
+            .await
+            .unwrap();
+
+        (client.client_id, secret)
+    }
+
+    async fn introspect(fixture: &Fixture, client: &(String, String), token: &str) -> (StatusCode, Value) {
+        post_form(fixture, "/api/oauth/introspect", &[
+            ("client_id", &client.0),
+            ("client_secret", &client.1),
+            ("token", token),
+        ]).await
+    }
+
+    #[sqlx::test]
+    async fn introspects_tokens_for_confidential_clients(pool: PgPool) {
+        let fixture = fixture(pool).await;
+        let gateway = resource_server(&fixture).await;
+        let code = authorization_code(&fixture).await;
+        let (_, body) = exchange(&fixture, &code, VERIFIER).await;
+        let access_token = body["access_token"].as_str().unwrap();
+
+        let (status, body) = introspect(&fixture, &gateway, access_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["active"], true);
+        assert_eq!(body["client_id"], json!(fixture.client_id));
+        assert_eq!(body["scope"], "profile");
+
+        let (_, body) = introspect(&fixture, &gateway, "not-a-token").await;
+        assert_eq!(body, json!({ "active": false }));
+
+        let (status, _) = post_form(&fixture, "/api/oauth/introspect", &[
+            ("client_id", &fixture.client_id),
+            ("token", access_token),
+        ]).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn revoke_token(fixture: &Fixture, token: &str) -> (StatusCode, Value) {
+        post_form(fixture, "/api/oauth/revoke", &[
+            ("client_id", &fixture.client_id),
+            ("token", token),
+        ]).await
+    }
+
+    #[sqlx::test]
+    async fn revokes_access_tokens_and_grants(pool: PgPool) {
+        let fixture = fixture(pool).await;
+        let gateway = resource_server(&fixture).await;
+        let code = authorization_code_with(&fixture, "openid profile", None).await;
+        let (_, body) = exchange(&fixture, &code, VERIFIER).await;
+        let access_token = body["access_token"].as_str().unwrap();
+        let refresh_token = body["refresh_token"].as_str().unwrap();
+
+        assert_eq!(revoke_token(&fixture, access_token).await.0, StatusCode::OK);
+        assert_eq!(introspect(&fixture, &gateway, access_token).await.1["active"], false);
+
+        let request = test_support::json_request(Method::GET, "/api/oauth/userinfo", Some(access_token), json!({}));
+        assert_eq!(test_support::send(&fixture.router, request).await.status(), StatusCode::UNAUTHORIZED);
+
+        assert_eq!(introspect(&fixture, &gateway, refresh_token).await.1["active"], false);
+        assert_eq!(revoke_token(&fixture, refresh_token).await.0, StatusCode::OK);
+        assert_eq!(refresh(&fixture, refresh_token).await.0, StatusCode::BAD_REQUEST);
+
+        // Unknown tokens are not an error.
+        assert_eq!(revoke_token(&fixture, "not-a-token").await.0, StatusCode::OK);
+    }
+}
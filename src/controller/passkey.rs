@@ -0,0 +1,388 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json
+};
+use chrono::{Duration, Utc};
+use validator::Validate;
+
+use crate::{
+    controller::auth::create_login_session,
+    database::{UserExt, WebauthnExt},
+    dtos::{
+        AuthenticatorSelectionDto,
+        FilterPasskeyDto,
+        PasskeyListResponseDto,
+        PasskeyLoginDto,
+        PasskeyLoginOptionsResponseDto,
+        PasskeyRegisterDto,
+        PasskeyRegistrationOptionsResponseDto,
+        PasskeyResponseDto,
+        PublicKeyCredentialCreationOptionsDto,
+        PublicKeyCredentialDescriptorDto,
+        PublicKeyCredentialParamDto,
+        PublicKeyCredentialRequestOptionsDto,
+        PublicKeyUserDto,
+        RelyingPartyDto,
+        Response
+    },
+    error::{
+        ErrorMessage,
+        HttpError
+    },
+    middleware::JWTAuthMiddleware,
+    utils::webauthn::{self, RelyingParty},
+    AppState
+};
+
+const REGISTRATION_CEREMONY: &str = "registration";
+const AUTHENTICATION_CEREMONY: &str = "authentication";
+const CEREMONY_TIMEOUT_SECONDS: i64 = 300;
+
+pub async fn passkey_register_options(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = &user.user;
+
+    let credentials = app_state.db_client
+        .get_user_webauthn_credentials(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let challenge = webauthn::generate_challenge();
+    let expires_at = Utc::now() + Duration::seconds(CEREMONY_TIMEOUT_SECONDS);
+
+    let webauthn_challenge = app_state.db_client
+        .save_webauthn_challenge(Some(user.id), &challenge, REGISTRATION_CEREMONY, expires_at)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let public_key = PublicKeyCredentialCreationOptionsDto {
+        challenge,
+        rp: RelyingPartyDto {
+            id: app_state.env.webauthn_rp_id.to_owned(),
+            name: app_state.env.webauthn_rp_name.to_owned(),
+        },
+        user: PublicKeyUserDto {
+            id: webauthn::encode(user.id.as_bytes()),
+            name: user.email.to_owned(),
+            display_name: user.name.to_owned(),
+        },
+        pub_key_cred_params: webauthn::SUPPORTED_ALGORITHMS
+            .iter()
+            .map(|alg| PublicKeyCredentialParamDto {
+                credential_type: "public-key".to_string(),
+                alg: *alg,
+            })
+            .collect(),
+        timeout: CEREMONY_TIMEOUT_SECONDS as u64 * 1000,
+        exclude_credentials: credentials
+            .iter()
+            .map(|credential| PublicKeyCredentialDescriptorDto {
+                credential_type: "public-key".to_string(),
+                id: credential.credential_id.to_owned(),
+            })
+            .collect(),
+        authenticator_selection: AuthenticatorSelectionDto {
+            resident_key: "required".to_string(),
+            user_verification: "required".to_string(),
+        },
+        attestation: "none".to_string(),
+    };
+
+    let response = PasskeyRegistrationOptionsResponseDto {
+        status: "success".to_string(),
+        challenge_id: webauthn_challenge.id.to_string(),
+        public_key,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn passkey_register(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<PasskeyRegisterDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user = &user.user;
+
+    let result = app_state.db_client
+        .take_webauthn_challenge(body.challenge_id, REGISTRATION_CEREMONY)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let webauthn_challenge = match result {
+        Some(webauthn_challenge) if webauthn_challenge.user_id == Some(user.id) => webauthn_challenge,
+        _ => return Err(HttpError::bad_request(ErrorMessage::InvalidPasskey.to_string())),
+    };
+
+    let rp = RelyingParty {
+        id: &app_state.env.webauthn_rp_id,
+        origin: &app_state.env.webauthn_origin,
+    };
+
+    let registered = webauthn::verify_registration(
+        &rp,
+        &webauthn_challenge.challenge,
+        &body.credential.response.client_data_json,
+        &body.credential.response.attestation_object,
+    )
+    .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let result = app_state.db_client
+        .save_webauthn_credential(
+            user.id,
+            &registered.credential_id,
+            &registered.public_key,
+            registered.sign_count as i64,
+            &body.name,
+        )
+        .await;
+
+    match result {
+        Ok(credential) => {
+            let response = PasskeyResponseDto {
+                status: "success".to_string(),
+                passkey: FilterPasskeyDto::filter_passkey(&credential),
+            };
+
+            Ok((StatusCode::CREATED, Json(response)))
+        },
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Err(HttpError::unquie_constraint_violation("Passkey is already registered".to_string()))
+        },
+        Err(e) => Err(HttpError::server_error(e.to_string())),
+    }
+}
+
+pub async fn get_passkeys(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    let credentials = app_state.db_client
+        .get_user_webauthn_credentials(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = PasskeyListResponseDto {
+        status: "success".to_string(),
+        passkeys: FilterPasskeyDto::filter_passkeys(&credentials),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn delete_passkey(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Path(passkey_id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_webauthn_credential(user.user.id, passkey_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Passkey not found".to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Passkey deleted successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn passkey_login_options(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let challenge = webauthn::generate_challenge();
+    let expires_at = Utc::now() + Duration::seconds(CEREMONY_TIMEOUT_SECONDS);
+
+    let webauthn_challenge = app_state.db_client
+        .save_webauthn_challenge(None, &challenge, AUTHENTICATION_CEREMONY, expires_at)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let public_key = PublicKeyCredentialRequestOptionsDto {
+        challenge,
+        timeout: CEREMONY_TIMEOUT_SECONDS as u64 * 1000,
+        rp_id: app_state.env.webauthn_rp_id.to_owned(),
+        allow_credentials: Vec::new(),
+        user_verification: "required".to_string(),
+    };
+
+    let response = PasskeyLoginOptionsResponseDto {
+        status: "success".to_string(),
+        challenge_id: webauthn_challenge.id.to_string(),
+        public_key,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn passkey_login(
+    Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(body): Json<PasskeyLoginDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    let webauthn_challenge = app_state.db_client
+        .take_webauthn_challenge(body.challenge_id, AUTHENTICATION_CEREMONY)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::InvalidPasskey.to_string()))?;
+
+    let credential_id = webauthn::encode(&webauthn::decode(&body.credential.id)
+        .map_err(|e| HttpError::unauthorized(e.to_string()))?);
+
+    let credential = app_state.db_client
+        .get_webauthn_credential(&credential_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::InvalidPasskey.to_string()))?;
+
+    if let Some(user_handle) = &body.credential.response.user_handle {
+        let user_handle = webauthn::decode(user_handle)
+            .map_err(|e| HttpError::unauthorized(e.to_string()))?;
+
+        if user_handle != credential.user_id.as_bytes() {
+            return Err(HttpError::unauthorized(ErrorMessage::InvalidPasskey.to_string()));
+        }
+    }
+
+    let rp = RelyingParty {
+        id: &app_state.env.webauthn_rp_id,
+        origin: &app_state.env.webauthn_origin,
+    };
+
+    let sign_count = webauthn::verify_authentication(
+        &rp,
+        &webauthn_challenge.challenge,
+        &body.credential.response.client_data_json,
+        &body.credential.response.authenticator_data,
+        &body.credential.response.signature,
+        &credential.public_key,
+        credential.sign_count as u32,
+    )
+    .map_err(|e| HttpError::unauthorized(e.to_string()))?;
+
+    let updated = app_state.db_client
+        .update_webauthn_sign_count(credential.id, sign_count as i64)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Another login already saved this counter, so the credential was cloned.
+    if !updated {
+        return Err(HttpError::unauthorized(ErrorMessage::PasskeyCounterRegression.to_string()));
+    }
+
+    let result = app_state.db_client
+        .get_user(Some(credential.user_id), None, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    create_login_session(&app_state, &user, &headers, addr).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::{
+        database::WebauthnExt,
+        test_support::{self, body_json, json_request, send},
+        utils::webauthn::{self, tests::{SoftwareAuthenticator, FLAGS, ORIGIN, RP_ID}}
+    };
+
+    #[sqlx::test]
+    async fn registers_a_passkey_and_logs_in_with_it(pool: PgPool) {
+        let mut config = test_support::config();
+        config.webauthn_rp_id = RP_ID.to_string();
+        config.webauthn_origin = ORIGIN.to_string();
+
+        let app_state = test_support::app_state(config, pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "passkey@example.com", "password1").await;
+        let token = test_support::login(&router, "passkey@example.com", "password1").await;
+
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential_id = webauthn::encode(&authenticator.credential_id);
+
+        let request = json_request(Method::POST, "/api/users/me/passkeys/options", Some(&token), json!({}));
+        let options = body_json(send(&router, request).await).await;
+
+        let response = authenticator.register(RP_ID, ORIGIN, options["publicKey"]["challenge"].as_str().unwrap(), FLAGS);
+        let request = json_request(Method::POST, "/api/users/me/passkeys", Some(&token), json!({
+            "challenge_id": options["challenge_id"],
+            "name": "Laptop",
+            "credential": {
+                "id": credential_id,
+                "response": {
+                    "clientDataJSON": response.client_data_json,
+                    "attestationObject": response.attestation_object,
+                },
+            },
+        }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::CREATED);
+
+        let passkey_login = async |authenticator: &mut SoftwareAuthenticator| {
+            let request = json_request(Method::POST, "/api/auth/passkey/options", None, json!({}));
+            let options = body_json(send(&router, request).await).await;
+
+            let response = authenticator.authenticate(RP_ID, ORIGIN, options["publicKey"]["challenge"].as_str().unwrap(), FLAGS);
+            let request = json_request(Method::POST, "/api/auth/passkey/login", None, json!({
+                "challenge_id": options["challenge_id"],
+                "credential": {
+                    "id": credential_id,
+                    "response": {
+                        "clientDataJSON": response.client_data_json,
+                        "authenticatorData": response.authenticator_data,
+                        "signature": response.signature,
+                        "userHandle": null,
+                    },
+                },
+            }));
+
+            send(&router, request).await
+        };
+
+        let response = passkey_login(&mut authenticator).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_json(response).await["token"].is_string());
+
+        // A cloned authenticator replays a counter the server already saw.
+        authenticator.sign_count -= 1;
+        assert_eq!(passkey_login(&mut authenticator).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn saves_a_sign_count_only_once(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let user = test_support::create_user(&app_state, "counter@example.com", "password1").await;
+
+        let counted = app_state.db_client.save_webauthn_credential(user.id, "counted", &[1], 1, "Key").await.unwrap();
+
+        // Two logins that both verified against the stored counter of 1.
+        assert!(app_state.db_client.update_webauthn_sign_count(counted.id, 2).await.unwrap());
+        assert!(!app_state.db_client.update_webauthn_sign_count(counted.id, 2).await.unwrap());
+
+        let uncounted = app_state.db_client.save_webauthn_credential(user.id, "uncounted", &[2], 0, "Key").await.unwrap();
+
+        assert!(app_state.db_client.update_webauthn_sign_count(uncounted.id, 0).await.unwrap());
+        assert!(app_state.db_client.update_webauthn_sign_count(uncounted.id, 0).await.unwrap());
+    }
+}
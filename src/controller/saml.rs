@@ -0,0 +1,212 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Extension},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    Form
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use chrono::{Duration, Utc};
+use validator::ValidateEmail;
+
+use crate::{
+    controller::auth::{login_redirect, provision_user},
+    dtos::SamlResponseDto,
+    error::{
+        ErrorMessage,
+        HttpError
+    },
+    models::UserRole,
+    utils::{
+        saml::{self, IdentityProvider, ResponseExpectations, SamlAssertion, SAML_REQUEST_COOKIE, SAML_REQUEST_MAXAGE},
+        token
+    },
+    AppState
+};
+
+pub async fn metadata(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        saml::sp_metadata(&app_state.env.saml_sp_entity_id),
+    )
+}
+
+/// SP-initiated sign-in: sends the browser to the IdP with an AuthnRequest.
+pub async fn saml_login(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let idp = identity_provider(&app_state)?;
+
+    let request = saml::authn_request(idp, &app_state.env.saml_sp_entity_id)
+        .map_err(HttpError::server_error)?;
+
+    let request_token = token::create_saml_request_token(&request.id, &app_state.key_ring, &app_state.env)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // The IdP posts the response back cross-site, which only carries
+    // cookies marked SameSite=None.
+    let cookie = Cookie::build((SAML_REQUEST_COOKIE, request_token))
+        .path("/api/saml")
+        .max_age(time::Duration::minutes(SAML_REQUEST_MAXAGE))
+        .same_site(SameSite::None)
+        .secure(true)
+        .http_only(true)
+        .build();
+
+    let mut response = Redirect::to(&request.url).into_response();
+    response.headers_mut().append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+
+    Ok(response)
+}
+
+/// Assertion consumer service for the HTTP-POST binding. A valid assertion
+/// signs the user in exactly like a password login.
+pub async fn assertion_consumer_service(
+    Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    cookie_jar: CookieJar,
+    headers: HeaderMap,
+    Form(body): Form<SamlResponseDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    let idp = identity_provider(&app_state)?;
+
+    let request_id = cookie_jar
+        .get(SAML_REQUEST_COOKIE)
+        .and_then(|cookie| token::decode_saml_request_token(cookie.value(), &app_state.key_ring, &app_state.env).ok())
+        .map(|claims| claims.request_id);
+
+    let expectations = ResponseExpectations {
+        sp_entity_id: &app_state.env.saml_sp_entity_id,
+        request_id: request_id.as_deref(),
+        allow_idp_initiated: app_state.env.saml_allow_idp_initiated,
+    };
+
+    let assertion = saml::parse_response(&body.saml_response, idp, &expectations)
+        .map_err(|e| HttpError::unauthorized(ErrorMessage::InvalidSamlResponse(e).to_string()))?;
+
+    // Assertion ids are kept in the revocation store for as long as the
+    // assertion would be accepted, clock skew included, so each one can only
+    // be used once.
+    let replay_key = token::hash_token(&format!("saml:{}", assertion.id));
+    let replay_until = assertion.not_on_or_after + Duration::seconds(saml::CLOCK_SKEW);
+
+    let first_use = app_state.revocation_store
+        .revoke(&replay_key, replay_until.max(Utc::now()))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !first_use {
+        return Err(HttpError::unauthorized(ErrorMessage::SamlAssertionReused.to_string()));
+    }
+
+    let (email, name, role) = map_attributes(&app_state, &assertion)?;
+    // Without a NameID the email is the only stable name for the account.
+    let subject = assertion.name_id.as_deref().unwrap_or(&email);
+    let user = provision_user(&app_state, saml::PROVIDER, subject, &email, &name, role).await?;
+
+    let mut response = login_redirect(&app_state, &user, &headers, addr).await?;
+
+    let cookie = Cookie::build((SAML_REQUEST_COOKIE, ""))
+        .path("/api/saml")
+        .max_age(time::Duration::ZERO)
+        .same_site(SameSite::None)
+        .secure(true)
+        .http_only(true)
+        .build();
+
+    response.headers_mut().append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+
+    Ok(response)
+}
+
+/// Maps assertion attributes to the user's email, name and role. The email
+/// falls back to the NameID, and roles are only managed through SAML when
+/// `SAML_ADMIN_VALUES` is set.
+fn map_attributes(
+    app_state: &AppState,
+    assertion: &SamlAssertion,
+) -> Result<(String, String, Option<UserRole>), HttpError> {
+    let config = &app_state.env;
+
+    let email = assertion.attribute(&config.saml_email_attribute)
+        .first()
+        .or(assertion.name_id.as_ref())
+        .map(|email| email.to_lowercase())
+        .filter(|email| email.validate_email())
+        .ok_or_else(|| {
+            HttpError::unauthorized(ErrorMessage::InvalidSamlResponse("assertion has no email".to_string()).to_string())
+        })?;
+
+    let name = assertion.attribute(&config.saml_name_attribute)
+        .first()
+        .cloned()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(&email).to_string());
+
+    let role = (!config.saml_admin_values.is_empty()).then(|| {
+        let is_admin = assertion.attribute(&config.saml_role_attribute)
+            .iter()
+            .any(|value| config.saml_admin_values.iter().any(|admin| admin.eq_ignore_ascii_case(value)));
+
+        if is_admin { UserRole::Admin } else { UserRole::User }
+    });
+
+    Ok((email, name, role))
+}
+
+fn identity_provider(app_state: &AppState) -> Result<&IdentityProvider, HttpError> {
+    app_state.saml_idp
+        .as_ref()
+        .ok_or_else(|| HttpError::new(ErrorMessage::SamlNotConfigured.to_string(), StatusCode::NOT_FOUND))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        database::{DBClient, UserExt},
+        test_support::{self, body_json, send},
+        utils::saml::tests::{identity_provider, TestAssertion, SP_ENTITY_ID}
+    };
+
+    #[sqlx::test]
+    async fn accepts_each_assertion_once(pool: PgPool) {
+        let mut config = test_support::config();
+        config.saml_sp_entity_id = SP_ENTITY_ID.to_string();
+
+        let mut app_state = AppState::new(config, DBClient::new(pool)).unwrap();
+        app_state.saml_idp = Some(identity_provider());
+
+        let app_state = Arc::new(app_state);
+        let router = test_support::router(app_state.clone());
+
+        let request_token = token::create_saml_request_token("_request", &app_state.key_ring, &app_state.env).unwrap();
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("SAMLResponse", &TestAssertion::default().encoded_response())
+            .finish();
+
+        let post_response = async || {
+            let request = Request::post("/api/saml/acs")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header(header::COOKIE, format!("{}={}", SAML_REQUEST_COOKIE, request_token))
+                .body(Body::from(body.clone()))
+                .unwrap();
+
+            send(&router, request).await
+        };
+
+        assert_eq!(post_response().await.status(), StatusCode::SEE_OTHER);
+
+        let user = app_state.db_client.get_user(None, None, Some("alice@corp.com")).await.unwrap().unwrap();
+        assert!(user.verified);
+
+        let response = post_response().await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body_json(response).await["message"], ErrorMessage::SamlAssertionReused.to_string());
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json
+};
+use validator::Validate;
+
+use crate::{
+    database::ServiceAccountExt,
+    dtos::{
+        CreateServiceAccountDto,
+        FilterServiceAccountDto,
+        Response,
+        ServiceAccountCreatedResponseDto,
+        ServiceAccountListResponseDto
+    },
+    error::{
+        ErrorMessage,
+        HttpError
+    },
+    middleware::JWTAuthMiddleware,
+    utils::{oauth, token},
+    AppState
+};
+
+/// Creates a service account and the client credentials it uses with the
+/// client_credentials grant. Its scopes are the personal access token scopes.
+pub async fn create_service_account(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<CreateServiceAccountDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let mut scopes = body.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    if let Some(scope) = scopes.iter().find(|scope| !token::ACCESS_TOKEN_SCOPES.contains(&scope.as_str())) {
+        return Err(HttpError::bad_request(ErrorMessage::UnknownScope(scope.to_string()).to_string()));
+    }
+
+    let client_secret = token::generate_opaque_token();
+
+    let service_account = app_state.db_client
+        .save_service_account(
+            &body.name,
+            body.role,
+            &oauth::generate_client_id(),
+            &token::hash_token(&client_secret),
+            &scopes,
+            user.user.id,
+        )
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = ServiceAccountCreatedResponseDto {
+        status: "success".to_string(),
+        service_account: FilterServiceAccountDto::filter_service_account(&service_account),
+        client_secret,
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn get_service_accounts(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let service_accounts = app_state.db_client
+        .get_service_accounts()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = ServiceAccountListResponseDto {
+        status: "success".to_string(),
+        service_accounts: FilterServiceAccountDto::filter_service_accounts(&service_accounts),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn delete_service_account(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_service_account(id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Service account not found".to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Service account deleted successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+        Router
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use crate::{database::UserExt, models::UserRole, test_support};
+
+    /// Creates a service account as an admin and returns its client id and
+    /// secret.
+    async fn service_account(router: &Router, app_state: &crate::AppState, scopes: Value) -> (String, String) {
+        let admin = test_support::create_user(app_state, "admin@example.com", "violet lantern orbit").await;
+        app_state.db_client.update_user_role(admin.id, UserRole::Admin).await.unwrap();
+        let session = test_support::login(router, "admin@example.com", "violet lantern orbit").await;
+
+        let request = test_support::json_request(
+            Method::POST,
+            "/api/oauth/service-accounts",
+            Some(&session),
+            json!({ "name": "ci", "scopes": scopes }),
+        );
+        let response = test_support::send(router, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body = test_support::body_json(response).await;
+
+        (
+            body["serviceAccount"]["clientId"].as_str().unwrap().to_string(),
+            body["clientSecret"].as_str().unwrap().to_string(),
+        )
+    }
+
+    async fn client_credentials(router: &Router, client_id: &str, client_secret: &str, scope: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/oauth/token")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::AUTHORIZATION, format!("Basic {}", STANDARD.encode(format!("{client_id}:{client_secret}"))))
+            .body(Body::from(format!("grant_type=client_credentials&scope={scope}")))
+            .unwrap();
+
+        let response = test_support::send(router, request).await;
+        let status = response.status();
+
+        (status, test_support::body_json(response).await)
+    }
+
+    #[sqlx::test]
+    async fn client_credentials_tokens_are_limited_to_the_account_scopes(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let (client_id, client_secret) = service_account(&router, &app_state, json!(["profile:read"])).await;
+
+        let (status, body) = client_credentials(&router, &client_id, &client_secret, "profile:read").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.get("refresh_token").is_none());
+        let access_token = body["access_token"].as_str().unwrap();
+
+        let request = test_support::json_request(Method::GET, "/api/users/me", Some(access_token), json!({}));
+        assert_eq!(test_support::send(&router, request).await.status(), StatusCode::OK);
+
+        let request = test_support::json_request(Method::PUT, "/api/users/name", Some(access_token), json!({ "name": "Renamed" }));
+        assert_eq!(test_support::send(&router, request).await.status(), StatusCode::FORBIDDEN);
+
+        let (status, body) = client_credentials(&router, &client_id, &client_secret, "users:write").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_scope");
+    }
+
+    #[sqlx::test]
+    async fn refuses_a_wrong_client_secret(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let (client_id, _) = service_account(&router, &app_state, json!(["profile:read"])).await;
+
+        let (status, body) = client_credentials(&router, &client_id, "not-the-secret", "profile:read").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_client");
+    }
+}
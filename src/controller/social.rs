@@ -0,0 +1,613 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    Json
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use validator::ValidateEmail;
+
+use crate::{
+    config::SocialProviderConfig,
+    controller::auth::login_redirect,
+    database::{IdentityExt, UserExt, WebauthnExt},
+    dtos::{
+        FilterIdentityDto,
+        IdentityListResponseDto,
+        Response,
+        SocialCallbackQueryDto,
+        SocialProviderListResponseDto
+    },
+    error::{
+        ErrorMessage,
+        HttpError
+    },
+    middleware::JWTAuthMiddleware,
+    models::User,
+    utils::{
+        social::{self, SocialProfile, SOCIAL_STATE_COOKIE, SOCIAL_STATE_MAXAGE},
+        token
+    },
+    AppState
+};
+
+pub async fn get_providers(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    let response = SocialProviderListResponseDto {
+        status: "success".to_string(),
+        providers: app_state.env.social_providers
+            .iter()
+            .map(|provider| provider.name.to_owned())
+            .collect(),
+    };
+
+    Json(response)
+}
+
+/// Sends the browser to the provider to sign in.
+pub async fn social_login(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Path(provider): Path<String>,
+) -> Result<impl IntoResponse, HttpError> {
+    start_social_flow(&app_state, &provider, None)
+}
+
+/// Sends a logged-in user to the provider to link it to their account.
+pub async fn social_link(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Path(provider): Path<String>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    start_social_flow(&app_state, &provider, Some(user.user.id))
+}
+
+fn start_social_flow(
+    app_state: &AppState,
+    provider: &str,
+    link_user_id: Option<uuid::Uuid>,
+) -> Result<axum::response::Response, HttpError> {
+    let provider = find_provider(app_state, provider)?;
+
+    let state = token::generate_opaque_token();
+    let (code_verifier, code_challenge) = social::generate_pkce();
+    let nonce = provider.is_oidc().then(token::generate_opaque_token);
+
+    let state_token = token::create_social_state_token(
+        &provider.name,
+        &state,
+        &code_verifier,
+        nonce.as_deref(),
+        link_user_id.map(|user_id| user_id.to_string()).as_deref(),
+        &app_state.key_ring,
+        &app_state.env
+    )
+    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let cookie = Cookie::build((SOCIAL_STATE_COOKIE, state_token))
+        .path("/api/auth/social")
+        .max_age(time::Duration::minutes(SOCIAL_STATE_MAXAGE))
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build();
+
+    let authorization_url = social::authorization_url(provider, &state, &code_challenge, nonce.as_deref());
+
+    let mut response = Redirect::to(&authorization_url).into_response();
+    response.headers_mut().append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+
+    Ok(response)
+}
+
+/// The provider redirects back here. Depending on how the flow started,
+/// this links the account, signs in the user it is linked to, or creates
+/// a new user from a verified email.
+pub async fn social_callback(
+    Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(provider): Path<String>,
+    Query(query): Query<SocialCallbackQueryDto>,
+    cookie_jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    let provider = find_provider(&app_state, &provider)?;
+
+    let state_claims = cookie_jar
+        .get(SOCIAL_STATE_COOKIE)
+        .ok_or_else(|| HttpError::bad_request(ErrorMessage::InvalidSocialState.to_string()))
+        .and_then(|cookie| {
+            token::decode_social_state_token(cookie.value(), &app_state.key_ring, &app_state.env)
+                .map_err(|_| HttpError::bad_request(ErrorMessage::InvalidSocialState.to_string()))
+        })?;
+
+    if state_claims.provider != provider.name || query.state.as_deref() != Some(state_claims.state.as_str()) {
+        return Err(HttpError::bad_request(ErrorMessage::InvalidSocialState.to_string()));
+    }
+
+    if let Some(error) = query.error {
+        let description = query.error_description.unwrap_or(error);
+        return Err(HttpError::unauthorized(format!("{} sign-in failed: {}", provider.name, description)));
+    }
+
+    let code = query.code
+        .ok_or_else(|| HttpError::bad_request("code is required"))?;
+
+    let tokens = social::exchange_code(&app_state.http_client, provider, &code, &state_claims.code_verifier)
+        .await
+        .map_err(HttpError::unauthorized)?;
+
+    let id_token_claims = match (provider.is_oidc(), tokens.id_token.as_deref()) {
+        (false, _) => None,
+        (true, Some(id_token)) => Some(
+            social::verify_id_token(&app_state.http_client, provider, id_token, state_claims.nonce.as_deref())
+                .await
+                .map_err(HttpError::unauthorized)?
+        ),
+        (true, None) => {
+            return Err(HttpError::unauthorized(format!("{} did not return an ID token", provider.name)));
+        },
+    };
+
+    let profile = social::fetch_profile(&app_state.http_client, provider, &tokens.access_token)
+        .await
+        .map_err(HttpError::unauthorized)?;
+
+    // The userinfo response must describe the account the ID token was issued for.
+    if id_token_claims.is_some_and(|claims| claims.sub != profile.subject) {
+        return Err(HttpError::unauthorized(format!("{} returned a profile for another account", provider.name)));
+    }
+
+    let mut response = match state_claims.link_user_id {
+        Some(user_id) => {
+            let user_id = uuid::Uuid::parse_str(&user_id)
+                .map_err(|_| HttpError::bad_request(ErrorMessage::InvalidSocialState.to_string()))?;
+
+            link_identity(&app_state, provider, &profile, user_id).await?;
+
+            Redirect::to("http://localhost:5173/settings").into_response()
+        },
+        None => {
+            let user = sign_in_user(&app_state, provider, &profile).await?;
+
+            login_redirect(&app_state, &user, &headers, addr).await?
+        },
+    };
+
+    let cookie = Cookie::build((SOCIAL_STATE_COOKIE, ""))
+        .path("/api/auth/social")
+        .max_age(time::Duration::ZERO)
+        .http_only(true)
+        .build();
+
+    response.headers_mut().append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+
+    Ok(response)
+}
+
+async fn link_identity(
+    app_state: &AppState,
+    provider: &SocialProviderConfig,
+    profile: &SocialProfile,
+    user_id: uuid::Uuid,
+) -> Result<(), HttpError> {
+    let identity = app_state.db_client
+        .get_identity(&provider.name, &profile.subject)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    match identity {
+        Some(identity) if identity.user_id == user_id => {
+            app_state.db_client
+                .touch_identity(identity.id, profile.email.as_deref())
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+        },
+        Some(_) => {
+            return Err(HttpError::unquie_constraint_violation(ErrorMessage::IdentityAlreadyLinked.to_string()));
+        },
+        None => {
+            app_state.db_client
+                .save_identity(user_id, &provider.name, &profile.subject, profile.email.as_deref())
+                .await
+                .map_err(|e| match &e {
+                    sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                        HttpError::unquie_constraint_violation(format!("Another {} account is already linked", provider.name))
+                    },
+                    _ => HttpError::server_error(e.to_string()),
+                })?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Finds the user an external account is linked to, or provisions a new
+/// one. Existing accounts are never linked by email alone, since the
+/// provider only vouches for its own account.
+async fn sign_in_user(
+    app_state: &AppState,
+    provider: &SocialProviderConfig,
+    profile: &SocialProfile,
+) -> Result<User, HttpError> {
+    let identity = app_state.db_client
+        .get_identity(&provider.name, &profile.subject)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if let Some(identity) = identity {
+        app_state.db_client
+            .touch_identity(identity.id, profile.email.as_deref())
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let user = app_state.db_client
+            .get_user(Some(identity.user_id), None, None)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        return user.ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()));
+    }
+
+    let email = profile.email
+        .as_deref()
+        .filter(|email| profile.email_verified && email.validate_email())
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::SocialEmailNotVerified.to_string()))?;
+
+    let existing_user = app_state.db_client
+        .get_user(None, None, Some(email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if existing_user.is_some() {
+        return Err(HttpError::unquie_constraint_violation(ErrorMessage::SocialAccountExists.to_string()));
+    }
+
+    let name = profile.name
+        .as_deref()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(email));
+    let name: String = name.chars().take(100).collect();
+
+    // Provisioned users have no password until they set one through a reset.
+    let user = app_state.db_client
+        .save_user(name, email.to_string(), String::new())
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                HttpError::unquie_constraint_violation(ErrorMessage::EmailExists.to_string())
+            },
+            _ => HttpError::server_error(e.to_string()),
+        })?;
+
+    app_state.db_client
+        .verify_user(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    app_state.db_client
+        .save_identity(user.id, &provider.name, &profile.subject, Some(email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(User { verified: true, ..user })
+}
+
+pub async fn get_identities(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    let identities = app_state.db_client
+        .get_user_identities(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = IdentityListResponseDto {
+        status: "success".to_string(),
+        identities: FilterIdentityDto::filter_identities(&identities),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn unlink_identity(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Path(identity_id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, HttpError> {
+    if user.user.password.is_empty() {
+        let identities = app_state.db_client
+            .get_user_identities(user.user.id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let passkeys = app_state.db_client
+            .get_user_webauthn_credentials(user.user.id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let last_identity = identities.iter().all(|identity| identity.id == identity_id);
+
+        if last_identity && passkeys.is_empty() {
+            return Err(HttpError::bad_request(ErrorMessage::LastSignInMethod.to_string()));
+        }
+    }
+
+    let deleted = app_state.db_client
+        .delete_identity(user.user.id, identity_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Identity not found".to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Identity unlinked successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+fn find_provider<'a>(app_state: &'a AppState, name: &str) -> Result<&'a SocialProviderConfig, HttpError> {
+    app_state.env.social_providers
+        .iter()
+        .find(|provider| provider.name == name)
+        .ok_or_else(|| HttpError::new(ErrorMessage::UnknownProvider(name.to_string()).to_string(), StatusCode::NOT_FOUND))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use argon2::password_hash::rand_core::OsRng;
+    use axum::{
+        body::Body,
+        http::{Request, Response as HttpResponse},
+        routing::{get, post},
+        Router
+    };
+    use jsonwebtoken::{Algorithm, Header};
+    use p256::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        test_support::{self, body_json, send},
+        utils::keys::JwtKey
+    };
+
+    const CLIENT_ID: &str = "auth-validator";
+    const SUBJECT: &str = "mock-1234";
+    const EMAIL: &str = "social@example.com";
+
+    /// A local OpenID Connect provider whose responses each test can change.
+    #[derive(Clone)]
+    struct MockProvider {
+        issuer: String,
+        key: JwtKey,
+        id_token_claims: Arc<Mutex<Value>>,
+        userinfo: Arc<Mutex<Value>>,
+    }
+
+    impl MockProvider {
+        async fn start() -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+            let secret_key = p256::SecretKey::random(&mut OsRng);
+            let private_pem = secret_key.to_pkcs8_pem(LineEnding::LF).unwrap();
+            let public_pem = secret_key.public_key().to_public_key_pem(LineEnding::LF).unwrap();
+            let key = JwtKey::from_pem("mock-key", Algorithm::ES256, private_pem.as_bytes(), public_pem.as_bytes()).unwrap();
+
+            let provider = Self {
+                issuer,
+                key,
+                id_token_claims: Arc::new(Mutex::new(Value::Null)),
+                userinfo: Arc::new(Mutex::new(json!({
+                    "sub": SUBJECT,
+                    "email": EMAIL,
+                    "email_verified": true,
+                    "name": "Social User",
+                }))),
+            };
+
+            let token = provider.clone();
+            let userinfo = provider.clone();
+            let jwks = provider.clone();
+
+            let router = Router::new()
+                .route("/token", post(async move || Json(token.token_response())))
+                .route("/userinfo", get(async move || Json(userinfo.userinfo.lock().unwrap().clone())))
+                .route("/jwks", get(async move || Json(json!({ "keys": [jwks.key.jwk.clone()] }))));
+
+            tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+            provider
+        }
+
+        fn config(&self) -> SocialProviderConfig {
+            SocialProviderConfig {
+                name: "mock".to_string(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: "secret".to_string(),
+                authorize_url: format!("{}/authorize", self.issuer),
+                token_url: format!("{}/token", self.issuer),
+                userinfo_url: format!("{}/userinfo", self.issuer),
+                scopes: "openid email profile".to_string(),
+                subject_claim: "sub".to_string(),
+                email_claim: "email".to_string(),
+                email_verified_claim: "email_verified".to_string(),
+                name_claim: "name".to_string(),
+                issuer: Some(self.issuer.clone()),
+                jwks_url: Some(format!("{}/jwks", self.issuer)),
+            }
+        }
+
+        /// Issues the next ID token for `nonce`, after applying `changes`
+        /// to the claims a well-behaved provider would send.
+        fn issue_id_token(&self, nonce: &str, changes: Value) {
+            let now = chrono::Utc::now().timestamp();
+            let mut claims = json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "sub": SUBJECT,
+                "nonce": nonce,
+                "iat": now,
+                "exp": now + 300,
+            });
+
+            for (name, value) in changes.as_object().unwrap() {
+                claims[name] = value.clone();
+            }
+
+            *self.id_token_claims.lock().unwrap() = claims;
+        }
+
+        fn token_response(&self) -> Value {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some(self.key.kid.clone());
+
+            let claims = self.id_token_claims.lock().unwrap().clone();
+            let id_token = jsonwebtoken::encode(&header, &claims, self.key.encoding_key.as_ref().unwrap()).unwrap();
+
+            json!({ "access_token": "mock-access-token", "token_type": "Bearer", "id_token": id_token })
+        }
+    }
+
+    /// A browser that started signing in with the mock provider.
+    struct SignIn {
+        cookie: String,
+        state: String,
+        nonce: String,
+    }
+
+    async fn setup(pool: PgPool) -> (Arc<AppState>, Router, MockProvider) {
+        let provider = MockProvider::start().await;
+
+        let mut config = test_support::config();
+        config.social_providers = vec![provider.config()];
+
+        let app_state = test_support::app_state(config, pool);
+        let router = test_support::router(app_state.clone());
+
+        (app_state, router, provider)
+    }
+
+    async fn start_sign_in(router: &Router) -> SignIn {
+        let request = Request::get("/api/auth/social/mock").body(Body::empty()).unwrap();
+        let response = send(router, request).await;
+
+        let location = url::Url::parse(response.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+        let param = |name: &str| {
+            location.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned()).unwrap()
+        };
+
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+
+        SignIn {
+            cookie: cookie.split(';').next().unwrap().to_string(),
+            state: param("state"),
+            nonce: param("nonce"),
+        }
+    }
+
+    async fn callback(router: &Router, sign_in: &SignIn, state: &str) -> HttpResponse<Body> {
+        let request = Request::get(format!("/api/auth/social/mock/callback?code=mock-code&state={}", state))
+            .header(header::COOKIE, &sign_in.cookie)
+            .body(Body::empty())
+            .unwrap();
+
+        send(router, request).await
+    }
+
+    #[sqlx::test]
+    async fn signs_in_with_a_valid_id_token(pool: PgPool) {
+        let (app_state, router, provider) = setup(pool).await;
+
+        let sign_in = start_sign_in(&router).await;
+        provider.issue_id_token(&sign_in.nonce, json!({}));
+
+        let response = callback(&router, &sign_in, &sign_in.state).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let identity = app_state.db_client.get_identity("mock", SUBJECT).await.unwrap().unwrap();
+        let user = app_state.db_client.get_user(Some(identity.user_id), None, None).await.unwrap().unwrap();
+        assert_eq!(user.email, EMAIL);
+        assert!(user.verified);
+    }
+
+    #[sqlx::test]
+    async fn rejects_a_state_or_nonce_mismatch(pool: PgPool) {
+        let (_, router, provider) = setup(pool).await;
+
+        let sign_in = start_sign_in(&router).await;
+        provider.issue_id_token(&sign_in.nonce, json!({}));
+
+        let response = callback(&router, &sign_in, "forged-state").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(response).await["message"], ErrorMessage::InvalidSocialState.to_string());
+
+        // An ID token issued for another sign-in.
+        let other = start_sign_in(&router).await;
+        provider.issue_id_token(&other.nonce, json!({}));
+        assert_eq!(callback(&router, &sign_in, &sign_in.state).await.status(), StatusCode::UNAUTHORIZED);
+
+        provider.issue_id_token(&sign_in.nonce, json!({ "nonce": null }));
+        assert_eq!(callback(&router, &sign_in, &sign_in.state).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_an_id_token_for_another_client_or_issuer(pool: PgPool) {
+        let (app_state, router, provider) = setup(pool).await;
+
+        let rejected = [
+            json!({ "aud": "another-client" }),
+            json!({ "iss": "https://idp.example.com" }),
+            json!({ "exp": chrono::Utc::now().timestamp() - 600 }),
+            json!({ "sub": "someone-else" }),
+        ];
+
+        for changes in rejected {
+            let sign_in = start_sign_in(&router).await;
+            provider.issue_id_token(&sign_in.nonce, changes.clone());
+
+            let response = callback(&router, &sign_in, &sign_in.state).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{} was accepted", changes);
+        }
+
+        assert!(app_state.db_client.get_identity("mock", SUBJECT).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn refuses_an_unverified_email(pool: PgPool) {
+        let (app_state, router, provider) = setup(pool).await;
+        provider.userinfo.lock().unwrap()["email_verified"] = json!(false);
+
+        let sign_in = start_sign_in(&router).await;
+        provider.issue_id_token(&sign_in.nonce, json!({}));
+
+        let response = callback(&router, &sign_in, &sign_in.state).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body_json(response).await["message"], ErrorMessage::SocialEmailNotVerified.to_string());
+
+        assert!(app_state.db_client.get_user(None, None, Some(EMAIL)).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn refuses_to_sign_in_to_an_existing_account_by_email(pool: PgPool) {
+        let (app_state, router, provider) = setup(pool).await;
+        let user = test_support::create_user(&app_state, EMAIL, "password1").await;
+
+        let sign_in = start_sign_in(&router).await;
+        provider.issue_id_token(&sign_in.nonce, json!({}));
+
+        let response = callback(&router, &sign_in, &sign_in.state).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(body_json(response).await["message"], ErrorMessage::SocialAccountExists.to_string());
+
+        assert!(app_state.db_client.get_user_identities(user.id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Extension,
+    response::IntoResponse,
+    Json
+};
+use validator::Validate;
+
+use crate::{
+    database::TwoFactorExt,
+    dtos::{
+        RecoveryCodesResponseDto,
+        Response,
+        TwoFactorCodeDto,
+        TwoFactorDisableDto,
+        TwoFactorSetupResponseDto
+    },
+    error::{
+        ErrorMessage,
+        HttpError
+    },
+    middleware::JWTAuthMiddleware,
+    models::User,
+    utils::{password::PasswordHashing, totp},
+    AppState
+};
+
+pub async fn setup_two_factor(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = &user.user;
+
+    let existing = app_state.db_client
+        .get_totp(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if existing.is_some_and(|totp| totp.enabled) {
+        return Err(HttpError::bad_request(ErrorMessage::TwoFactorAlreadyEnabled.to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    let otpauth_url = totp::provisioning_uri(&secret, &app_state.env.totp_issuer, &user.email)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    app_state.db_client
+        .save_totp_secret(user.id, &secret)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = TwoFactorSetupResponseDto {
+        status: "success".to_string(),
+        secret,
+        otpauth_url,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn confirm_two_factor(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<TwoFactorCodeDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user = &user.user;
+
+    let result = app_state.db_client
+        .get_totp(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let user_totp = result.ok_or(HttpError::bad_request(ErrorMessage::TwoFactorNotEnabled.to_string()))?;
+
+    if user_totp.enabled {
+        return Err(HttpError::bad_request(ErrorMessage::TwoFactorAlreadyEnabled.to_string()));
+    }
+
+    let step = totp::verify_code(&user_totp.secret, &body.code)
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request(ErrorMessage::InvalidTwoFactorCode.to_string()))?;
+
+    let (recovery_codes, recovery_code_hashes) = new_recovery_codes(&app_state.password_hashing)?;
+
+    app_state.db_client
+        .enable_totp(user.id, step, &recovery_code_hashes)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = RecoveryCodesResponseDto {
+        status: "success".to_string(),
+        recovery_codes,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn disable_two_factor(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<TwoFactorDisableDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user = &user.user;
+
+    // Users provisioned from a directory or identity provider have no
+    // password to confirm, so they confirm with a second-factor code.
+    if user.password.is_empty() {
+        let code = body.code
+            .as_deref()
+            .ok_or_else(|| HttpError::bad_request("Code is required"))?;
+
+        check_user_second_factor(&app_state, user, code).await?;
+    } else {
+        let password = body.password
+            .as_deref()
+            .ok_or_else(|| HttpError::bad_request("Password is required"))?;
+
+        let password_match = app_state.password_hashing.compare(password, &user.password)
+            .map_err(|_| HttpError::bad_request(ErrorMessage::WrongCredentials.to_string()))?;
+
+        if !password_match {
+            return Err(HttpError::bad_request(ErrorMessage::WrongCredentials.to_string()));
+        }
+    }
+
+    app_state.db_client
+        .disable_totp(user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = Response {
+        message: "Two-factor authentication disabled".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn regenerate_recovery_codes(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Json(body): Json<TwoFactorCodeDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user = &user.user;
+
+    check_user_second_factor(&app_state, user, &body.code).await?;
+
+    let (recovery_codes, recovery_code_hashes) = new_recovery_codes(&app_state.password_hashing)?;
+
+    app_state.db_client
+        .replace_recovery_codes(user.id, &recovery_code_hashes)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = RecoveryCodesResponseDto {
+        status: "success".to_string(),
+        recovery_codes,
+    };
+
+    Ok(Json(response))
+}
+
+/// Returns whether the user has confirmed TOTP enrollment.
+pub async fn two_factor_enabled(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+) -> Result<bool, HttpError> {
+    let user_totp = app_state.db_client
+        .get_totp(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(user_totp.is_some_and(|totp| totp.enabled))
+}
+
+/// Accepts either a current TOTP code or an unused recovery code, and
+/// consumes it so it cannot be used again.
+pub async fn verify_second_factor(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+    code: &str,
+) -> Result<bool, HttpError> {
+    let result = app_state.db_client
+        .get_totp(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let user_totp = match result {
+        Some(user_totp) if user_totp.enabled => user_totp,
+        _ => return Err(HttpError::bad_request(ErrorMessage::TwoFactorNotEnabled.to_string())),
+    };
+
+    let step = totp::verify_code(&user_totp.secret, code)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if let Some(step) = step {
+        return app_state.db_client
+            .update_totp_step(user_id, step)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()));
+    }
+
+    let code = code.trim().to_lowercase();
+
+    if !totp::is_recovery_code(&code) {
+        return Ok(false);
+    }
+
+    let recovery_codes = app_state.db_client
+        .get_unused_recovery_codes(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    for recovery_code in recovery_codes {
+        if app_state.password_hashing.compare(&code, &recovery_code.code_hash).unwrap_or(false) {
+            return app_state.db_client
+                .use_recovery_code(recovery_code.id)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()));
+        }
+    }
+
+    Ok(false)
+}
+
+/// Checks a second-factor code from a signed-in user. Wrong codes count
+/// toward the account lockout just like failed logins.
+async fn check_user_second_factor(
+    app_state: &AppState,
+    user: &User,
+    code: &str,
+) -> Result<(), HttpError> {
+    let throttle = &app_state.login_throttle;
+
+    throttle.check_account(&user.email).await?;
+
+    if verify_second_factor(app_state, user.id, code).await? {
+        return Ok(());
+    }
+
+    throttle.record_failure(&user.email).await?;
+
+    Err(HttpError::bad_request(ErrorMessage::InvalidTwoFactorCode.to_string()))
+}
+
+fn new_recovery_codes(hashing: &PasswordHashing) -> Result<(Vec<String>, Vec<String>), HttpError> {
+    let recovery_codes = totp::generate_recovery_codes();
+
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| hashing.hash_password(code))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok((recovery_codes, recovery_code_hashes))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    use crate::test_support::{self, body_json, json_request, send};
+
+    fn current_code(secret: &str) -> String {
+        let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+
+        TOTP::new(Algorithm::SHA1, 6, 0, 30, secret, None, String::new())
+            .unwrap()
+            .generate_current()
+            .unwrap()
+    }
+
+    async fn login_mfa(router: &axum::Router, mfa_token: &str, code: &str) -> (StatusCode, Value) {
+        let request = json_request(
+            Method::POST,
+            "/api/auth/login/mfa",
+            None,
+            json!({ "mfa_token": mfa_token, "code": code }),
+        );
+        let response = send(router, request).await;
+
+        (response.status(), body_json(response).await)
+    }
+
+    #[sqlx::test]
+    async fn requires_a_second_factor_once_enrolled(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "totp@example.com", "password1").await;
+        let token = test_support::login(&router, "totp@example.com", "password1").await;
+
+        let request = json_request(Method::POST, "/api/users/me/2fa", Some(&token), json!({}));
+        let setup = body_json(send(&router, request).await).await;
+        let secret = setup["secret"].as_str().unwrap();
+        assert!(setup["otpauth_url"].as_str().unwrap().starts_with("otpauth://totp/"));
+
+        let request = json_request(Method::POST, "/api/users/me/2fa/confirm", Some(&token), json!({ "code": "000000x" }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::BAD_REQUEST);
+
+        let code = current_code(secret);
+        let request = json_request(Method::POST, "/api/users/me/2fa/confirm", Some(&token), json!({ "code": code }));
+        let response = send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["recovery_codes"].as_array().unwrap().len(), 10);
+
+        let challenge = test_support::login_body(&router, "totp@example.com", "password1").await;
+        assert_eq!(challenge["status"], "mfa_required");
+        assert!(challenge["token"].is_null());
+        let mfa_token = challenge["mfa_token"].as_str().unwrap();
+
+        // The challenge token is not an access token.
+        let request = json_request(Method::GET, "/api/users/me", Some(mfa_token), json!({}));
+        assert_eq!(send(&router, request).await.status(), StatusCode::UNAUTHORIZED);
+
+        // The code that confirmed enrollment was already used in this time step.
+        let (status, _) = login_mfa(&router, mfa_token, &code).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = login_mfa(&router, &token, &code).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn recovery_codes_work_once(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "recovery@example.com", "password1").await;
+        let token = test_support::login(&router, "recovery@example.com", "password1").await;
+
+        let request = json_request(Method::POST, "/api/users/me/2fa", Some(&token), json!({}));
+        let secret = body_json(send(&router, request).await).await["secret"].as_str().unwrap().to_string();
+
+        let request = json_request(Method::POST, "/api/users/me/2fa/confirm", Some(&token), json!({ "code": current_code(&secret) }));
+        let recovery_codes = body_json(send(&router, request).await).await["recovery_codes"].clone();
+        let recovery_code = recovery_codes[0].as_str().unwrap();
+
+        let challenge = test_support::login_body(&router, "recovery@example.com", "password1").await;
+        let mfa_token = challenge["mfa_token"].as_str().unwrap();
+
+        let (status, body) = login_mfa(&router, mfa_token, &recovery_code.to_uppercase()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["token"].is_string());
+        assert!(body["refresh_token"].is_string());
+
+        let (status, _) = login_mfa(&router, mfa_token, recovery_code).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
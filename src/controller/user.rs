@@ -1,20 +1,25 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Query, Extension}, 
+    extract::{Path, Query, Extension}, 
+    http::StatusCode,
     response::IntoResponse, 
     Json
 };
 use validator::Validate;
 
 use crate::{
-    database::UserExt,
+    database::{PasswordHistoryExt, SessionExt, UserExt},
     dtos::{
+        FilterSessionDto,
         FilterUserDto, 
+        ImportUsersDto,
+        ImportUsersResponseDto,
         NameUpdateDto, 
         RequestQueryDto, 
         Response, 
         RoleUpdateDto, 
+        SessionListResponseDto,
         UserData, 
         UserListResponseDto, 
         UserPasswordUpdateDto, 
@@ -25,13 +30,13 @@ use crate::{
         HttpError
     }, 
     middleware::JWTAuthMiddleware, 
-    utils::password, 
+    utils::password::HashScheme,
     AppState
 };
 
 pub async fn get_me(
     Extension(_app_state): Extension<Arc<AppState>>,
-    Extension(user): Extension<JWTAuthMiddleware>
+    user: JWTAuthMiddleware
 ) -> Result<impl IntoResponse, HttpError> {
 
     let filtered_user = FilterUserDto::filter_user(&user.user);
@@ -75,9 +80,70 @@ pub async fn get_users(
     Ok(Json(response))
 }
 
+/// Creates accounts from another system's user table, keeping their
+/// bcrypt, PBKDF2, scrypt or Argon2 hashes as they are. Legacy hashes are
+/// upgraded to Argon2 the first time each user logs in. Hashes with a cost
+/// above the limits in `utils::password` are refused.
+pub async fn import_users(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(mut body): Json<ImportUsersDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    // Exports often differ in case or carry stray whitespace; stored emails
+    // are lowercase like those of provisioned accounts.
+    for user in &mut body.users {
+        user.email = user.email.trim().to_lowercase();
+    }
+
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let unsupported: Vec<String> = body.users
+        .iter()
+        .filter(|user| HashScheme::detect(&user.password_hash).is_none())
+        .map(|user| format!("{}: {}", user.email, ErrorMessage::InvalidHashFormat))
+        .collect();
+
+    if !unsupported.is_empty() {
+        return Err(HttpError::bad_request(ErrorMessage::InvalidHashFormat.to_string())
+            .with_errors(unsupported));
+    }
+
+    let names: Vec<String> = body.users.iter().map(|user| user.name.clone()).collect();
+    let emails: Vec<String> = body.users.iter().map(|user| user.email.clone()).collect();
+    let password_hashes: Vec<String> = body.users.iter().map(|user| user.password_hash.clone()).collect();
+    let verified: Vec<bool> = body.users.iter().map(|user| user.verified).collect();
+
+    let imported = app_state.db_client
+        .import_users(&names, &emails, &password_hashes, &verified)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Each imported email accounts for one row, so a repeated row is
+    // reported as skipped too.
+    let mut unmatched = imported.clone();
+    let skipped = emails
+        .into_iter()
+        .filter(|email| match unmatched.iter().position(|imported| imported == email) {
+            Some(index) => {
+                unmatched.swap_remove(index);
+                false
+            },
+            None => true,
+        })
+        .collect();
+
+    let response = ImportUsersResponseDto {
+        status: "success".to_string(),
+        imported: imported.len(),
+        skipped,
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
 pub async fn update_user_name(
     Extension(app_state): Extension<Arc<AppState>>,
-    Extension(user): Extension<JWTAuthMiddleware>,
+    user: JWTAuthMiddleware,
     Json(body): Json<NameUpdateDto>,
 ) -> Result<impl IntoResponse, HttpError> {
     body.validate()
@@ -88,7 +154,7 @@ pub async fn update_user_name(
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
     let result = app_state.db_client.
-        update_user_name(user_id.clone(), &body.name)
+        update_user_name(user_id, &body.name)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -106,7 +172,7 @@ pub async fn update_user_name(
 
 pub async fn update_user_role(
     Extension(app_state): Extension<Arc<AppState>>,
-    Extension(user): Extension<JWTAuthMiddleware>,
+    user: JWTAuthMiddleware,
     Json(body): Json<RoleUpdateDto>,
 ) -> Result<impl IntoResponse, HttpError> {
     body.validate()
@@ -117,7 +183,7 @@ pub async fn update_user_role(
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
     let result = app_state.db_client
-        .update_user_role(user_id.clone(), body.role)
+        .update_user_role(user_id, body.role)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -135,7 +201,7 @@ pub async fn update_user_role(
 
 pub async fn update_user_password(
     Extension(app_state): Extension<Arc<AppState>>,
-    Extension(user): Extension<JWTAuthMiddleware>,
+    user: JWTAuthMiddleware,
     Json(body): Json<UserPasswordUpdateDto>,
 ) -> Result<impl IntoResponse, HttpError> {
     body.validate()
@@ -146,24 +212,33 @@ pub async fn update_user_password(
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
     let result = app_state.db_client
-        .get_user(Some(user_id.clone()), None, None, None)
+        .get_user(Some(user_id), None, None)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
     let user = result.ok_or(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
 
-    let password_match = password::compare(&body.old_password, &user.password)
+    let password_match = app_state.password_hashing.compare(&body.old_password, &user.password)
             .map_err(|e| HttpError::server_error(e.to_string()))?;
 
     if !password_match {
         return Err(HttpError::bad_request("Old password is incorrect".to_string()));
     }
 
-    let hash_password = password::hash_password(&body.new_password)
+    app_state.password_policy.check(&body.new_password, &[&user.name, &user.email])?;
+
+    let history = app_state.db_client
+        .get_password_history(user.id, app_state.password_policy.history_size)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    app_state.password_policy.check_reuse(&body.new_password, &user.password, &history, &app_state.password_hashing)?;
+
+    let hash_password = app_state.password_hashing.hash_password(&body.new_password)
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
     app_state.db_client
-        .update_user_password(user_id.clone(), hash_password)
+        .update_user_password(user_id, hash_password, app_state.password_policy.history_size)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -174,4 +249,250 @@ pub async fn update_user_password(
 
     Ok(Json(response))
 
-}
\ No newline at end of file
+}
+
+pub async fn get_sessions(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+) -> Result<impl IntoResponse, HttpError> {
+    let sessions = app_state.db_client
+        .get_user_sessions(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = SessionListResponseDto {
+        status: "success".to_string(),
+        sessions: FilterSessionDto::filter_sessions(&sessions, user.session_id),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn revoke_session(
+    Extension(app_state): Extension<Arc<AppState>>,
+    user: JWTAuthMiddleware,
+    Path(session_id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, HttpError> {
+    let revoked = app_state.db_client
+        .revoke_session(user.user.id, session_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !revoked {
+        return Err(HttpError::new("Session not found".to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Session revoked successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use crate::{
+        config::Config,
+        database::UserExt,
+        error::ErrorMessage,
+        models::UserRole,
+        test_support::{self, body_json, json_request, send},
+        AppState
+    };
+
+    const FIRST: &str = "violet lantern orbit";
+    const SECOND: &str = "copper meadow signal";
+    const THIRD: &str = "harbor quartz ember";
+
+    async fn change_password(router: &axum::Router, token: &str, old_password: &str, new_password: &str) -> (StatusCode, Value) {
+        let request = json_request(Method::PUT, "/api/users/password", Some(token), json!({
+            "old_password": old_password,
+            "new_password": new_password,
+            "new_password_confirm": new_password,
+        }));
+        let response = send(router, request).await;
+
+        (response.status(), body_json(response).await)
+    }
+
+    #[sqlx::test]
+    async fn refuses_the_current_and_recent_passwords(pool: PgPool) {
+        let config = Config { password_history_size: 1, ..test_support::config() };
+        let app_state = test_support::app_state(config, pool);
+        let router = test_support::router(app_state.clone());
+
+        test_support::create_user(&app_state, "history@example.com", FIRST).await;
+        let token = test_support::login(&router, "history@example.com", FIRST).await;
+
+        let (status, body) = change_password(&router, &token, FIRST, FIRST).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"], json!([ErrorMessage::PasswordReused.to_string()]));
+
+        assert_eq!(change_password(&router, &token, FIRST, SECOND).await.0, StatusCode::OK);
+
+        let (status, body) = change_password(&router, &token, SECOND, FIRST).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"], json!([ErrorMessage::PasswordReused.to_string()]));
+
+        assert_eq!(change_password(&router, &token, SECOND, THIRD).await.0, StatusCode::OK);
+
+        // Only the most recent previous password is remembered.
+        assert_eq!(change_password(&router, &token, THIRD, FIRST).await.0, StatusCode::OK);
+    }
+
+    async fn admin_token(app_state: &AppState, router: &axum::Router) -> String {
+        let admin = test_support::create_user(app_state, "admin@example.com", FIRST).await;
+        app_state.db_client.update_user_role(admin.id, UserRole::Admin).await.unwrap();
+
+        test_support::login(router, "admin@example.com", FIRST).await
+    }
+
+    fn legacy_hashes(password: &str) -> Vec<(&'static str, String)> {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let salt = SaltString::from_b64("c2FsdHNhbHQ").unwrap();
+
+        let pbkdf2 = pbkdf2::Pbkdf2
+            .hash_password_customized(password.as_bytes(), None, None, pbkdf2::Params { rounds: 1000, output_length: 32 }, &salt)
+            .unwrap()
+            .to_string();
+
+        let scrypt = scrypt::Scrypt
+            .hash_password_customized(password.as_bytes(), None, None, scrypt::Params::new(10, 8, 1, 32).unwrap(), &salt)
+            .unwrap()
+            .to_string();
+
+        let mut derived = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password.as_bytes(), b"salt", 1000, &mut derived);
+        let django = format!("pbkdf2_sha256$1000$salt${}", STANDARD.encode(derived));
+
+        vec![
+            ("bcrypt", bcrypt::hash(password, 4).unwrap()),
+            ("pbkdf2", pbkdf2),
+            ("django", django),
+            ("scrypt", scrypt),
+        ]
+    }
+
+    #[sqlx::test]
+    async fn imports_each_legacy_hash_format_and_upgrades_it_on_login(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let token = admin_token(&app_state, &router).await;
+
+        let hashes = legacy_hashes(SECOND);
+        let users: Vec<Value> = hashes.iter()
+            .map(|(scheme, hash)| json!({
+                "name": scheme,
+                "email": format!("{}@example.com", scheme),
+                "passwordHash": hash,
+                "verified": true,
+            }))
+            .collect();
+
+        let request = json_request(Method::POST, "/api/users/users/import", Some(&token), json!({ "users": users }));
+        let response = send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(body_json(response).await["imported"], hashes.len());
+
+        for (scheme, hash) in hashes {
+            let email = format!("{}@example.com", scheme);
+
+            let body = test_support::login_body(&router, &email, "wrong password").await;
+            assert!(body["token"].is_null(), "{}", scheme);
+
+            let body = test_support::login_body(&router, &email, SECOND).await;
+            assert!(body["token"].is_string(), "{}", scheme);
+
+            let user = app_state.db_client.get_user(None, None, Some(&email)).await.unwrap().unwrap();
+            assert_ne!(user.password, hash);
+            assert!(user.password.starts_with("$argon2id$"), "{}", scheme);
+        }
+    }
+
+    #[sqlx::test]
+    async fn skips_existing_accounts(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let token = admin_token(&app_state, &router).await;
+
+        test_support::create_user(&app_state, "taken@example.com", FIRST).await;
+        let hash = bcrypt::hash(SECOND, 4).unwrap();
+
+        let request = json_request(Method::POST, "/api/users/users/import", Some(&token), json!({ "users": [
+            { "name": "Taken", "email": "taken@example.com", "passwordHash": hash },
+            { "name": "New", "email": "new@example.com", "passwordHash": hash },
+        ] }));
+        let body = body_json(send(&router, request).await).await;
+        assert_eq!(body["imported"], 1);
+        assert_eq!(body["skipped"], json!(["taken@example.com"]));
+
+        // The existing account keeps its own password.
+        assert!(test_support::login_body(&router, "taken@example.com", FIRST).await["token"].is_string());
+        assert!(test_support::login_body(&router, "taken@example.com", SECOND).await["token"].is_null());
+    }
+
+    #[sqlx::test]
+    async fn normalizes_imported_emails(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let token = admin_token(&app_state, &router).await;
+
+        test_support::create_user(&app_state, "taken@example.com", FIRST).await;
+        let hash = bcrypt::hash(SECOND, 4).unwrap();
+
+        let request = json_request(Method::POST, "/api/users/users/import", Some(&token), json!({ "users": [
+            { "name": "Taken", "email": " Taken@Example.com", "passwordHash": hash },
+            { "name": "Mixed", "email": "Mixed@Example.COM ", "passwordHash": hash },
+            { "name": "Again", "email": "mixed@example.com", "passwordHash": hash },
+        ] }));
+        let body = body_json(send(&router, request).await).await;
+        assert_eq!(body["imported"], 1);
+        assert_eq!(body["skipped"], json!(["taken@example.com", "mixed@example.com"]));
+
+        let user = app_state.db_client.get_user(None, None, Some("mixed@example.com")).await.unwrap().unwrap();
+        assert_eq!(user.name, "Mixed");
+        assert!(test_support::login_body(&router, "mixed@example.com", SECOND).await["token"].is_string());
+    }
+
+    #[sqlx::test]
+    async fn refuses_batches_with_malformed_rows(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let token = admin_token(&app_state, &router).await;
+
+        let hash = bcrypt::hash(SECOND, 4).unwrap();
+
+        let request = json_request(Method::POST, "/api/users/users/import", Some(&token), json!({ "users": [
+            { "name": "Valid", "email": "valid@example.com", "passwordHash": hash },
+            { "name": "Md5", "email": "md5@example.com", "passwordHash": "5f4dcc3b5aa765d61d8327deb882cf99" },
+        ] }));
+        let response = send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_json(response).await["errors"],
+            json!([format!("md5@example.com: {}", ErrorMessage::InvalidHashFormat)])
+        );
+
+        let request = json_request(Method::POST, "/api/users/users/import", Some(&token), json!({ "users": [
+            { "name": "Valid", "email": "valid@example.com", "passwordHash": hash },
+            { "name": "Invalid", "email": "not-an-email", "passwordHash": hash },
+        ] }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::BAD_REQUEST);
+
+        assert!(app_state.db_client.get_user(None, None, Some("valid@example.com")).await.unwrap().is_none());
+
+        test_support::create_user(&app_state, "plain@example.com", FIRST).await;
+        let user_token = test_support::login(&router, "plain@example.com", FIRST).await;
+        let request = json_request(Method::POST, "/api/users/users/import", Some(&user_token), json!({ "users": [
+            { "name": "Valid", "email": "valid@example.com", "passwordHash": hash },
+        ] }));
+        assert_eq!(send(&router, request).await.status(), StatusCode::FORBIDDEN);
+    }
+}
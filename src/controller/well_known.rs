@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Extension,
+    response::IntoResponse,
+    Json
+};
+
+use axum::http::StatusCode;
+
+use crate::{
+    dtos::OpenIdConfigurationDto,
+    error::{ErrorMessage, HttpError},
+    utils::oauth,
+    AppState
+};
+
+pub async fn jwks(
+    Extension(app_state): Extension<Arc<AppState>>
+) -> Result<impl IntoResponse, HttpError> {
+    Ok(Json(app_state.key_ring.jwks()))
+}
+
+/// OpenID Connect discovery, with endpoints under `PUBLIC_URL`. `JWT_ISSUER`
+/// should be set to the same URL, since relying parties compare it with the
+/// ID token `iss`. Only served with an asymmetric `JWT_ALGORITHM`, so ID
+/// tokens can be verified from the JWKS.
+pub async fn openid_configuration(
+    Extension(app_state): Extension<Arc<AppState>>
+) -> Result<impl IntoResponse, HttpError> {
+    if !app_state.key_ring.is_asymmetric() {
+        return Err(HttpError::new(ErrorMessage::OpenIdNotConfigured.to_string(), StatusCode::NOT_FOUND));
+    }
+
+    let to_strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+    let algorithm = format!("{:?}", app_state.key_ring.signing_key().algorithm);
+    let endpoint = |path: &str| format!("{}{}", app_state.env.public_url, path);
+
+    let configuration = OpenIdConfigurationDto {
+        issuer: app_state.env.jwt_issuer.to_owned(),
+        authorization_endpoint: endpoint("/api/oauth/authorize"),
+        token_endpoint: endpoint("/api/oauth/token"),
+        userinfo_endpoint: endpoint("/api/oauth/userinfo"),
+        introspection_endpoint: endpoint("/api/oauth/introspect"),
+        revocation_endpoint: endpoint("/api/oauth/revoke"),
+        jwks_uri: endpoint("/.well-known/jwks.json"),
+        scopes_supported: to_strings(&oauth::SUPPORTED_SCOPES),
+        response_types_supported: to_strings(&["code"]),
+        grant_types_supported: to_strings(&["authorization_code", "refresh_token", "client_credentials"]),
+        subject_types_supported: to_strings(&["public"]),
+        id_token_signing_alg_values_supported: vec![algorithm],
+        token_endpoint_auth_methods_supported: to_strings(&["client_secret_basic", "client_secret_post", "none"]),
+        code_challenge_methods_supported: to_strings(&[oauth::PKCE_METHOD]),
+        claims_supported: to_strings(&["sub", "iss", "aud", "exp", "iat", "auth_time", "nonce", "name", "email", "email_verified"]),
+    };
+
+    Ok(Json(configuration))
+}
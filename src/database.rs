@@ -3,7 +3,10 @@ use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 use uuid::Uuid;
 
-use crate::models::{User, UserRole};
+use crate::models::{Identity, OAuthAuthorizationCode, OAuthClient, OAuthConsent, OAuthRefreshToken, OneTimeToken, PersonalAccessToken, RecoveryCode, RefreshToken, ServiceAccount, Session, TokenPurpose, User, UserRole, UserTotp, WebauthnChallenge, WebauthnCredential};
+use crate::rate_limit::{RateLimitDecision, RateLimitPolicy, RateLimitStore};
+use crate::utils::revocation::RevocationStore;
+use crate::utils::throttle::{AttemptStore, AttemptWindow, Lockout, StoreError};
 
 #[derive(Debug, Clone)]
 pub struct DBClient {
@@ -23,7 +26,6 @@ pub trait UserExt {
         user_id: Option<Uuid>,
         name: Option<&str>,
         email: Option<&str>,
-        token: Option<&str>,
     ) -> Result<Option<User>, sqlx::Error>;
 
     async fn get_users(
@@ -37,12 +39,18 @@ pub trait UserExt {
         name: T,
         email: T,
         password: T,
-        verification_token: T,
-        token_expires_at: DateTime<Utc>,
     ) -> Result<User, sqlx::Error>;
 
     async fn get_user_count(&self) -> Result<i64, sqlx::Error>;
 
+    async fn import_users(
+        &self,
+        names: &[String],
+        emails: &[String],
+        password_hashes: &[String],
+        verified: &[bool],
+    ) -> Result<Vec<String>, sqlx::Error>;
+
     async fn update_user_name<T: Into<String> + Send>(
         &self,
         user_id: Uuid,
@@ -59,18 +67,19 @@ pub trait UserExt {
         &self,
         user_id: Uuid,
         password: String,
+        history_size: i64,
     ) -> Result<User, sqlx::Error>;
 
-    async fn verifed_token(
+    async fn rehash_user_password(
         &self,
-        token: &str,
+        user_id: Uuid,
+        old_password: &str,
+        new_password: &str,
     ) -> Result<(), sqlx::Error>;
 
-    async fn add_verifed_token(
+    async fn verify_user(
         &self,
         user_id: Uuid,
-        token: &str,
-        expires_at: DateTime<Utc>,
     ) -> Result<(), sqlx::Error>;
 }
 
@@ -81,39 +90,27 @@ impl UserExt for DBClient {
         user_id: Option<Uuid>,
         name: Option<&str>,
         email: Option<&str>,
-        token: Option<&str>,
     ) -> Result<Option<User>, sqlx::Error> {
         let mut user: Option<User> = None;
 
         if let Some(user_id) = user_id {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users WHERE id = $1"#,
+                r#"SELECT id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole" FROM users WHERE id = $1"#,
                 user_id
             ).fetch_optional(&self.pool).await?;
         } else if let Some(name) = name {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users WHERE name = $1"#,
+                r#"SELECT id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole" FROM users WHERE name = $1"#,
                 name
             ).fetch_optional(&self.pool).await?;
         } else if let Some(email) = email {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users WHERE email = $1"#,
+                r#"SELECT id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole" FROM users WHERE email = $1"#,
                 email
             ).fetch_optional(&self.pool).await?;
-        } else if let Some(token) = token {
-            user = sqlx::query_as!(
-                User,
-                r#"
-                SELECT id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" 
-                FROM users 
-                WHERE verification_token = $1"#,
-                token
-            )
-            .fetch_optional(&self.pool)
-            .await?;
         }
 
         Ok(user)
@@ -128,7 +125,8 @@ impl UserExt for DBClient {
 
         let users = sqlx::query_as!(
             User,
-            r#"SELECT id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users 
+            r#"SELECT id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole" FROM users 
+            WHERE service_account = false
             ORDER BY created_at DESC LIMIT $1 OFFSET $2"#,
             limit as i64,
             offset as i64,
@@ -143,29 +141,51 @@ impl UserExt for DBClient {
         name: T,
         email: T,
         password: T,
-        verification_token: T,
-        token_expires_at: DateTime<Utc>,
     ) -> Result<User, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
             r#"
-            INSERT INTO users (name, email, password,verification_token, token_expires_at) 
-            VALUES ($1, $2, $3, $4, $5) 
-            RETURNING id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            INSERT INTO users (name, email, password) 
+            VALUES ($1, $2, $3) 
+            RETURNING id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole"
             "#,
             name.into(),
             email.into(),
-            password.into(),
-            verification_token.into(),
-            token_expires_at
+            password.into()
         ).fetch_one(&self.pool)
         .await?;
         Ok(user)
     }
 
+    /// Inserts users with existing password hashes, skipping emails that are
+    /// already taken, and returns the emails that were inserted.
+    async fn import_users(
+        &self,
+        names: &[String],
+        emails: &[String],
+        password_hashes: &[String],
+        verified: &[bool],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let imported = sqlx::query_scalar!(
+            r#"
+            INSERT INTO users (name, email, password, verified)
+            SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::VARCHAR[], $4::BOOLEAN[])
+            ON CONFLICT (email) DO NOTHING
+            RETURNING email
+            "#,
+            names,
+            emails,
+            password_hashes,
+            verified
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(imported)
+    }
+
     async fn get_user_count(&self) -> Result<i64, sqlx::Error> {
         let count = sqlx::query_scalar!(
-            r#"SELECT COUNT(*) FROM users"#
+            r#"SELECT COUNT(*) FROM users WHERE service_account = false"#
         )
        .fetch_one(&self.pool)
        .await?;
@@ -184,7 +204,7 @@ impl UserExt for DBClient {
             UPDATE users
             SET name = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole"
             "#,
             new_name.into(),
             user_id
@@ -205,7 +225,7 @@ impl UserExt for DBClient {
             UPDATE users
             SET role = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole"
             "#,
             new_role as UserRole,
             user_id
@@ -215,65 +235,2131 @@ impl UserExt for DBClient {
         Ok(user)
     }
 
+    /// Replaces the user's password, moving the old hash into
+    /// `password_history` and keeping only the newest `history_size` entries.
     async fn update_user_password(
         &self,
         user_id: Uuid,
-        new_password: String
+        new_password: String,
+        history_size: i64,
     ) -> Result<User, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let old_password = sqlx::query_scalar!(
+            r#"
+            SELECT password FROM users
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            user_id
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        if history_size > 0 {
+            sqlx::query!(
+                r#"
+                INSERT INTO password_history (user_id, password_hash)
+                VALUES ($1, $2)
+                "#,
+                user_id,
+                old_password
+            ).execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM password_history
+            WHERE user_id = $1 AND id NOT IN (
+                SELECT id FROM password_history
+                WHERE user_id = $1
+                ORDER BY created_at DESC
+                LIMIT $2
+            )
+            "#,
+            user_id,
+            history_size.max(0)
+        ).execute(&mut *tx)
+        .await?;
+
         let user = sqlx::query_as!(
             User,
             r#"
             UPDATE users
             SET password = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, name, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, name, email, password, verified, service_account, created_at, updated_at, role as "role: UserRole"
             "#,
             new_password,
             user_id
-        ).fetch_one(&self.pool)
+        ).fetch_one(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
         Ok(user)
     }
 
-    async fn verifed_token(
+    /// Swaps in a hash of the same password made with current settings,
+    /// unless the password changed in the meantime.
+    async fn rehash_user_password(
         &self,
-        token: &str,
+        user_id: Uuid,
+        old_password: &str,
+        new_password: &str,
     ) -> Result<(), sqlx::Error> {
-        let _ =sqlx::query!(
+        sqlx::query!(
             r#"
             UPDATE users
-            SET verified = true, 
-                updated_at = Now(),
-                verification_token = NULL,
-                token_expires_at = NULL
-            WHERE verification_token = $1
+            SET password = $1
+            WHERE id = $2 AND password = $3
             "#,
-            token
+            new_password,
+            user_id,
+            old_password
         ).execute(&self.pool)
-       .await;
+        .await?;
 
         Ok(())
     }
 
-    async fn add_verifed_token(
+    async fn verify_user(
         &self,
         user_id: Uuid,
-        token: &str,
-        token_expires_at: DateTime<Utc>,
     ) -> Result<(), sqlx::Error> {
-        let _ = sqlx::query!(
+        sqlx::query!(
             r#"
             UPDATE users
-            SET verification_token = $1, token_expires_at = $2, updated_at = Now()
-            WHERE id = $3
+            SET verified = true, updated_at = Now()
+            WHERE id = $1
             "#,
-            token,
-            token_expires_at,
-            user_id,
+            user_id
         ).execute(&self.pool)
-       .await?;
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait RefreshTokenExt {
+    async fn save_refresh_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<RefreshToken, sqlx::Error>;
+
+    async fn get_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, sqlx::Error>;
+
+    async fn rotate_refresh_token(
+        &self,
+        current_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<RefreshToken>, sqlx::Error>;
+}
+
+#[async_trait]
+impl RefreshTokenExt for DBClient {
+    async fn save_refresh_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<RefreshToken, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, family_id, token_hash, expires_at, revoked_at, replaced_by, created_at
+            "#,
+            user_id,
+            family_id,
+            token_hash,
+            expires_at
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(refresh_token)
+    }
+
+    async fn get_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            SELECT id, user_id, family_id, token_hash, expires_at, revoked_at, replaced_by, created_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            "#,
+            token_hash
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(refresh_token)
+    }
+
+    /// Revokes `current_id` and issues its successor in the same family.
+    /// Returns `None` if the current token was already revoked, which means
+    /// it lost a race with another rotation and must be treated as reuse.
+    async fn rotate_refresh_token(
+        &self,
+        current_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<RefreshToken>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let current = sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = Now()
+            WHERE id = $1 AND revoked_at IS NULL
+            RETURNING user_id, family_id
+            "#,
+            current_id
+        ).fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(current) = current else {
+            tx.rollback().await?;
+            return Ok(None);
+        };
+
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, family_id, token_hash, expires_at, revoked_at, replaced_by, created_at
+            "#,
+            current.user_id,
+            current.family_id,
+            token_hash,
+            expires_at
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET replaced_by = $1
+            WHERE id = $2
+            "#,
+            refresh_token.id,
+            current_id
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(refresh_token))
+    }
+}
+
+#[async_trait]
+pub trait SessionExt {
+    async fn save_session(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Session, sqlx::Error>;
+
+    async fn touch_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, sqlx::Error>;
+
+    async fn get_active_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, sqlx::Error>;
+
+    async fn get_user_sessions(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<Session>, sqlx::Error>;
+
+    async fn revoke_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn revoke_user_sessions(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl SessionExt for DBClient {
+    async fn save_session(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Session, sqlx::Error> {
+        let session = sqlx::query_as!(
+            Session,
+            r#"
+            INSERT INTO sessions (user_id, user_agent, ip_address, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, user_agent, ip_address, expires_at, revoked_at, last_seen_at, created_at
+            "#,
+            user_id,
+            user_agent,
+            ip_address,
+            expires_at
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Marks an active session as seen and returns it, or `None` if the
+    /// session is revoked, expired or unknown.
+    async fn touch_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, sqlx::Error> {
+        let session = sqlx::query_as!(
+            Session,
+            r#"
+            UPDATE sessions
+            SET last_seen_at = Now()
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > Now()
+            RETURNING id, user_id, user_agent, ip_address, expires_at, revoked_at, last_seen_at, created_at
+            "#,
+            session_id
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Returns an active session without marking it as seen.
+    async fn get_active_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, sqlx::Error> {
+        let session = sqlx::query_as!(
+            Session,
+            r#"
+            SELECT id, user_id, user_agent, ip_address, expires_at, revoked_at, last_seen_at, created_at
+            FROM sessions
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > Now()
+            "#,
+            session_id
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    async fn get_user_sessions(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<Session>, sqlx::Error> {
+        let sessions = sqlx::query_as!(
+            Session,
+            r#"
+            SELECT id, user_id, user_agent, ip_address, expires_at, revoked_at, last_seen_at, created_at
+            FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > Now()
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Revokes a single session together with its refresh token family.
+    /// Returns `false` if the session does not belong to `user_id` or is
+    /// already revoked.
+    async fn revoke_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET revoked_at = Now()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+            session_id,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = Now()
+            WHERE family_id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+            session_id,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_user_sessions(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET revoked_at = Now()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = Now()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait TwoFactorExt {
+    async fn get_totp(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<UserTotp>, sqlx::Error>;
+
+    async fn save_totp_secret(
+        &self,
+        user_id: Uuid,
+        secret: &str,
+    ) -> Result<UserTotp, sqlx::Error>;
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error>;
+
+    async fn update_totp_step(
+        &self,
+        user_id: Uuid,
+        step: i64,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn disable_totp(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_unused_recovery_codes(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<RecoveryCode>, sqlx::Error>;
+
+    async fn use_recovery_code(
+        &self,
+        recovery_code_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl TwoFactorExt for DBClient {
+    async fn get_totp(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<UserTotp>, sqlx::Error> {
+        let totp = sqlx::query_as!(
+            UserTotp,
+            r#"
+            SELECT user_id, secret, enabled, last_used_step, created_at, updated_at
+            FROM user_totp
+            WHERE user_id = $1
+            "#,
+            user_id
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(totp)
+    }
+
+    async fn save_totp_secret(
+        &self,
+        user_id: Uuid,
+        secret: &str,
+    ) -> Result<UserTotp, sqlx::Error> {
+        let totp = sqlx::query_as!(
+            UserTotp,
+            r#"
+            INSERT INTO user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = $2, enabled = false, last_used_step = NULL, updated_at = Now()
+            RETURNING user_id, secret, enabled, last_used_step, created_at, updated_at
+            "#,
+            user_id,
+            secret
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(totp)
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET enabled = true, last_used_step = $2, updated_at = Now()
+            WHERE user_id = $1
+            "#,
+            user_id,
+            step
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO recovery_codes (user_id, code_hash)
+            SELECT $1, code_hash FROM UNNEST($2::text[]) AS code_hash
+            "#,
+            user_id,
+            recovery_code_hashes
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Records `step` as the last accepted TOTP step. Returns `false` if a
+    /// code from the same or a later step was already used.
+    async fn update_totp_step(
+        &self,
+        user_id: Uuid,
+        step: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET last_used_step = $2, updated_at = Now()
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+            user_id,
+            step
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn disable_totp(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"DELETE FROM user_totp WHERE user_id = $1"#,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_unused_recovery_codes(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<RecoveryCode>, sqlx::Error> {
+        let recovery_codes = sqlx::query_as!(
+            RecoveryCode,
+            r#"
+            SELECT id, user_id, code_hash, used_at, created_at
+            FROM recovery_codes
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user_id
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(recovery_codes)
+    }
+
+    async fn use_recovery_code(
+        &self,
+        recovery_code_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE recovery_codes
+            SET used_at = Now()
+            WHERE id = $1 AND used_at IS NULL
+            "#,
+            recovery_code_id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+            user_id
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO recovery_codes (user_id, code_hash)
+            SELECT $1, code_hash FROM UNNEST($2::text[]) AS code_hash
+            "#,
+            user_id,
+            recovery_code_hashes
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait WebauthnExt {
+    async fn save_webauthn_challenge(
+        &self,
+        user_id: Option<Uuid>,
+        challenge: &str,
+        ceremony: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<WebauthnChallenge, sqlx::Error>;
+
+    async fn take_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        ceremony: &str,
+    ) -> Result<Option<WebauthnChallenge>, sqlx::Error>;
+
+    async fn save_webauthn_credential(
+        &self,
+        user_id: Uuid,
+        credential_id: &str,
+        public_key: &[u8],
+        sign_count: i64,
+        name: &str,
+    ) -> Result<WebauthnCredential, sqlx::Error>;
+
+    async fn get_webauthn_credential(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<WebauthnCredential>, sqlx::Error>;
+
+    async fn get_user_webauthn_credentials(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<WebauthnCredential>, sqlx::Error>;
+
+    async fn update_webauthn_sign_count(
+        &self,
+        id: Uuid,
+        sign_count: i64,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn delete_webauthn_credential(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn delete_expired_webauthn_challenges(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl WebauthnExt for DBClient {
+    async fn save_webauthn_challenge(
+        &self,
+        user_id: Option<Uuid>,
+        challenge: &str,
+        ceremony: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<WebauthnChallenge, sqlx::Error> {
+        let webauthn_challenge = sqlx::query_as!(
+            WebauthnChallenge,
+            r#"
+            INSERT INTO webauthn_challenges (user_id, challenge, ceremony, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, challenge, ceremony, expires_at, created_at
+            "#,
+            user_id,
+            challenge,
+            ceremony,
+            expires_at
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(webauthn_challenge)
+    }
+
+    /// Deletes and returns an unexpired challenge so it can only be answered once.
+    async fn take_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        ceremony: &str,
+    ) -> Result<Option<WebauthnChallenge>, sqlx::Error> {
+        let webauthn_challenge = sqlx::query_as!(
+            WebauthnChallenge,
+            r#"
+            DELETE FROM webauthn_challenges
+            WHERE id = $1 AND ceremony = $2 AND expires_at > Now()
+            RETURNING id, user_id, challenge, ceremony, expires_at, created_at
+            "#,
+            challenge_id,
+            ceremony
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(webauthn_challenge)
+    }
+
+    async fn save_webauthn_credential(
+        &self,
+        user_id: Uuid,
+        credential_id: &str,
+        public_key: &[u8],
+        sign_count: i64,
+        name: &str,
+    ) -> Result<WebauthnCredential, sqlx::Error> {
+        let credential = sqlx::query_as!(
+            WebauthnCredential,
+            r#"
+            INSERT INTO webauthn_credentials (user_id, credential_id, public_key, sign_count, name)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, credential_id, public_key, sign_count, name, last_used_at, created_at
+            "#,
+            user_id,
+            credential_id,
+            public_key,
+            sign_count,
+            name
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(credential)
+    }
+
+    async fn get_webauthn_credential(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<WebauthnCredential>, sqlx::Error> {
+        let credential = sqlx::query_as!(
+            WebauthnCredential,
+            r#"
+            SELECT id, user_id, credential_id, public_key, sign_count, name, last_used_at, created_at
+            FROM webauthn_credentials
+            WHERE credential_id = $1
+            "#,
+            credential_id
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(credential)
+    }
+
+    async fn get_user_webauthn_credentials(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<WebauthnCredential>, sqlx::Error> {
+        let credentials = sqlx::query_as!(
+            WebauthnCredential,
+            r#"
+            SELECT id, user_id, credential_id, public_key, sign_count, name, last_used_at, created_at
+            FROM webauthn_credentials
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(credentials)
+    }
+
+    /// Saves a counter only if it is higher than the stored one, so two
+    /// logins racing with the same counter can't both succeed. Counters of
+    /// authenticators that don't implement one stay at zero. Returns whether
+    /// the credential was updated.
+    async fn update_webauthn_sign_count(
+        &self,
+        id: Uuid,
+        sign_count: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE webauthn_credentials
+            SET sign_count = $1, last_used_at = Now()
+            WHERE id = $2 AND (sign_count < $1 OR $1 = 0)
+            "#,
+            sign_count,
+            id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_webauthn_credential(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM webauthn_credentials
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_expired_webauthn_challenges(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM webauthn_challenges
+            WHERE expires_at <= Now()
+            "#
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+pub trait IdentityExt {
+    async fn get_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Identity>, sqlx::Error>;
+
+    async fn get_user_identities(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<Identity>, sqlx::Error>;
+
+    async fn save_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<Identity, sqlx::Error>;
+
+    async fn touch_identity(
+        &self,
+        id: Uuid,
+        email: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn delete_identity(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl IdentityExt for DBClient {
+    async fn get_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Identity>, sqlx::Error> {
+        let identity = sqlx::query_as!(
+            Identity,
+            r#"
+            SELECT id, user_id, provider, subject, email, last_used_at, created_at
+            FROM identities
+            WHERE provider = $1 AND subject = $2
+            "#,
+            provider,
+            subject
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(identity)
+    }
+
+    async fn get_user_identities(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<Identity>, sqlx::Error> {
+        let identities = sqlx::query_as!(
+            Identity,
+            r#"
+            SELECT id, user_id, provider, subject, email, last_used_at, created_at
+            FROM identities
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(identities)
+    }
+
+    async fn save_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<Identity, sqlx::Error> {
+        let identity = sqlx::query_as!(
+            Identity,
+            r#"
+            INSERT INTO identities (user_id, provider, subject, email, last_used_at)
+            VALUES ($1, $2, $3, $4, Now())
+            RETURNING id, user_id, provider, subject, email, last_used_at, created_at
+            "#,
+            user_id,
+            provider,
+            subject,
+            email
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(identity)
+    }
+
+    async fn touch_identity(
+        &self,
+        id: Uuid,
+        email: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE identities
+            SET email = COALESCE($1, email), last_used_at = Now()
+            WHERE id = $2
+            "#,
+            email,
+            id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_identity(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM identities
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+pub trait AccessTokenExt {
+    async fn save_access_token(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        token_prefix: &str,
+        token_hash: &str,
+        scopes: &[String],
+        expires_at: DateTime<Utc>,
+    ) -> Result<PersonalAccessToken, sqlx::Error>;
+
+    async fn get_user_access_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, sqlx::Error>;
+
+    async fn touch_access_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PersonalAccessToken>, sqlx::Error>;
+
+    async fn get_active_access_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PersonalAccessToken>, sqlx::Error>;
+
+    async fn revoke_access_token(
+        &self,
+        user_id: Uuid,
+        token_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl AccessTokenExt for DBClient {
+    async fn save_access_token(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        token_prefix: &str,
+        token_hash: &str,
+        scopes: &[String],
+        expires_at: DateTime<Utc>,
+    ) -> Result<PersonalAccessToken, sqlx::Error> {
+        let access_token = sqlx::query_as!(
+            PersonalAccessToken,
+            r#"
+            INSERT INTO personal_access_tokens (user_id, name, token_prefix, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, name, token_prefix, token_hash, scopes, expires_at, revoked_at, last_used_at, created_at
+            "#,
+            user_id,
+            name,
+            token_prefix,
+            token_hash,
+            scopes,
+            expires_at
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(access_token)
+    }
+
+    /// Returns the user's tokens that are neither revoked nor expired.
+    async fn get_user_access_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, sqlx::Error> {
+        let access_tokens = sqlx::query_as!(
+            PersonalAccessToken,
+            r#"
+            SELECT id, user_id, name, token_prefix, token_hash, scopes, expires_at, revoked_at, last_used_at, created_at
+            FROM personal_access_tokens
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > Now()
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(access_tokens)
+    }
+
+    /// Records a use of a valid token and returns it.
+    async fn touch_access_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PersonalAccessToken>, sqlx::Error> {
+        let access_token = sqlx::query_as!(
+            PersonalAccessToken,
+            r#"
+            UPDATE personal_access_tokens
+            SET last_used_at = Now()
+            WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > Now()
+            RETURNING id, user_id, name, token_prefix, token_hash, scopes, expires_at, revoked_at, last_used_at, created_at
+            "#,
+            token_hash
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(access_token)
+    }
+
+    /// Returns a valid token without recording a use.
+    async fn get_active_access_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PersonalAccessToken>, sqlx::Error> {
+        let access_token = sqlx::query_as!(
+            PersonalAccessToken,
+            r#"
+            SELECT id, user_id, name, token_prefix, token_hash, scopes, expires_at, revoked_at, last_used_at, created_at
+            FROM personal_access_tokens
+            WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > Now()
+            "#,
+            token_hash
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(access_token)
+    }
+
+    async fn revoke_access_token(
+        &self,
+        user_id: Uuid,
+        token_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE personal_access_tokens
+            SET revoked_at = Now()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+            token_id,
+            user_id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+pub trait OAuthExt {
+    async fn save_oauth_client(
+        &self,
+        client_id: &str,
+        client_secret_hash: Option<&str>,
+        name: &str,
+        redirect_uris: &[String],
+        scopes: &[String],
+        owner_id: Uuid,
+    ) -> Result<OAuthClient, sqlx::Error>;
+
+    async fn get_oauth_client(
+        &self,
+        client_id: &str,
+    ) -> Result<Option<OAuthClient>, sqlx::Error>;
+
+    async fn get_oauth_clients(&self) -> Result<Vec<OAuthClient>, sqlx::Error>;
+
+    async fn delete_oauth_client(
+        &self,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn get_oauth_consent(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+    ) -> Result<Option<OAuthConsent>, sqlx::Error>;
+
+    async fn save_oauth_consent(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+        scopes: &[String],
+    ) -> Result<OAuthConsent, sqlx::Error>;
+
+    async fn save_authorization_code(
+        &self,
+        code: &OAuthAuthorizationCode,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<OAuthAuthorizationCode>, sqlx::Error>;
+
+    async fn consume_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<OAuthAuthorizationCode>, sqlx::Error>;
+
+    async fn save_oauth_refresh_token(
+        &self,
+        client_id: Uuid,
+        user_id: Uuid,
+        grant_id: Uuid,
+        token_hash: &str,
+        scope: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<OAuthRefreshToken, sqlx::Error>;
+
+    async fn get_oauth_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<OAuthRefreshToken>, sqlx::Error>;
+
+    async fn rotate_oauth_refresh_token(
+        &self,
+        current_id: Uuid,
+        token_hash: &str,
+        scope: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<OAuthRefreshToken>, sqlx::Error>;
+
+    async fn revoke_oauth_grant(
+        &self,
+        grant_id: Uuid,
+    ) -> Result<u64, sqlx::Error>;
+
+    async fn is_oauth_grant_active(
+        &self,
+        grant_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn delete_stale_authorization_codes(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl OAuthExt for DBClient {
+    async fn save_oauth_client(
+        &self,
+        client_id: &str,
+        client_secret_hash: Option<&str>,
+        name: &str,
+        redirect_uris: &[String],
+        scopes: &[String],
+        owner_id: Uuid,
+    ) -> Result<OAuthClient, sqlx::Error> {
+        let client = sqlx::query_as!(
+            OAuthClient,
+            r#"
+            INSERT INTO oauth_clients (client_id, client_secret_hash, name, redirect_uris, scopes, owner_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, client_id, client_secret_hash, name, redirect_uris, scopes, owner_id, service_account_id, created_at
+            "#,
+            client_id,
+            client_secret_hash,
+            name,
+            redirect_uris,
+            scopes,
+            owner_id
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(client)
+    }
+
+    async fn get_oauth_client(
+        &self,
+        client_id: &str,
+    ) -> Result<Option<OAuthClient>, sqlx::Error> {
+        let client = sqlx::query_as!(
+            OAuthClient,
+            r#"
+            SELECT id, client_id, client_secret_hash, name, redirect_uris, scopes, owner_id, service_account_id, created_at
+            FROM oauth_clients
+            WHERE client_id = $1
+            "#,
+            client_id
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(client)
+    }
+
+    async fn get_oauth_clients(&self) -> Result<Vec<OAuthClient>, sqlx::Error> {
+        let clients = sqlx::query_as!(
+            OAuthClient,
+            r#"
+            SELECT id, client_id, client_secret_hash, name, redirect_uris, scopes, owner_id, service_account_id, created_at
+            FROM oauth_clients
+            WHERE service_account_id IS NULL
+            ORDER BY created_at DESC
+            "#
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(clients)
+    }
+
+    async fn delete_oauth_client(
+        &self,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM oauth_clients
+            WHERE id = $1 AND service_account_id IS NULL
+            "#,
+            id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_oauth_consent(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+    ) -> Result<Option<OAuthConsent>, sqlx::Error> {
+        let consent = sqlx::query_as!(
+            OAuthConsent,
+            r#"
+            SELECT user_id, client_id, scopes, created_at, updated_at
+            FROM oauth_consents
+            WHERE user_id = $1 AND client_id = $2
+            "#,
+            user_id,
+            client_id
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(consent)
+    }
+
+    /// Records consent to `scopes`, adding to anything granted before.
+    async fn save_oauth_consent(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+        scopes: &[String],
+    ) -> Result<OAuthConsent, sqlx::Error> {
+        let consent = sqlx::query_as!(
+            OAuthConsent,
+            r#"
+            INSERT INTO oauth_consents (user_id, client_id, scopes)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, client_id) DO UPDATE
+            SET scopes = ARRAY(SELECT DISTINCT UNNEST(oauth_consents.scopes || EXCLUDED.scopes)),
+                updated_at = Now()
+            RETURNING user_id, client_id, scopes as "scopes!", created_at, updated_at
+            "#,
+            user_id,
+            client_id,
+            scopes
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(consent)
+    }
+
+    async fn save_authorization_code(
+        &self,
+        code: &OAuthAuthorizationCode,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO oauth_authorization_codes
+                (id, code_hash, client_id, user_id, grant_id, redirect_uri, scope, code_challenge, code_challenge_method, nonce, auth_time, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+            code.id,
+            code.code_hash,
+            code.client_id,
+            code.user_id,
+            code.grant_id,
+            code.redirect_uri,
+            code.scope,
+            code.code_challenge,
+            code.code_challenge_method,
+            code.nonce,
+            code.auth_time,
+            code.expires_at
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<OAuthAuthorizationCode>, sqlx::Error> {
+        let code = sqlx::query_as!(
+            OAuthAuthorizationCode,
+            r#"
+            SELECT id, code_hash, client_id, user_id, grant_id, redirect_uri, scope, code_challenge, code_challenge_method, nonce, auth_time, expires_at, used_at, created_at
+            FROM oauth_authorization_codes
+            WHERE code_hash = $1
+            "#,
+            code_hash
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(code)
+    }
+
+    /// Marks an unused, unexpired code as used and returns it.
+    async fn consume_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<OAuthAuthorizationCode>, sqlx::Error> {
+        let code = sqlx::query_as!(
+            OAuthAuthorizationCode,
+            r#"
+            UPDATE oauth_authorization_codes
+            SET used_at = Now()
+            WHERE code_hash = $1 AND used_at IS NULL AND expires_at > Now()
+            RETURNING id, code_hash, client_id, user_id, grant_id, redirect_uri, scope, code_challenge, code_challenge_method, nonce, auth_time, expires_at, used_at, created_at
+            "#,
+            code_hash
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(code)
+    }
+
+    async fn save_oauth_refresh_token(
+        &self,
+        client_id: Uuid,
+        user_id: Uuid,
+        grant_id: Uuid,
+        token_hash: &str,
+        scope: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<OAuthRefreshToken, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            OAuthRefreshToken,
+            r#"
+            INSERT INTO oauth_refresh_tokens (client_id, user_id, grant_id, token_hash, scope, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, client_id, user_id, grant_id, token_hash, scope, expires_at, revoked_at, replaced_by, created_at
+            "#,
+            client_id,
+            user_id,
+            grant_id,
+            token_hash,
+            scope,
+            expires_at
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(refresh_token)
+    }
+
+    async fn get_oauth_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<OAuthRefreshToken>, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            OAuthRefreshToken,
+            r#"
+            SELECT id, client_id, user_id, grant_id, token_hash, scope, expires_at, revoked_at, replaced_by, created_at
+            FROM oauth_refresh_tokens
+            WHERE token_hash = $1
+            "#,
+            token_hash
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(refresh_token)
+    }
+
+    /// Revokes `current_id` and issues its successor in the same grant.
+    /// Returns `None` if the current token was already revoked.
+    async fn rotate_oauth_refresh_token(
+        &self,
+        current_id: Uuid,
+        token_hash: &str,
+        scope: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<OAuthRefreshToken>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let current = sqlx::query!(
+            r#"
+            UPDATE oauth_refresh_tokens
+            SET revoked_at = Now()
+            WHERE id = $1 AND revoked_at IS NULL
+            RETURNING client_id, user_id, grant_id
+            "#,
+            current_id
+        ).fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(current) = current else {
+            tx.rollback().await?;
+            return Ok(None);
+        };
+
+        let refresh_token = sqlx::query_as!(
+            OAuthRefreshToken,
+            r#"
+            INSERT INTO oauth_refresh_tokens (client_id, user_id, grant_id, token_hash, scope, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, client_id, user_id, grant_id, token_hash, scope, expires_at, revoked_at, replaced_by, created_at
+            "#,
+            current.client_id,
+            current.user_id,
+            current.grant_id,
+            token_hash,
+            scope,
+            expires_at
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE oauth_refresh_tokens
+            SET replaced_by = $1
+            WHERE id = $2
+            "#,
+            refresh_token.id,
+            current_id
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(refresh_token))
+    }
+
+    /// Revokes every refresh token issued under a grant.
+    async fn revoke_oauth_grant(
+        &self,
+        grant_id: Uuid,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE oauth_refresh_tokens
+            SET revoked_at = Now()
+            WHERE grant_id = $1 AND revoked_at IS NULL
+            "#,
+            grant_id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// A grant stays active while it has a live refresh token; access
+    /// tokens issued under it stop being accepted once it is revoked.
+    async fn is_oauth_grant_active(
+        &self,
+        grant_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let active = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM oauth_refresh_tokens
+                WHERE grant_id = $1 AND revoked_at IS NULL AND expires_at > Now()
+            ) as "active!"
+            "#,
+            grant_id
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(active)
+    }
+
+    /// Removes authorization codes that expired more than a day ago. Used
+    /// codes are kept until then so a replayed code can still revoke its grant.
+    async fn delete_stale_authorization_codes(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM oauth_authorization_codes
+            WHERE expires_at < Now() - INTERVAL '1 day'
+            "#
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+pub trait ServiceAccountExt {
+    async fn save_service_account(
+        &self,
+        name: &str,
+        role: UserRole,
+        client_id: &str,
+        client_secret_hash: &str,
+        scopes: &[String],
+        owner_id: Uuid,
+    ) -> Result<ServiceAccount, sqlx::Error>;
+
+    async fn get_service_accounts(&self) -> Result<Vec<ServiceAccount>, sqlx::Error>;
+
+    async fn delete_service_account(
+        &self,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl ServiceAccountExt for DBClient {
+    /// Creates the service account's user row and its OAuth client in one
+    /// transaction. The account has no usable password, and its email is
+    /// derived from the client id under a domain that can never receive mail.
+    async fn save_service_account(
+        &self,
+        name: &str,
+        role: UserRole,
+        client_id: &str,
+        client_secret_hash: &str,
+        scopes: &[String],
+        owner_id: Uuid,
+    ) -> Result<ServiceAccount, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let user = sqlx::query!(
+            r#"
+            INSERT INTO users (name, email, password, role, verified, service_account)
+            VALUES ($1, $2 || '@service-accounts.invalid', '', $3, true, true)
+            RETURNING id, created_at
+            "#,
+            name,
+            client_id,
+            role as UserRole
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO oauth_clients (client_id, client_secret_hash, name, redirect_uris, scopes, owner_id, service_account_id)
+            VALUES ($1, $2, $3, '{}', $4, $5, $6)
+            "#,
+            client_id,
+            client_secret_hash,
+            name,
+            scopes,
+            owner_id,
+            user.id
+        ).execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(ServiceAccount {
+            id: user.id,
+            name: name.to_string(),
+            role,
+            client_id: client_id.to_string(),
+            scopes: scopes.to_vec(),
+            created_at: user.created_at,
+        })
+    }
+
+    async fn get_service_accounts(&self) -> Result<Vec<ServiceAccount>, sqlx::Error> {
+        let service_accounts = sqlx::query_as!(
+            ServiceAccount,
+            r#"
+            SELECT users.id, users.name, users.role as "role: UserRole", oauth_clients.client_id, oauth_clients.scopes, users.created_at
+            FROM users
+            JOIN oauth_clients ON oauth_clients.service_account_id = users.id
+            WHERE users.service_account = true
+            ORDER BY users.created_at DESC
+            "#
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(service_accounts)
+    }
+
+    /// Deleting the user row removes its OAuth client as well.
+    async fn delete_service_account(
+        &self,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM users
+            WHERE id = $1 AND service_account = true
+            "#,
+            id
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+pub trait PasswordHistoryExt {
+    async fn get_password_history(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<String>, sqlx::Error>;
+}
+
+#[async_trait]
+impl PasswordHistoryExt for DBClient {
+    /// Returns the user's previous password hashes, newest first.
+    async fn get_password_history(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let hashes = sqlx::query_scalar!(
+            r#"
+            SELECT password_hash FROM password_history
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            user_id,
+            limit.max(0)
+        ).fetch_all(&self.pool)
+        .await?;
+
+        Ok(hashes)
+    }
+}
+
+#[async_trait]
+pub trait OneTimeTokenExt {
+    async fn save_one_time_token(
+        &self,
+        user_id: Uuid,
+        purpose: TokenPurpose,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<OneTimeToken, sqlx::Error>;
+
+    async fn get_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, sqlx::Error>;
+
+    async fn consume_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, sqlx::Error>;
+
+    async fn delete_stale_one_time_tokens(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl OneTimeTokenExt for DBClient {
+    /// Stores a new token, invalidating the user's earlier tokens for the
+    /// same purpose.
+    async fn save_one_time_token(
+        &self,
+        user_id: Uuid,
+        purpose: TokenPurpose,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<OneTimeToken, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM one_time_tokens
+            WHERE user_id = $1 AND purpose = $2
+            "#,
+            user_id,
+            purpose as TokenPurpose
+        ).execute(&mut *tx)
+        .await?;
+
+        let one_time_token = sqlx::query_as!(
+            OneTimeToken,
+            r#"
+            INSERT INTO one_time_tokens (user_id, purpose, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, purpose as "purpose: TokenPurpose", token_hash, expires_at, used_at, created_at
+            "#,
+            user_id,
+            purpose as TokenPurpose,
+            token_hash,
+            expires_at
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(one_time_token)
+    }
+
+    /// Returns an unused, unexpired token without redeeming it.
+    async fn get_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, sqlx::Error> {
+        let one_time_token = sqlx::query_as!(
+            OneTimeToken,
+            r#"
+            SELECT id, user_id, purpose as "purpose: TokenPurpose", token_hash, expires_at, used_at, created_at
+            FROM one_time_tokens
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > Now()
+            "#,
+            token_hash,
+            purpose as TokenPurpose
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(one_time_token)
+    }
+
+    /// Marks an unused, unexpired token as used and returns it, so a token
+    /// can only ever be redeemed once and only for the purpose it was issued for.
+    async fn consume_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, sqlx::Error> {
+        let one_time_token = sqlx::query_as!(
+            OneTimeToken,
+            r#"
+            UPDATE one_time_tokens
+            SET used_at = Now()
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > Now()
+            RETURNING id, user_id, purpose as "purpose: TokenPurpose", token_hash, expires_at, used_at, created_at
+            "#,
+            token_hash,
+            purpose as TokenPurpose
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(one_time_token)
+    }
+
+    /// Removes expired and already used tokens.
+    async fn delete_stale_one_time_tokens(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM one_time_tokens
+            WHERE used_at IS NOT NULL OR expires_at < Now()
+            "#
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+pub trait LoginThrottleExt {
+    async fn delete_stale_login_attempts(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error>;
+
+    async fn delete_expired_lockouts(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl LoginThrottleExt for DBClient {
+    /// Removes attempts made before `before`, across all keys.
+    async fn delete_stale_login_attempts(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE attempted_at < $1
+            "#,
+            before
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Removes account lockouts and spent MFA challenges that ended before
+    /// `before`.
+    async fn delete_expired_lockouts(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM login_lockouts
+            WHERE locked_until < $1
+            "#,
+            before
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl AttemptStore for DBClient {
+    async fn record_attempt(
+        &self,
+        key: &str,
+        window_start: DateTime<Utc>,
+    ) -> Result<AttemptWindow, StoreError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE attempted_at < $1 AND throttle_key = $2
+            "#,
+            window_start,
+            key
+        ).execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO login_attempts (throttle_key)
+            VALUES ($1)
+            "#,
+            key
+        ).execute(&mut *tx)
+        .await?;
+
+        let window = sqlx::query!(
+            r#"
+            SELECT COUNT(*) as "count!", MIN(attempted_at) as oldest
+            FROM login_attempts
+            WHERE throttle_key = $1
+            "#,
+            key
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(AttemptWindow {
+            count: window.count,
+            oldest: window.oldest,
+        })
+    }
+
+    async fn clear_attempts(&self, key: &str) -> Result<(), StoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE throttle_key = $1
+            "#,
+            key
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_lockout(&self, key: &str) -> Result<Option<Lockout>, StoreError> {
+        let lockout = sqlx::query_as!(
+            Lockout,
+            r#"
+            SELECT locked_until, lockout_count
+            FROM login_lockouts
+            WHERE throttle_key = $1
+            "#,
+            key
+        ).fetch_optional(&self.pool)
+        .await?;
+
+        Ok(lockout)
+    }
+
+    async fn save_lockout(&self, key: &str, lockout: Lockout) -> Result<(), StoreError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO login_lockouts (throttle_key, locked_until, lockout_count)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (throttle_key)
+            DO UPDATE SET locked_until = $2, lockout_count = $3, updated_at = Now()
+            "#,
+            key,
+            lockout.locked_until,
+            lockout.lockout_count
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn clear_lockout(&self, key: &str) -> Result<(), StoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM login_lockouts
+            WHERE throttle_key = $1
+            "#,
+            key
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait RateLimitExt {
+    async fn delete_expired_rate_limits(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl RateLimitExt for DBClient {
+    /// Removes keys whose arrival time has passed; they behave like new keys.
+    async fn delete_expired_rate_limits(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM rate_limits
+            WHERE tat < Now()
+            "#
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for DBClient {
+    async fn check(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitDecision, StoreError> {
+        let mut tx = self.pool.begin().await?;
+
+        // An arrival time in the past means no requests are outstanding, so
+        // new keys can be inserted up front and locked like existing ones.
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limits (rate_key, tat)
+            VALUES ($1, Now())
+            ON CONFLICT (rate_key) DO NOTHING
+            "#,
+            key
+        ).execute(&mut *tx)
+        .await?;
+
+        let tat = sqlx::query_scalar!(
+            r#"
+            SELECT tat
+            FROM rate_limits
+            WHERE rate_key = $1
+            FOR UPDATE
+            "#,
+            key
+        ).fetch_one(&mut *tx)
+        .await?;
+
+        let (decision, new_tat) = policy.evaluate(Some(tat), Utc::now());
+
+        if let Some(new_tat) = new_tat {
+            sqlx::query!(
+                r#"
+                UPDATE rate_limits
+                SET tat = $1
+                WHERE rate_key = $2
+                "#,
+                new_tat,
+                key
+            ).execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(decision)
+    }
+}
+
+#[async_trait]
+pub trait RevocationExt {
+    async fn delete_expired_revocations(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl RevocationExt for DBClient {
+    /// Forgets revoked tokens that have expired anyway.
+    async fn delete_expired_revocations(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM revoked_tokens
+            WHERE expires_at < Now()
+            "#
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RevocationStore for DBClient {
+    async fn revoke(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, StoreError> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens (jti, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            jti,
+            expires_at
+        ).execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, StoreError> {
+        let revoked = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM revoked_tokens
+                WHERE jti = $1 AND expires_at > Now()
+            ) as "revoked!"
+            "#,
+            jti
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(revoked)
+    }
+}
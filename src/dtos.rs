@@ -3,7 +3,7 @@ use chrono::{ DateTime, Utc };
 use serde::{ Deserialize, Serialize };
 use validator::Validate;
 
-use crate::models::{ User, UserRole };
+use crate::models::{ Identity, OAuthClient, PersonalAccessToken, ServiceAccount, Session, User, UserRole, WebauthnCredential };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct RegisterUserDto {
@@ -14,12 +14,9 @@ pub struct RegisterUserDto {
         email(message = "Email is invalid")
     )]
     pub email: String,
-    #[validate(
-        length(min = 8, message = "Password must be at least 8 characters")
-    )]
     pub password: String,
     #[validate(
-        length(min = 1, message = "Role is required"),
+        length(min = 1, message = "Password confirmation is required"),
         must_match(other = "password", message = "New passwords do not match")
     )]
     #[serde(rename = "passwordConfirm")]
@@ -35,7 +32,7 @@ pub struct LoginUserDto {
     )]
     pub email: String,
     #[validate(
-        length(min = 6, message = "Password must be at least 6 characters")
+        length(min = 1, message = "Password is required")
     )]
     pub password: String,
 }
@@ -74,10 +71,10 @@ impl FilterUserDto {
         }
     }
 
-    pub fn filter_users(users: &Vec<User>) -> Vec<Self> {
+    pub fn filter_users(users: &[User]) -> Vec<Self> {
         users
             .iter()
-            .map(|user| Self::filter_user(user))
+            .map(Self::filter_user)
             .collect()
     }
 }
@@ -100,10 +97,74 @@ pub struct UserListResponseDto {
     pub result: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilterSessionDto {
+    pub id: String,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    pub current: bool,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+    #[serde(rename = "lastSeenAt")]
+    pub last_seen_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl FilterSessionDto {
+    pub fn filter_session(session: &Session, current_session_id: uuid::Uuid) -> Self {
+        Self {
+            id: session.id.to_string(),
+            user_agent: session.user_agent.to_owned(),
+            ip_address: session.ip_address.to_owned(),
+            current: session.id == current_session_id,
+            expires_at: session.expires_at,
+            last_seen_at: session.last_seen_at,
+            created_at: session.created_at,
+        }
+    }
+
+    pub fn filter_sessions(sessions: &[Session], current_session_id: uuid::Uuid) -> Vec<Self> {
+        sessions
+            .iter()
+            .map(|session| Self::filter_session(session, current_session_id))
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionListResponseDto {
+    pub status: String,
+    pub sessions: Vec<FilterSessionDto>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserLoginResponseDto {
     pub status: String,
     pub token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallengeResponseDto {
+    pub status: String,
+    pub mfa_token: String,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct MfaLoginDto {
+    #[validate(length(min = 1, message = "MFA token is required"))]
+    pub mfa_token: String,
+    #[validate(length(min = 6, max = 32, message = "Code must be between 6 and 32 characters"))]
+    pub code: String,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshTokenDto {
+    #[validate(length(min = 1, message = "Refresh token is required"))]
+    pub refresh_token: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -131,19 +192,53 @@ fn validate_user_role(role: &UserRole) -> Result<(), validator::ValidationError>
     }
 }
 
+#[derive(Debug, Validate, Clone, Serialize, Deserialize)]
+pub struct ImportUserDto {
+    #[validate(length(min = 1, max = 100, message = "Name is required"))]
+    pub name: String,
+    #[validate(
+        length(min = 1, message = "Email is required"),
+        email(message = "Email is invalid")
+    )]
+    pub email: String,
+    #[validate(length(min = 1, max = 255, message = "Password hash is required"))]
+    #[serde(rename = "passwordHash")]
+    pub password_hash: String,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+#[derive(Debug, Validate, Clone, Serialize, Deserialize)]
+pub struct ImportUsersDto {
+    #[validate(length(min = 1, max = 1000, message = "Between 1 and 1000 users can be imported at once"), nested)]
+    pub users: Vec<ImportUserDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportUsersResponseDto {
+    pub status: String,
+    pub imported: usize,
+    /// Emails that already belong to an account.
+    pub skipped: Vec<String>,
+}
+
 #[derive(Debug, Validate, Default, Clone, Serialize, Deserialize)]
 pub struct UserPasswordUpdateDto {
-    #[validate(length(min = 8, message = "Password must be at least 8 characters"))]
     pub new_password: String,
     #[validate(
-        length(min = 8, message = "Password must be at least 8 characters"),
+        length(min = 1, message = "Password confirmation is required"),
         must_match(other = "new_password", message = "New passwords do not match")
     )]
     pub new_password_confirm: String,
-    #[validate(length(min = 8, message = "Password must be at least 8 characters"))]
+    #[validate(length(min = 1, message = "Old password is required"))]
     pub old_password: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ForwardAuthQueryDto {
+    pub role: Option<String>,
+}
+
 #[derive(Validate, Serialize, Deserialize)]
 pub struct VerifyEmailQueryDto {
     #[validate(length(min = 1, message = "Token is required"))]
@@ -156,16 +251,628 @@ pub struct ForgotPasswordRequestDto {
     pub email: String,
 }
 
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLinkRequestDto {
+    #[validate(
+        length(min = 1, message = "Email is required"),
+        email(message = "Email is invalid")
+    )]
+    pub email: String,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLinkQueryDto {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate, Clone)]
 pub struct ResetPasswordDto {
     #[validate(length(min = 1, message = "Token is required"))]
     pub token: String,
-    #[validate(length(min = 8, message = "Password must be at least 8 characters"))]
     pub new_password: String,
     #[validate(
-        length(min = 8, message = "Password must be at least 8 characters"),
+        length(min = 1, message = "Password confirmation is required"),
         must_match(other = "new_password", message = "New passwords do not match")
     )]
     pub new_password_confirm: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorSetupResponseDto {
+    pub status: String,
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorCodeDto {
+    #[validate(length(min = 6, max = 32, message = "Code must be between 6 and 32 characters"))]
+    pub code: String,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorDisableDto {
+    #[validate(length(min = 1, message = "Password is required"))]
+    pub password: Option<String>,
+    #[validate(length(min = 6, max = 32, message = "Code must be between 6 and 32 characters"))]
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponseDto {
+    pub status: String,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelyingPartyDto {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyUserDto {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicKeyCredentialParamDto {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicKeyCredentialDescriptorDto {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelectionDto {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialCreationOptionsDto {
+    pub challenge: String,
+    pub rp: RelyingPartyDto,
+    pub user: PublicKeyUserDto,
+    pub pub_key_cred_params: Vec<PublicKeyCredentialParamDto>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<PublicKeyCredentialDescriptorDto>,
+    pub authenticator_selection: AuthenticatorSelectionDto,
+    pub attestation: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialRequestOptionsDto {
+    pub challenge: String,
+    pub timeout: u64,
+    pub rp_id: String,
+    pub allow_credentials: Vec<PublicKeyCredentialDescriptorDto>,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyRegistrationOptionsResponseDto {
+    pub status: String,
+    pub challenge_id: String,
+    #[serde(rename = "publicKey")]
+    pub public_key: PublicKeyCredentialCreationOptionsDto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyLoginOptionsResponseDto {
+    pub status: String,
+    pub challenge_id: String,
+    #[serde(rename = "publicKey")]
+    pub public_key: PublicKeyCredentialRequestOptionsDto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestationResponseDto {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationCredentialDto {
+    pub id: String,
+    pub response: AttestationResponseDto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponseDto {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationCredentialDto {
+    pub id: String,
+    pub response: AssertionResponseDto,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyRegisterDto {
+    pub challenge_id: uuid::Uuid,
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+    pub credential: RegistrationCredentialDto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyLoginDto {
+    pub challenge_id: uuid::Uuid,
+    pub credential: AuthenticationCredentialDto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilterPasskeyDto {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl FilterPasskeyDto {
+    pub fn filter_passkey(credential: &WebauthnCredential) -> Self {
+        Self {
+            id: credential.id.to_string(),
+            name: credential.name.to_owned(),
+            last_used_at: credential.last_used_at,
+            created_at: credential.created_at,
+        }
+    }
+
+    pub fn filter_passkeys(credentials: &[WebauthnCredential]) -> Vec<Self> {
+        credentials
+            .iter()
+            .map(Self::filter_passkey)
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyListResponseDto {
+    pub status: String,
+    pub passkeys: Vec<FilterPasskeyDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyResponseDto {
+    pub status: String,
+    pub passkey: FilterPasskeyDto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SamlResponseDto {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SocialCallbackQueryDto {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SocialProviderListResponseDto {
+    pub status: String,
+    pub providers: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilterIdentityDto {
+    pub id: String,
+    pub provider: String,
+    pub email: Option<String>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl FilterIdentityDto {
+    pub fn filter_identity(identity: &Identity) -> Self {
+        Self {
+            id: identity.id.to_string(),
+            provider: identity.provider.to_owned(),
+            email: identity.email.to_owned(),
+            last_used_at: identity.last_used_at,
+            created_at: identity.created_at,
+        }
+    }
+
+    pub fn filter_identities(identities: &[Identity]) -> Vec<Self> {
+        identities
+            .iter()
+            .map(Self::filter_identity)
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityListResponseDto {
+    pub status: String,
+    pub identities: Vec<FilterIdentityDto>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct CreateAccessTokenDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[validate(range(min = 1, max = 365, message = "Expiry must be between 1 and 365 days"))]
+    #[serde(rename = "expiresInDays")]
+    pub expires_in_days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilterAccessTokenDto {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "tokenPrefix")]
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl FilterAccessTokenDto {
+    pub fn filter_access_token(access_token: &PersonalAccessToken) -> Self {
+        Self {
+            id: access_token.id.to_string(),
+            name: access_token.name.to_owned(),
+            token_prefix: access_token.token_prefix.to_owned(),
+            scopes: access_token.scopes.to_owned(),
+            expires_at: access_token.expires_at,
+            last_used_at: access_token.last_used_at,
+            created_at: access_token.created_at,
+        }
+    }
+
+    pub fn filter_access_tokens(access_tokens: &[PersonalAccessToken]) -> Vec<Self> {
+        access_tokens
+            .iter()
+            .map(FilterAccessTokenDto::filter_access_token)
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessTokenListResponseDto {
+    pub status: String,
+    pub tokens: Vec<FilterAccessTokenDto>,
+}
+
+/// Returned once when a token is created; the token itself cannot be
+/// retrieved again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessTokenCreatedResponseDto {
+    pub status: String,
+    pub token: String,
+    #[serde(rename = "accessToken")]
+    pub access_token: FilterAccessTokenDto,
+}
+
+/// Query of `GET /oauth/authorize`. Parameter names follow RFC 6749, so no
+/// camelCase renames here.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct AuthorizeQueryDto {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    #[validate(length(max = 255))]
+    pub nonce: Option<String>,
+}
+
+/// Form posted by the consent page.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct ConsentDecisionDto {
+    #[validate(length(min = 1, message = "Consent token is required"))]
+    pub consent_token: String,
+    #[validate(custom(function = "validate_consent_decision"))]
+    pub decision: String,
+}
+
+fn validate_consent_decision(decision: &str) -> Result<(), validator::ValidationError> {
+    match decision {
+        "approve" | "deny" => Ok(()),
+        _ => Err(validator::ValidationError::new("decision")
+            .with_message("Decision must be approve or deny".into())),
+    }
+}
+
+/// Form posted to `/oauth/token`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenRequestDto {
+    pub grant_type: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Form posted to `/oauth/introspect` and `/oauth/revoke`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenLookupDto {
+    pub token: Option<String>,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// RFC 7662 introspection response. Inactive tokens only report `active`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IntrospectionResponseDto {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+}
+
+impl IntrospectionResponseDto {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    pub fn active(user: &User, token_type: &str, scope: &str, client_id: Option<&str>, exp: i64) -> Self {
+        Self {
+            active: true,
+            sub: Some(user.id.to_string()),
+            username: Some(user.email.to_owned()),
+            role: Some(user.role.to_str().to_string()),
+            scope: Some(scope.to_string()),
+            client_id: client_id.map(str::to_string),
+            token_type: Some(token_type.to_string()),
+            exp: Some(exp),
+            iat: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthTokenResponseDto {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    /// Not issued for the client_credentials grant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct RegisterOAuthClientDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, max = 10, message = "Between 1 and 10 redirect URIs are required"))]
+    #[serde(rename = "redirectUris")]
+    pub redirect_uris: Vec<String>,
+    /// Public clients (SPAs, native apps) get no secret and rely on PKCE.
+    #[serde(default = "default_confidential")]
+    pub confidential: bool,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+fn default_confidential() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilterOAuthClientDto {
+    pub id: String,
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub name: String,
+    #[serde(rename = "redirectUris")]
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub confidential: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl FilterOAuthClientDto {
+    pub fn filter_client(client: &OAuthClient) -> Self {
+        Self {
+            id: client.id.to_string(),
+            client_id: client.client_id.to_owned(),
+            name: client.name.to_owned(),
+            redirect_uris: client.redirect_uris.to_owned(),
+            scopes: client.scopes.to_owned(),
+            confidential: client.is_confidential(),
+            created_at: client.created_at,
+        }
+    }
+
+    pub fn filter_clients(clients: &[OAuthClient]) -> Vec<Self> {
+        clients
+            .iter()
+            .map(FilterOAuthClientDto::filter_client)
+            .collect()
+    }
+}
+
+/// Returned once when a client is registered; the secret cannot be
+/// retrieved again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthClientResponseDto {
+    pub status: String,
+    pub client: FilterOAuthClientDto,
+    #[serde(rename = "clientSecret", skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthClientListResponseDto {
+    pub status: String,
+    pub clients: Vec<FilterOAuthClientDto>,
+}
+
+/// OpenID Connect UserInfo response, limited to the claims of the scopes
+/// the access token was granted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserInfoResponseDto {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+impl UserInfoResponseDto {
+    pub fn from_user(user: &FilterUserDto, scopes: &[String]) -> Self {
+        let profile = scopes.iter().any(|scope| scope == "profile");
+        let email = scopes.iter().any(|scope| scope == "email");
+
+        Self {
+            sub: user.id.to_owned(),
+            name: profile.then(|| user.name.to_owned()),
+            role: profile.then(|| user.role.to_owned()),
+            updated_at: profile.then(|| user.updated_at.timestamp()),
+            email: email.then(|| user.email.to_owned()),
+            email_verified: email.then_some(user.verified),
+        }
+    }
+}
+
+/// OpenID Connect discovery document.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenIdConfigurationDto {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub jwks_uri: String,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct CreateServiceAccountDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "At least one scope is required"))]
+    pub scopes: Vec<String>,
+    #[serde(default = "default_service_account_role")]
+    pub role: UserRole,
+}
+
+fn default_service_account_role() -> UserRole {
+    UserRole::User
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilterServiceAccountDto {
+    pub id: String,
+    pub name: String,
+    pub role: String,
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl FilterServiceAccountDto {
+    pub fn filter_service_account(service_account: &ServiceAccount) -> Self {
+        Self {
+            id: service_account.id.to_string(),
+            name: service_account.name.to_owned(),
+            role: service_account.role.to_str().to_string(),
+            client_id: service_account.client_id.to_owned(),
+            scopes: service_account.scopes.to_owned(),
+            created_at: service_account.created_at,
+        }
+    }
+
+    pub fn filter_service_accounts(service_accounts: &[ServiceAccount]) -> Vec<Self> {
+        service_accounts
+            .iter()
+            .map(FilterServiceAccountDto::filter_service_account)
+            .collect()
+    }
+}
+
+/// Returned once when a service account is created; the secret cannot be
+/// retrieved again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceAccountCreatedResponseDto {
+    pub status: String,
+    #[serde(rename = "serviceAccount")]
+    pub service_account: FilterServiceAccountDto,
+    #[serde(rename = "clientSecret")]
+    pub client_secret: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceAccountListResponseDto {
+    pub status: String,
+    #[serde(rename = "serviceAccounts")]
+    pub service_accounts: Vec<FilterServiceAccountDto>,
+}
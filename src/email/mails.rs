@@ -47,4 +47,18 @@ pub async fn send_forgot_password_email(
     ];
 
     send_email(to_email, subject, template_path, &placeholders).await
-}
\ No newline at end of file
+}
+pub async fn send_magic_link_email(
+    to_email: &str,
+    username: &str,
+    login_link: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subject = "Your Login Link";
+    let template_path = "src/email/templates/magic_link_email.html";
+    let placeholders = vec![
+        ("{{username}}".to_string(), username.to_string()),
+        ("{{login_link}}".to_string(), login_link.to_string())
+    ];
+
+    send_email(to_email, subject, template_path, &placeholders).await
+}
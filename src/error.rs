@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -10,6 +10,8 @@ use std::fmt;
 pub struct ErrorResponse {
     pub status: String,
     pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
 }
 
 impl fmt::Display for ErrorResponse {
@@ -18,12 +20,13 @@ impl fmt::Display for ErrorResponse {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum ErrorMessage {
     EmptyPassword,
     ExceededMaxPasswordLength(usize),
     InvalidHashFormat,
     HashingError,
+    UnknownPepper,
     InvalidToken,
     // ServerError,
     WrongCredentials,
@@ -32,11 +35,49 @@ pub enum ErrorMessage {
     TokenNotProvided,
     PermissionDenied,
     UserNotAuthenticated,
+    RefreshTokenReused,
+    SessionRevoked,
+    TokenRevoked,
+    InvalidAccessToken,
+    InsufficientScope(String),
+    AccessTokenNotAllowed,
+    UnknownScope(String),
+    InvalidTwoFactorCode,
+    InvalidTwoFactorSecret,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    InvalidPasskey,
+    PasskeyCounterRegression,
+    TooManyLoginAttempts,
+    AccountLocked,
+    MfaChallengeSpent,
+    RateLimitExceeded,
+    PasswordPolicyViolation,
+    PasswordTooShort(usize),
+    PasswordMissingLowercase,
+    PasswordMissingUppercase,
+    PasswordMissingDigit,
+    PasswordMissingSymbol,
+    PasswordTooWeak,
+    PasswordContainsPersonalInfo,
+    PasswordBreached,
+    PasswordReused,
+    UnknownProvider(String),
+    InvalidSocialState,
+    IdentityAlreadyLinked,
+    SocialEmailNotVerified,
+    SocialAccountExists,
+    ExternalAccountExists,
+    LastSignInMethod,
+    SamlNotConfigured,
+    InvalidSamlResponse(String),
+    SamlAssertionReused,
+    OpenIdNotConfigured,
 }
 
-impl ToString for ErrorMessage {
-    fn to_string(&self) -> String {
-        self.to_str().to_owned()
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str())
     }
 }
 
@@ -45,8 +86,9 @@ impl ErrorMessage {
         match self {
             ErrorMessage::EmptyPassword => "Password cannot be empty".to_string(),
             ErrorMessage::ExceededMaxPasswordLength(length) => format!("Password cannot be longer than {} characters", length),
-            ErrorMessage::InvalidHashFormat => "Invalid password hash format".to_string(),
+            ErrorMessage::InvalidHashFormat => "Invalid password hash format or cost".to_string(),
             ErrorMessage::HashingError => "An error occurred while hashing the password".to_string(),
+            ErrorMessage::UnknownPepper => "Password hash was made with an unknown pepper".to_string(),
             ErrorMessage::InvalidToken => "Invalid token".to_string(),
             // ErrorMessage::ServerError => "An error occurred on the server".to_string(),
             ErrorMessage::WrongCredentials => "Wrong credentials".to_string(),
@@ -55,6 +97,44 @@ impl ErrorMessage {
             ErrorMessage::TokenNotProvided => "Token not provided".to_string(),
             ErrorMessage::PermissionDenied => "Permission denied".to_string(),
             ErrorMessage::UserNotAuthenticated => "User not authenticated".to_string(),
+            ErrorMessage::RefreshTokenReused => "Refresh token has already been used".to_string(),
+            ErrorMessage::SessionRevoked => "Session has been revoked or has expired".to_string(),
+            ErrorMessage::TokenRevoked => "Token has been revoked".to_string(),
+            ErrorMessage::InvalidAccessToken => "Access token is invalid, revoked or expired".to_string(),
+            ErrorMessage::InsufficientScope(scope) => format!("Access token is missing the {} scope", scope),
+            ErrorMessage::AccessTokenNotAllowed => "This endpoint requires an interactive login".to_string(),
+            ErrorMessage::UnknownScope(scope) => format!("Unknown scope: {}", scope),
+            ErrorMessage::InvalidTwoFactorCode => "Invalid two-factor authentication code".to_string(),
+            ErrorMessage::InvalidTwoFactorSecret => "Invalid two-factor authentication secret".to_string(),
+            ErrorMessage::TwoFactorAlreadyEnabled => "Two-factor authentication is already enabled".to_string(),
+            ErrorMessage::TwoFactorNotEnabled => "Two-factor authentication is not enabled".to_string(),
+            ErrorMessage::InvalidPasskey => "Passkey verification failed".to_string(),
+            ErrorMessage::PasskeyCounterRegression => "Passkey signature counter did not increase".to_string(),
+            ErrorMessage::TooManyLoginAttempts => "Too many login attempts, please try again later".to_string(),
+            ErrorMessage::AccountLocked => "Account is temporarily locked after too many failed login attempts".to_string(),
+            ErrorMessage::MfaChallengeSpent => "This login challenge can no longer be used, please log in again".to_string(),
+            ErrorMessage::RateLimitExceeded => "Rate limit exceeded, please try again later".to_string(),
+            ErrorMessage::PasswordPolicyViolation => "Password does not meet the password policy".to_string(),
+            ErrorMessage::PasswordTooShort(length) => format!("Password must be at least {} characters", length),
+            ErrorMessage::PasswordMissingLowercase => "Password must contain a lowercase letter".to_string(),
+            ErrorMessage::PasswordMissingUppercase => "Password must contain an uppercase letter".to_string(),
+            ErrorMessage::PasswordMissingDigit => "Password must contain a digit".to_string(),
+            ErrorMessage::PasswordMissingSymbol => "Password must contain a symbol".to_string(),
+            ErrorMessage::PasswordTooWeak => "Password is too easy to guess".to_string(),
+            ErrorMessage::PasswordContainsPersonalInfo => "Password must not contain your name or email".to_string(),
+            ErrorMessage::PasswordBreached => "Password has appeared in a data breach".to_string(),
+            ErrorMessage::PasswordReused => "Password must not match your current or a recent password".to_string(),
+            ErrorMessage::UnknownProvider(provider) => format!("Unknown identity provider: {}", provider),
+            ErrorMessage::InvalidSocialState => "Sign-in request is invalid or has expired".to_string(),
+            ErrorMessage::IdentityAlreadyLinked => "This external account is already linked to another user".to_string(),
+            ErrorMessage::SocialEmailNotVerified => "The identity provider did not return a verified email".to_string(),
+            ErrorMessage::SocialAccountExists => "An account with this email already exists, sign in and link the provider from your settings".to_string(),
+            ErrorMessage::ExternalAccountExists => "An account with this email already exists and is not linked to this sign-in method".to_string(),
+            ErrorMessage::LastSignInMethod => "Set a password before removing your last sign-in method".to_string(),
+            ErrorMessage::SamlNotConfigured => "SAML sign-in is not configured".to_string(),
+            ErrorMessage::InvalidSamlResponse(reason) => format!("Invalid SAML response: {}", reason),
+            ErrorMessage::SamlAssertionReused => "SAML assertion has already been used".to_string(),
+            ErrorMessage::OpenIdNotConfigured => "OpenID Connect requires an asymmetric JWT_ALGORITHM".to_string(),
         }
     }
 }
@@ -63,6 +143,8 @@ impl ErrorMessage {
 pub struct HttpError {
     pub message: String,
     pub status: StatusCode,
+    pub retry_after: Option<u64>,
+    pub errors: Vec<String>,
 }
 
 impl HttpError {
@@ -70,6 +152,8 @@ impl HttpError {
         Self {
             message: message.into(),
             status,
+            retry_after: None,
+            errors: Vec::new(),
         }
     }
 
@@ -77,6 +161,8 @@ impl HttpError {
         Self {
             message: message.into(),
             status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: None,
+            errors: Vec::new(),
         }
     }
 
@@ -84,6 +170,8 @@ impl HttpError {
         Self {
             message: message.into(),
             status: StatusCode::BAD_REQUEST,
+            retry_after: None,
+            errors: Vec::new(),
         }
     }
 
@@ -91,6 +179,8 @@ impl HttpError {
         Self {
             message: message.into(),
             status: StatusCode::CONFLICT,
+            retry_after: None,
+            errors: Vec::new(),
         }
     }   
 
@@ -98,16 +188,40 @@ impl HttpError {
         Self {
             message: message.into(),
             status: StatusCode::UNAUTHORIZED,
+            retry_after: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn too_many_requests(message: impl Into<String>, retry_after: u64) -> Self {
+        Self {
+            message: message.into(),
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(retry_after),
+            errors: Vec::new(),
         }
     }
 
+    /// Attaches a list of individual failures, such as one per broken rule.
+    pub fn with_errors(mut self, errors: Vec<String>) -> Self {
+        self.errors = errors;
+        self
+    }
+
     pub fn into_http_response(self) -> Response {
         let json_response = Json(ErrorResponse {
             status: self.status.to_string(),
             message: self.message.clone(),
+            errors: self.errors,
         });
 
-        (self.status, json_response).into_response()
+        let mut response = (self.status, json_response).into_response();
+
+        if let Some(retry_after) = self.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
     }
 }
 
@@ -127,4 +241,103 @@ impl IntoResponse for HttpError {
     fn into_response(self) -> Response {
         self.into_http_response()
     }
-}
\ No newline at end of file
+}
+/// Error body defined by RFC 6749 for the OAuth token endpoint.
+#[derive(Serialize, Deserialize)]
+pub struct OAuthErrorResponse {
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OAuthError {
+    pub error: &'static str,
+    pub description: String,
+    pub status: StatusCode,
+}
+
+impl OAuthError {
+    pub fn new(error: &'static str, description: impl Into<String>) -> Self {
+        Self {
+            error,
+            description: description.into(),
+            status: StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn invalid_request(description: impl Into<String>) -> Self {
+        Self::new("invalid_request", description)
+    }
+
+    pub fn invalid_grant(description: impl Into<String>) -> Self {
+        Self::new("invalid_grant", description)
+    }
+
+    pub fn invalid_scope(description: impl Into<String>) -> Self {
+        Self::new("invalid_scope", description)
+    }
+
+    pub fn invalid_client(description: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            ..Self::new("invalid_client", description)
+        }
+    }
+
+    pub fn server_error(description: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            ..Self::new("server_error", description)
+        }
+    }
+
+    /// Bearer token errors from RFC 6750, for resource endpoints.
+    pub fn invalid_token(description: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            ..Self::new("invalid_token", description)
+        }
+    }
+
+    pub fn insufficient_scope(description: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            ..Self::new("insufficient_scope", description)
+        }
+    }
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OAuthError: {}: {}", self.error, self.description)
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let body = Json(OAuthErrorResponse {
+            error: self.error.to_string(),
+            error_description: Some(self.description),
+        });
+
+        let mut response = (self.status, body).into_response();
+        response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+        let challenge = match self.error {
+            "invalid_client" => Some(HeaderValue::from_static("Basic")),
+            "invalid_token" | "insufficient_scope" => {
+                HeaderValue::from_str(&format!("Bearer error=\"{}\"", self.error)).ok()
+            },
+            _ => None,
+        };
+
+        if let Some(challenge) = challenge {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, challenge);
+        }
+
+        response
+    }
+}
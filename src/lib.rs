@@ -7,8 +7,12 @@ mod utils;
 mod middleware;
 mod email;
 mod controller;
+mod rate_limit;
 mod routes;
+#[cfg(test)]
+mod test_support;
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::http::header::{
@@ -19,19 +23,57 @@ use axum::http::header::{
 use axum::http::HeaderValue;
 use axum::http::Method;
 use config::Config;
-use database::DBClient;
+use chrono::{Duration, Utc};
+use database::{DBClient, LoginThrottleExt, OAuthExt, OneTimeTokenExt, RateLimitExt, RevocationExt, WebauthnExt};
+use rate_limit::RateLimitStore;
 use routes::create_router;
 use sqlx::postgres::PgPoolOptions;
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::filter::LevelFilter;
+use utils::keys::KeyRing;
+use utils::ldap::{self, Directory};
+use utils::password::PasswordHashing;
+use utils::password_policy::PasswordPolicy;
+use utils::revocation::{self, RevocationStore};
+use utils::saml::IdentityProvider;
+use utils::throttle::LoginThrottle;
 use dotenvy::dotenv;
 
 #[derive(Debug,Clone)]
 pub struct AppState {
     pub env: Config,
     pub db_client: DBClient,
+    pub key_ring: KeyRing,
+    pub login_throttle: LoginThrottle,
+    pub rate_limit_store: Arc<dyn RateLimitStore>,
+    pub revocation_store: Arc<dyn RevocationStore>,
+    pub password_policy: PasswordPolicy,
+    pub password_hashing: PasswordHashing,
+    pub http_client: reqwest::Client,
+    pub directory: Option<Arc<dyn Directory>>,
+    pub saml_idp: Option<IdentityProvider>,
 }
 
+impl AppState {
+    pub fn new(config: Config, db_client: DBClient) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            key_ring: KeyRing::from_config(&config)?,
+            login_throttle: LoginThrottle::from_config(&config, db_client.clone())?,
+            rate_limit_store: rate_limit::store_from_config(&config, db_client.clone())?,
+            revocation_store: revocation::store_from_config(&config, db_client.clone())?,
+            password_policy: PasswordPolicy::from_config(&config)?,
+            password_hashing: PasswordHashing::from_config(&config)?,
+            http_client: reqwest::Client::builder()
+                .user_agent("auth-validator")
+                .timeout(std::time::Duration::from_secs(10))
+                .build()?,
+            directory: ldap::directory_from_config(&config)?,
+            saml_idp: IdentityProvider::from_config(&config)?,
+            db_client,
+            env: config,
+        })
+    }
+}
 
 pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
@@ -65,22 +107,103 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     let db_client = DBClient::new(pool);
 
-    let app_state = AppState {
-        env: config.clone(),
-        db_client,
-    };
+    tokio::spawn(purge_stale_records(db_client.clone(), config.clone()));
+
+    let app_state = AppState::new(config.clone(), db_client)?;
 
     let app = create_router(Arc::new(app_state))
         .layer(cors.clone());
 
-    println!(
-        "{}",
-        format!("Server is running on port {}", config.port)
-    );
+    println!("Server is running on port {}", config.port);
 
     let listener = tokio::net::TcpListener::bind(&format!("0.0.0.0:{}", config.port)).await?;
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+
+    Ok(())
+}
+
+/// Removes used or expired one-time tokens, rate-limit state,
+/// authorization codes, token revocations, passkey challenges, login
+/// attempts and lockouts once an hour.
+///
+/// Lockouts are kept for the longest lockout after they end, so an account
+/// that keeps failing is still locked for longer each time.
+async fn purge_stale_records(db_client: DBClient, config: Config) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = db_client.delete_stale_one_time_tokens().await {
+            eprintln!("Failed to purge one-time tokens: {}", e);
+        }
+
+        if let Err(e) = db_client.delete_expired_rate_limits().await {
+            eprintln!("Failed to purge rate limits: {}", e);
+        }
+
+        if let Err(e) = db_client.delete_stale_authorization_codes().await {
+            eprintln!("Failed to purge authorization codes: {}", e);
+        }
+
+        if let Err(e) = db_client.delete_expired_revocations().await {
+            eprintln!("Failed to purge token revocations: {}", e);
+        }
+
+        if let Err(e) = db_client.delete_expired_webauthn_challenges().await {
+            eprintln!("Failed to purge passkey challenges: {}", e);
+        }
+
+        let attempt_window = Duration::seconds(config.login_attempt_window)
+            .max(Duration::minutes(utils::token::MFA_TOKEN_MAXAGE));
+
+        if let Err(e) = db_client.delete_stale_login_attempts(Utc::now() - attempt_window).await {
+            eprintln!("Failed to purge login attempts: {}", e);
+        }
+
+        let lockout_grace = Duration::seconds(config.login_lockout_max_seconds);
+
+        if let Err(e) = db_client.delete_expired_lockouts(Utc::now() - lockout_grace).await {
+            eprintln!("Failed to purge login lockouts: {}", e);
+        }
+    }
+}
+
+/// Builds the breached password filter loaded through
+/// `BREACHED_PASSWORDS_FILTER` from a Have I Been Pwned SHA-1 dump:
+///
+/// `auth-validator build-breach-filter <dump> <output> [--fp-rate 0.001] [--min-count 1]`
+pub fn build_breach_filter(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "Usage: auth-validator build-breach-filter <dump> <output> [--fp-rate 0.001] [--min-count 1]";
+
+    let (dump_path, output_path) = match args {
+        [dump_path, output_path, ..] => (dump_path, output_path),
+        _ => return Err(usage.into()),
+    };
+
+    let mut false_positive_rate = 0.001;
+    let mut min_count = 1;
+
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(usage)?;
+
+        match option.as_str() {
+            "--fp-rate" => false_positive_rate = value.parse::<f64>()?,
+            "--min-count" => min_count = value.parse::<u64>()?,
+            _ => return Err(usage.into()),
+        }
+    }
+
+    if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+        return Err("--fp-rate must be between 0 and 1".into());
+    }
+
+    let (filter, items) = utils::breach::build_from_dump(dump_path, false_positive_rate, min_count)?;
+    filter.save(output_path)?;
+
+    println!("Wrote {} password hashes to {}", items, output_path);
 
     Ok(())
-}
\ No newline at end of file
+}
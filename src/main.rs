@@ -1,6 +1,11 @@
-use auth_validator::run;
+use auth_validator::{build_breach_filter, run};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    run().await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("build-breach-filter") => build_breach_filter(&args[1..]),
+        _ => run().await,
+    }
 }
@@ -1,24 +1,44 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::Request,
-    http::{header, StatusCode},
+    extract::{FromRequestParts, Query, Request},
+    http::{header, request::Parts, StatusCode},
     middleware::Next,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension
 };
 use axum_extra::extract::cookie::CookieJar;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    database::UserExt, error::{ErrorMessage, HttpError}, models::{User, UserRole}, utils::token, AppState
+    database::{AccessTokenExt, OAuthExt, SessionExt, UserExt}, dtos::ForwardAuthQueryDto, error::{ErrorMessage, HttpError}, models::{User, UserRole}, utils::token::{self, TokenClaims}, AppState
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JWTAuthMiddleware {
     pub user: User,
+    pub session_id: uuid::Uuid,
+    pub claims: TokenClaims,
+    /// Set when the request authenticated with a personal access token
+    /// rather than a login session.
+    pub access_token_id: Option<uuid::Uuid>,
 }
 
+impl JWTAuthMiddleware {
+    /// Personal access tokens and service accounts are limited to the
+    /// scopes they were granted; interactive logins are not.
+    pub fn is_scoped(&self) -> bool {
+        self.access_token_id.is_some() || self.user.service_account
+    }
+}
+
+/// Verifies the request's JWT or personal access token and attaches its
+/// `TokenClaims`.
+///
+/// By default the session and user are also checked against the database
+/// and attached as `JWTAuthMiddleware`. With `JWT_TRUST_CLAIMS` enabled the
+/// claims are trusted as-is until they expire; handlers that extract
+/// `JWTAuthMiddleware` then load the user on demand.
 pub async fn auth(
     cookie_jar: CookieJar,
     Extension(app_state): Extension<Arc<AppState>>,
@@ -33,11 +53,9 @@ pub async fn auth(
                 .get(header::AUTHORIZATION)
                 .and_then(|auth_header| auth_header.to_str().ok())
                 .and_then(|auth_value| {
-                    if auth_value.starts_with("Bearer ") {
-                        Some(auth_value[7..].to_string())
-                    } else {
-                        None
-                    }
+                    auth_value
+                        .strip_prefix("Bearer ")
+                        .map(|token| token.to_string())
                 }
             )
         }
@@ -47,17 +65,129 @@ pub async fn auth(
         HttpError::unauthorized(ErrorMessage::TokenNotProvided.to_string())
     })?;
 
-    let token_details = match  token::decode_token(token, app_state.env.jwt_secret.as_bytes()) {
-        Ok(token_details) => token_details,
-        Err(_) => {
-            return Err(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()));
+    if token.starts_with(token::ACCESS_TOKEN_PREFIX) {
+        let auth = load_access_token_auth(&app_state, &token, true).await?;
+
+        req.extensions_mut().insert(auth.claims.clone());
+        req.extensions_mut().insert(auth);
+
+        return Ok(next.run(req).await);
+    }
+
+    let token_details = decode_unrevoked_token(&app_state, &token).await?;
+
+    if !app_state.env.jwt_trust_claims {
+        let auth = load_auth(&app_state, token_details.clone(), true).await?;
+        req.extensions_mut().insert(auth);
+    }
+
+    req.extensions_mut().insert(token_details);
+
+    Ok(next.run(req).await)
+}
+
+/// Decodes a JWT addressed to this service. Tokens issued to OAuth clients
+/// can be revoked before they expire, so those are checked against the
+/// revocation store.
+async fn decode_unrevoked_token(
+    app_state: &AppState,
+    token: &str,
+) -> Result<TokenClaims, HttpError> {
+    let claims = token::decode_token(token, &app_state.key_ring, &app_state.env)
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    if claims.client_id.is_some() {
+        let revoked = app_state.revocation_store
+            .is_revoked(&claims.jti)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if revoked {
+            return Err(HttpError::unauthorized(ErrorMessage::TokenRevoked.to_string()));
         }
-    };
+    }
 
-    let user_id = uuid::Uuid::parse_str(&token_details.to_string())
+    Ok(claims)
+}
+
+/// Verifies a login token, service account token or personal access token
+/// against the database, whatever `JWT_TRUST_CLAIMS` is set to.
+pub async fn verify_token(
+    app_state: &AppState,
+    token: &str,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    lookup_token(app_state, token, true).await
+}
+
+/// Like `verify_token`, but leaves the session's last-seen time and the
+/// access token's last-used time alone, so that token introspection by a
+/// resource server does not count as activity.
+pub async fn inspect_token(
+    app_state: &AppState,
+    token: &str,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    lookup_token(app_state, token, false).await
+}
+
+async fn lookup_token(
+    app_state: &AppState,
+    token: &str,
+    touch: bool,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    if token.starts_with(token::ACCESS_TOKEN_PREFIX) {
+        return load_access_token_auth(app_state, token, touch).await;
+    }
+
+    let claims = decode_unrevoked_token(app_state, token).await?;
+
+    load_auth(app_state, claims, touch).await
+}
+
+/// Authenticates the login cookie outside the `auth` middleware, for pages
+/// such as the OAuth authorization endpoint that redirect anonymous users
+/// instead of rejecting them.
+pub async fn authenticate_session(
+    app_state: &AppState,
+    token: &str,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    let auth = verify_token(app_state, token).await?;
+
+    if auth.is_scoped() {
+        return Err(HttpError::unauthorized(ErrorMessage::AccessTokenNotAllowed.to_string()));
+    }
+
+    Ok(auth)
+}
+
+async fn load_auth(
+    app_state: &AppState,
+    claims: TokenClaims,
+    touch: bool,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    let user_id = uuid::Uuid::parse_str(&claims.sub)
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    if let Some(client_id) = claims.client_id.clone() {
+        return load_service_account_auth(app_state, claims, user_id, &client_id).await;
+    }
+
+    let session_id = uuid::Uuid::parse_str(&claims.sid)
         .map_err(|_| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
 
-    let user = app_state.db_client.get_user(Some(user_id), None, None, None)
+    let session = if touch {
+        app_state.db_client.touch_session(session_id).await
+    } else {
+        app_state.db_client.get_active_session(session_id).await
+    };
+
+    let session = session.map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    match session {
+        Some(session) if session.user_id == user_id => {},
+        _ => return Err(HttpError::unauthorized(ErrorMessage::SessionRevoked.to_string())),
+    }
+
+    let user = app_state.db_client.get_user(Some(user_id), None, None)
         .await
         .map_err(|_| HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
 
@@ -65,29 +195,319 @@ pub async fn auth(
         HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string())
     })?;
 
-    req.extensions_mut().insert(JWTAuthMiddleware {
-        user: user.clone() 
-    });
+    Ok(JWTAuthMiddleware {
+        user,
+        session_id,
+        claims,
+        access_token_id: None,
+    })
+}
 
-    Ok(next.run(req).await)
+/// Authenticates a service account token. Instead of a session, the
+/// account's OAuth client must still exist, so deleting the account cuts
+/// off its tokens.
+async fn load_service_account_auth(
+    app_state: &AppState,
+    claims: TokenClaims,
+    user_id: uuid::Uuid,
+    client_id: &str,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    let client = app_state.db_client.get_oauth_client(client_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .filter(|client| client.service_account_id == Some(user_id))
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    let user = app_state.db_client.get_user(Some(user_id), None, None)
+        .await
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?
+        .filter(|user| user.service_account)
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    Ok(JWTAuthMiddleware {
+        user,
+        session_id: client.id,
+        claims,
+        access_token_id: None,
+    })
+}
+
+/// Authenticates a personal access token. Its scopes take the place of the
+/// login scope in the claims.
+async fn load_access_token_auth(
+    app_state: &AppState,
+    token: &str,
+    touch: bool,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    let token_hash = token::hash_token(token);
+
+    let access_token = if touch {
+        app_state.db_client.touch_access_token(&token_hash).await
+    } else {
+        app_state.db_client.get_active_access_token(&token_hash).await
+    };
+
+    let access_token = access_token
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::InvalidAccessToken.to_string()))?;
+
+    let user = app_state.db_client.get_user(Some(access_token.user_id), None, None)
+        .await
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::UserNoLongerExists.to_string()))?;
+
+    let claims = TokenClaims {
+        iss: app_state.env.jwt_issuer.to_owned(),
+        aud: app_state.env.jwt_audience.to_owned(),
+        sub: user.id.to_string(),
+        jti: access_token.id.to_string(),
+        sid: access_token.id.to_string(),
+        role: user.role.to_str().to_string(),
+        email_verified: user.verified,
+        scope: access_token.scopes.join(" "),
+        client_id: None,
+        iat: access_token.created_at.unwrap_or_else(chrono::Utc::now).timestamp(),
+        exp: access_token.expires_at.timestamp(),
+    };
+
+    Ok(JWTAuthMiddleware {
+        user,
+        session_id: access_token.id,
+        claims,
+        access_token_id: Some(access_token.id),
+    })
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for JWTAuthMiddleware {
+    type Rejection = HttpError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(auth) = parts.extensions.get::<JWTAuthMiddleware>() {
+            return Ok(auth.clone());
+        }
+
+        let claims = parts.extensions
+            .get::<TokenClaims>()
+            .cloned()
+            .ok_or_else(|| {
+                HttpError::unauthorized(ErrorMessage::UserNotAuthenticated.to_string())
+            })?;
+
+        let app_state = parts.extensions
+            .get::<Arc<AppState>>()
+            .cloned()
+            .ok_or_else(|| HttpError::server_error("Application state is missing".to_string()))?;
+
+        let auth = load_auth(&app_state, claims, true).await?;
+        parts.extensions.insert(auth.clone());
+
+        Ok(auth)
+    }
 }
 
 pub async fn role_check(
-    Extension(_app_state): Extension<Arc<AppState>>,
+    Extension(app_state): Extension<Arc<AppState>>,
     req: Request,
     next: Next,
     required_role: Vec<UserRole>
 ) -> Result<impl IntoResponse, HttpError> {
-    let user = req
-        .extensions()
-        .get::<JWTAuthMiddleware>()
-        .ok_or_else(|| {
-            HttpError::unauthorized(ErrorMessage::UserNotAuthenticated.to_string())
-        })?;
-
-    if !required_role.contains(&user.user.role) {
+    let has_role = if app_state.env.jwt_trust_claims {
+        let claims = req
+            .extensions()
+            .get::<TokenClaims>()
+            .ok_or_else(|| {
+                HttpError::unauthorized(ErrorMessage::UserNotAuthenticated.to_string())
+            })?;
+
+        required_role.iter().any(|role| role.to_str() == claims.role)
+    } else {
+        let user = req
+            .extensions()
+            .get::<JWTAuthMiddleware>()
+            .ok_or_else(|| {
+                HttpError::unauthorized(ErrorMessage::UserNotAuthenticated.to_string())
+            })?;
+
+        required_role.contains(&user.user.role)
+    };
+
+    if !has_role {
         return Err(HttpError::new(ErrorMessage::PermissionDenied.to_string(), StatusCode::FORBIDDEN));
     }
 
     Ok(next.run(req).await)
-}
\ No newline at end of file
+}
+
+/// Header a reverse proxy sets to require roles on a forward-auth check.
+pub const REQUIRED_ROLE_HEADER: &str = "x-required-role";
+
+/// Runs `role_check` with the roles named in the `X-Required-Role` header
+/// or `role` query parameter, comma separated. Without either, any
+/// authenticated user passes. Setting both to different values, or either
+/// to a blank value, is rejected rather than letting one quietly win.
+pub async fn required_role_check(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Query(query): Query<ForwardAuthQueryDto>,
+    req: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let header = match req.headers().get(REQUIRED_ROLE_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| HttpError::bad_request("Invalid X-Required-Role header".to_string()))?
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    let roles = match (header, query.role) {
+        (None, None) => return Ok(next.run(req).await),
+        (Some(header), Some(role)) if header.trim() != role.trim() => {
+            return Err(HttpError::bad_request(
+                "X-Required-Role header and role parameter disagree".to_string(),
+            ));
+        },
+        (Some(roles), _) | (None, Some(roles)) => roles,
+    };
+
+    let required_role = roles
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .map(|role| {
+            UserRole::parse(role)
+                .ok_or_else(|| HttpError::bad_request(format!("Unknown role: {}", role)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if required_role.is_empty() {
+        return Err(HttpError::bad_request("Required role must not be blank".to_string()));
+    }
+
+    role_check(Extension(app_state), req, next, required_role)
+        .await
+        .map(IntoResponse::into_response)
+}
+
+/// Requires personal access tokens and service accounts to carry `scope`.
+/// Interactive logins are not limited by token scopes.
+pub async fn scope_check(
+    req: Request,
+    next: Next,
+    scope: &'static str,
+) -> Result<impl IntoResponse, HttpError> {
+    let missing_scope = scoped_claims(&req)
+        .is_some_and(|claims| !claims.scope.split_whitespace().any(|granted| granted == scope));
+
+    if missing_scope {
+        return Err(HttpError::new(ErrorMessage::InsufficientScope(scope.to_string()).to_string(), StatusCode::FORBIDDEN));
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Rejects personal access tokens and service accounts, for routes that
+/// manage credentials and sessions.
+pub async fn session_only(
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, HttpError> {
+    if scoped_claims(&req).is_some() {
+        return Err(HttpError::new(ErrorMessage::AccessTokenNotAllowed.to_string(), StatusCode::FORBIDDEN));
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// The claims of a scoped principal, or `None` for an interactive login.
+/// With `JWT_TRUST_CLAIMS` the principal isn't loaded, so service accounts
+/// are recognised by their `client_id` claim.
+fn scoped_claims(req: &Request) -> Option<&TokenClaims> {
+    match req.extensions().get::<JWTAuthMiddleware>() {
+        Some(auth) => auth.is_scoped().then_some(&auth.claims),
+        None => req
+            .extensions()
+            .get::<TokenClaims>()
+            .filter(|claims| claims.client_id.is_some()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{config::Config, test_support};
+
+    async fn list_users(router: &axum::Router, token: &str) -> StatusCode {
+        let request = test_support::json_request(Method::GET, "/api/users/users", Some(token), json!({}));
+
+        test_support::send(router, request).await.status()
+    }
+
+    #[sqlx::test]
+    async fn checks_roles_against_the_database_by_default(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "promoted@example.com", "password1").await;
+        let token = test_support::login(&router, "promoted@example.com", "password1").await;
+        assert_eq!(list_users(&router, &token).await, StatusCode::FORBIDDEN);
+
+        app_state.db_client.update_user_role(user.id, UserRole::Admin).await.unwrap();
+        assert_eq!(list_users(&router, &token).await, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn trusts_token_claims_until_they_expire(pool: PgPool) {
+        let config = Config { jwt_trust_claims: true, ..test_support::config() };
+        let app_state = test_support::app_state(config, pool);
+        let router = test_support::router(app_state.clone());
+
+        let user = test_support::create_user(&app_state, "trusted@example.com", "password1").await;
+        let token = test_support::login(&router, "trusted@example.com", "password1").await;
+
+        app_state.db_client.update_user_role(user.id, UserRole::Admin).await.unwrap();
+        assert_eq!(list_users(&router, &token).await, StatusCode::FORBIDDEN);
+
+        let request = test_support::json_request(Method::GET, "/api/users/me", Some(&token), json!({}));
+        let response = test_support::send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test_support::body_json(response).await["user"]["user"]["role"], "admin");
+    }
+
+    #[sqlx::test]
+    async fn inspecting_a_token_does_not_count_as_activity(pool: PgPool) {
+        let app_state = test_support::app_state(test_support::config(), pool);
+        let router = test_support::router(app_state.clone());
+        let db = &app_state.db_client;
+
+        let user = test_support::create_user(&app_state, "inspect@example.com", "password1").await;
+        let token = test_support::login(&router, "inspect@example.com", "password1").await;
+
+        let (access_token, token_prefix) = token::generate_access_token();
+        let token_hash = token::hash_token(&access_token);
+        db.save_access_token(user.id, "CI", &token_prefix, &token_hash, &[], Utc::now() + Duration::days(1))
+            .await
+            .unwrap();
+
+        let auth = inspect_token(&app_state, &token).await.unwrap();
+        let last_seen_at = db.get_active_session(auth.session_id).await.unwrap().unwrap().last_seen_at;
+
+        inspect_token(&app_state, &token).await.unwrap();
+        assert_eq!(db.get_active_session(auth.session_id).await.unwrap().unwrap().last_seen_at, last_seen_at);
+
+        verify_token(&app_state, &token).await.unwrap();
+        assert!(db.get_active_session(auth.session_id).await.unwrap().unwrap().last_seen_at > last_seen_at);
+
+        inspect_token(&app_state, &access_token).await.unwrap();
+        assert_eq!(db.get_active_access_token(&token_hash).await.unwrap().unwrap().last_used_at, None);
+
+        verify_token(&app_state, &access_token).await.unwrap();
+        assert!(db.get_active_access_token(&token_hash).await.unwrap().unwrap().last_used_at.is_some());
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{ DateTime, Utc };
+use chrono::{ DateTime, Duration, Utc };
 use serde::{ Deserialize, Serialize };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, sqlx::Type, PartialEq, Eq, PartialOrd, Ord)]
@@ -9,12 +9,20 @@ pub enum UserRole {
 }
 
 impl UserRole {
-    pub fn to_str(&self) -> &str {
+    pub fn to_str(self) -> &'static str {
         match self {
             UserRole::Admin => "admin",
             UserRole::User => "user",
         }
     }
+
+    pub fn parse(value: &str) -> Option<UserRole> {
+        match value.to_ascii_lowercase().as_str() {
+            "admin" => Some(UserRole::Admin),
+            "user" => Some(UserRole::User),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, sqlx::Type)]
@@ -25,10 +33,228 @@ pub struct User {
     pub password: String,
     pub role: UserRole,
     pub verified: bool,
-    pub verification_token: Option<String>,
-    pub token_expires_at: Option<DateTime<Utc>>,
+    /// Non-human principal that authenticates with the client_credentials
+    /// grant instead of a password.
+    #[serde(rename = "serviceAccount")]
+    pub service_account: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct RefreshToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub family_id: uuid::Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub replaced_by: Option<uuid::Uuid>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct Session {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastSeenAt")]
+    pub last_seen_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct UserTotp {
+    pub user_id: uuid::Uuid,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_used_step: Option<i64>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct RecoveryCode {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub code_hash: String,
+    pub used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct WebauthnCredential {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Links an account at an external identity provider to a user.
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct Identity {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct WebauthnChallenge {
+    pub id: uuid::Uuid,
+    pub user_id: Option<uuid::Uuid>,
+    pub challenge: String,
+    pub ceremony: String,
+    pub expires_at: DateTime<Utc>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, sqlx::Type, PartialEq, Eq)]
+#[sqlx(type_name = "token_purpose", rename_all = "snake_case")]
+pub enum TokenPurpose {
+    EmailVerification,
+    PasswordReset,
+    MagicLink,
+}
+
+impl TokenPurpose {
+    /// How long a token issued for this purpose stays valid.
+    pub fn max_age(self) -> Duration {
+        match self {
+            TokenPurpose::EmailVerification => Duration::hours(24),
+            TokenPurpose::PasswordReset => Duration::minutes(30),
+            TokenPurpose::MagicLink => Duration::minutes(15),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct OneTimeToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub purpose: TokenPurpose,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct PersonalAccessToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub name: String,
+    pub token_prefix: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct OAuthClient {
+    pub id: uuid::Uuid,
+    pub client_id: String,
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub owner_id: Option<uuid::Uuid>,
+    /// Set for the credentials of a service account, which may only use
+    /// the client_credentials grant.
+    pub service_account_id: Option<uuid::Uuid>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl OAuthClient {
+    /// Confidential clients authenticate with a secret; public clients
+    /// rely on PKCE alone.
+    pub fn is_confidential(&self) -> bool {
+        self.client_secret_hash.is_some()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct OAuthConsent {
+    pub user_id: uuid::Uuid,
+    pub client_id: uuid::Uuid,
+    pub scopes: Vec<String>,
     #[serde(rename = "createdAt")]
     pub created_at: Option<DateTime<Utc>>,
     #[serde(rename = "updatedAt")]
     pub updated_at: Option<DateTime<Utc>>,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct OAuthAuthorizationCode {
+    pub id: uuid::Uuid,
+    pub code_hash: String,
+    pub client_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub grant_id: uuid::Uuid,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+    pub nonce: Option<String>,
+    pub auth_time: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct OAuthRefreshToken {
+    pub id: uuid::Uuid,
+    pub client_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub grant_id: uuid::Uuid,
+    pub token_hash: String,
+    pub scope: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub replaced_by: Option<uuid::Uuid>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A service account together with the OAuth client it authenticates as.
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct ServiceAccount {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub role: UserRole,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
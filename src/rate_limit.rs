@@ -0,0 +1,435 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension
+};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::Config,
+    database::DBClient,
+    error::{ErrorMessage, HttpError},
+    middleware::JWTAuthMiddleware,
+    utils::{throttle::StoreError, token::TokenClaims},
+    AppState
+};
+
+/// Largest request body buffered when a policy is keyed by email.
+const MAX_BUFFERED_BODY: usize = 64 * 1024;
+
+/// What a policy counts requests against.
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimitKey {
+    /// The client IP address.
+    Ip,
+    /// The authenticated user; the route must sit behind the `auth` middleware.
+    User,
+    /// The `email` field of a JSON body.
+    Email,
+}
+
+/// A rate-limit policy evaluated with GCRA: one request is allowed every
+/// `period / limit`, with up to `burst` requests at once.
+#[derive(Debug, Clone)]
+pub struct RateLimitPolicy {
+    pub name: &'static str,
+    pub key: RateLimitKey,
+    pub limit: u32,
+    pub period: Duration,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub remaining: u32,
+    pub reset: u64,
+    pub retry_after: u64,
+}
+
+impl RateLimitPolicy {
+    /// Allows `limit` requests per `period`, all of which may be spent at once.
+    pub fn gcra(name: &'static str, limit: u32, period: Duration, key: RateLimitKey) -> Self {
+        Self {
+            name,
+            key,
+            limit,
+            period,
+            burst: limit,
+        }
+    }
+
+    /// A bucket of `capacity` tokens, refilled with one token every `refill_interval`.
+    pub fn token_bucket(name: &'static str, capacity: u32, refill_interval: Duration, key: RateLimitKey) -> Self {
+        Self {
+            name,
+            key,
+            limit: 1,
+            period: refill_interval,
+            burst: capacity,
+        }
+    }
+
+    fn emission_interval(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.period / self.limit.max(1))
+            .unwrap_or(chrono::Duration::MAX)
+    }
+
+    fn window(&self) -> chrono::Duration {
+        self.emission_interval() * self.burst.max(1) as i32
+    }
+
+    /// Evaluates a request against the stored theoretical arrival time and
+    /// returns the decision along with the arrival time to store, if any.
+    pub fn evaluate(
+        &self,
+        tat: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> (RateLimitDecision, Option<DateTime<Utc>>) {
+        let interval = self.emission_interval();
+        let tat = tat.filter(|tat| *tat > now).unwrap_or(now);
+        let new_tat = tat + interval;
+        let allow_at = new_tat - self.window();
+
+        if now < allow_at {
+            let decision = RateLimitDecision {
+                allowed: false,
+                remaining: 0,
+                reset: seconds_until(tat, now),
+                retry_after: seconds_until(allow_at, now).max(1),
+            };
+
+            return (decision, None);
+        }
+
+        let remaining = (now + self.window() - new_tat).num_milliseconds()
+            / interval.num_milliseconds().max(1);
+
+        let decision = RateLimitDecision {
+            allowed: true,
+            remaining: remaining as u32,
+            reset: seconds_until(new_tat, now),
+            retry_after: 0,
+        };
+
+        (decision, Some(new_tat))
+    }
+}
+
+/// Storage backend for rate-limit state.
+#[async_trait]
+pub trait RateLimitStore: fmt::Debug + Send + Sync {
+    /// Atomically evaluates `policy` for `key` and records the request if it
+    /// is allowed.
+    async fn check(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitDecision, StoreError>;
+}
+
+/// Keeps arrival times in this process, so a restart resets every limit and
+/// each instance enforces its own. Keys whose arrival time has passed are
+/// swept once the map holds more than 10 000 of them.
+#[derive(Debug, Default)]
+pub struct MemoryRateLimitStore {
+    arrivals: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn check(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitDecision, StoreError> {
+        let now = Utc::now();
+        let mut arrivals = self.arrivals.lock().unwrap();
+
+        if arrivals.len() > 10_000 {
+            arrivals.retain(|_, tat| *tat > now);
+        }
+
+        let (decision, tat) = policy.evaluate(arrivals.get(key).copied(), now);
+
+        if let Some(tat) = tat {
+            arrivals.insert(key.to_string(), tat);
+        }
+
+        Ok(decision)
+    }
+}
+
+pub fn store_from_config(
+    config: &Config,
+    db_client: DBClient,
+) -> Result<Arc<dyn RateLimitStore>, Box<dyn std::error::Error>> {
+    match config.rate_limit_backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryRateLimitStore::default())),
+        "postgres" => Ok(Arc::new(db_client)),
+        backend => Err(format!("Unsupported RATE_LIMIT_BACKEND: {}", backend).into()),
+    }
+}
+
+/// Applies a `RateLimitPolicy` to a route:
+///
+/// `.layer(middleware::from_fn_with_state(policy, rate_limit))`
+pub async fn rate_limit(
+    State(policy): State<RateLimitPolicy>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let (req, subject) = rate_limit_subject(policy.key, req).await?;
+
+    let decision = app_state.rate_limit_store
+        .check(&format!("{}:{}", policy.name, subject), &policy)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut response = if decision.allowed {
+        next.run(req).await
+    } else {
+        HttpError::too_many_requests(ErrorMessage::RateLimitExceeded.to_string(), decision.retry_after)
+            .into_response()
+    };
+
+    set_rate_limit_headers(response.headers_mut(), &policy, &decision);
+
+    Ok(response)
+}
+
+async fn rate_limit_subject(
+    key: RateLimitKey,
+    req: Request,
+) -> Result<(Request, String), HttpError> {
+    let ip = req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    match key {
+        RateLimitKey::Ip => Ok((req, format!("ip:{}", ip))),
+        RateLimitKey::User => {
+            let user_id = req.extensions()
+                .get::<JWTAuthMiddleware>()
+                .map(|auth| auth.user.id.to_string())
+                .or_else(|| req.extensions().get::<TokenClaims>().map(|claims| claims.sub.clone()));
+
+            let subject = match user_id {
+                Some(user_id) => format!("user:{}", user_id),
+                None => format!("ip:{}", ip),
+            };
+
+            Ok((req, subject))
+        },
+        RateLimitKey::Email => {
+            let (parts, body) = req.into_parts();
+            let bytes = to_bytes(body, MAX_BUFFERED_BODY)
+                .await
+                .map_err(|_| HttpError::bad_request("Request body is too large".to_string()))?;
+
+            let email = serde_json::from_slice::<serde_json::Value>(&bytes)
+                .ok()
+                .and_then(|body| body.get("email")?.as_str().map(|email| email.trim().to_lowercase()));
+
+            let subject = match email {
+                Some(email) if !email.is_empty() => format!("email:{}", email),
+                _ => format!("ip:{}", ip),
+            };
+
+            Ok((Request::from_parts(parts, Body::from(bytes)), subject))
+        },
+    }
+}
+
+/// Sets the `RateLimit-*` headers, keeping those of a stricter policy
+/// already applied further in.
+fn set_rate_limit_headers(
+    headers: &mut HeaderMap,
+    policy: &RateLimitPolicy,
+    decision: &RateLimitDecision,
+) {
+    let stricter = headers
+        .get("ratelimit-remaining")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u32>().ok())
+        .is_some_and(|remaining| remaining <= decision.remaining);
+
+    if stricter {
+        return;
+    }
+
+    let policy_value = format!("{};w={}", policy.burst, policy.window().num_seconds());
+
+    headers.insert("ratelimit-limit", HeaderValue::from(policy.burst));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(decision.reset));
+
+    if let Ok(policy_value) = HeaderValue::from_str(&policy_value) {
+        headers.insert("ratelimit-policy", policy_value);
+    }
+}
+
+fn seconds_until(at: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    let milliseconds = (at - now).num_milliseconds().max(0);
+
+    (milliseconds as u64).div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header, Request, StatusCode},
+        middleware,
+        routing::get,
+        Router
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_support;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    /// Runs requests at the given times, carrying the stored arrival time
+    /// between them like a store would.
+    fn run(policy: &RateLimitPolicy, times: &[i64]) -> Vec<RateLimitDecision> {
+        let mut tat = None;
+
+        times
+            .iter()
+            .map(|seconds| {
+                let (decision, new_tat) = policy.evaluate(tat, at(*seconds));
+                tat = new_tat.or(tat);
+                decision
+            })
+            .collect()
+    }
+
+    #[test]
+    fn token_bucket_allows_a_burst_then_denies() {
+        let policy = RateLimitPolicy::token_bucket("test", 3, Duration::from_secs(10), RateLimitKey::Ip);
+        let decisions = run(&policy, &[0, 0, 0, 0]);
+
+        let allowed: Vec<_> = decisions.iter().map(|decision| decision.allowed).collect();
+        assert_eq!(allowed, [true, true, true, false]);
+
+        let remaining: Vec<_> = decisions.iter().map(|decision| decision.remaining).collect();
+        assert_eq!(remaining, [2, 1, 0, 0]);
+
+        let denied = decisions[3];
+        assert_eq!(denied.retry_after, 10);
+        assert_eq!(denied.reset, 30);
+    }
+
+    #[test]
+    fn token_bucket_refills_one_token_per_interval() {
+        let policy = RateLimitPolicy::token_bucket("test", 3, Duration::from_secs(10), RateLimitKey::Ip);
+
+        let decisions = run(&policy, &[0, 0, 0, 9, 10, 10]);
+        let allowed: Vec<_> = decisions.iter().map(|decision| decision.allowed).collect();
+        assert_eq!(allowed, [true, true, true, false, true, false]);
+        assert_eq!(decisions[3].retry_after, 1);
+
+        // A bucket left alone long enough is full again.
+        let decisions = run(&policy, &[0, 0, 0, 30]);
+        assert!(decisions[3].allowed);
+        assert_eq!(decisions[3].remaining, 2);
+    }
+
+    #[test]
+    fn gcra_spreads_the_limit_over_the_period() {
+        let policy = RateLimitPolicy::gcra("test", 60, Duration::from_secs(60), RateLimitKey::Ip);
+
+        let burst: Vec<_> = (0..60).map(|_| 0).collect();
+        let decisions = run(&policy, &[burst, vec![0, 1, 1]].concat());
+
+        assert!(decisions[..60].iter().all(|decision| decision.allowed));
+        assert_eq!(decisions[59].remaining, 0);
+        assert!(!decisions[60].allowed);
+        assert_eq!(decisions[60].retry_after, 1);
+        assert!(decisions[61].allowed);
+        assert!(!decisions[62].allowed);
+    }
+
+    #[test]
+    fn denied_requests_are_not_recorded() {
+        let policy = RateLimitPolicy::token_bucket("test", 1, Duration::from_secs(10), RateLimitKey::Ip);
+
+        let (_, tat) = policy.evaluate(None, at(0));
+        let (decision, denied_tat) = policy.evaluate(tat, at(5));
+
+        assert!(!decision.allowed);
+        assert_eq!(denied_tat, None);
+        assert!(policy.evaluate(tat, at(10)).0.allowed);
+    }
+
+    fn router(policies: &[RateLimitPolicy]) -> Router {
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let app_state = test_support::app_state(test_support::config(), pool);
+
+        let router = policies
+            .iter()
+            .fold(Router::new().route("/", get(|| async { "ok" })), |router, policy| {
+                router.layer(middleware::from_fn_with_state(policy.clone(), rate_limit))
+            });
+
+        test_support::router_with(router.layer(Extension(app_state)))
+    }
+
+    async fn get_root(router: &Router) -> Response {
+        test_support::send(router, Request::get("/").body(Body::empty()).unwrap()).await
+    }
+
+    fn header_value<'a>(response: &'a Response, name: &str) -> &'a str {
+        response.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sets_rate_limit_and_retry_after_headers() {
+        let policy = RateLimitPolicy::token_bucket("headers", 2, Duration::from_secs(30), RateLimitKey::Ip);
+        let router = router(&[policy]);
+
+        let response = get_root(&router).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header_value(&response, "ratelimit-limit"), "2");
+        assert_eq!(header_value(&response, "ratelimit-remaining"), "1");
+        assert_eq!(header_value(&response, "ratelimit-reset"), "30");
+        assert_eq!(header_value(&response, "ratelimit-policy"), "2;w=60");
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+
+        get_root(&router).await;
+
+        let response = get_root(&router).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header_value(&response, "ratelimit-remaining"), "0");
+        assert_eq!(header_value(&response, "ratelimit-reset"), "60");
+        assert_eq!(header_value(&response, header::RETRY_AFTER.as_str()), "30");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_headers_of_the_stricter_policy() {
+        let strict = RateLimitPolicy::token_bucket("strict", 2, Duration::from_secs(10), RateLimitKey::Ip);
+        let loose = RateLimitPolicy::gcra("loose", 100, Duration::from_secs(60), RateLimitKey::Ip);
+        let router = router(&[strict, loose]);
+
+        let response = get_root(&router).await;
+        assert_eq!(header_value(&response, "ratelimit-limit"), "2");
+        assert_eq!(header_value(&response, "ratelimit-remaining"), "1");
+    }
+}
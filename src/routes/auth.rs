@@ -1,12 +1,84 @@
-use axum::{routing::{get, post}, Router};
+use std::time::Duration;
 
-use crate::controller::auth::{forgot_password, login, register, reset_password, verify_email};
+use axum::{middleware, routing::{any, get, post}, Router};
+
+use crate::controller::auth::{check, forgot_password, login, login_mfa, logout, logout_all, refresh, register, request_magic_link, reset_password, verify_email, verify_magic_link};
+use crate::controller::passkey::{passkey_login, passkey_login_options};
+use crate::controller::social::{get_providers, social_callback, social_link, social_login};
+use crate::middleware::{auth, required_role_check, session_only};
+use crate::rate_limit::{rate_limit, RateLimitKey, RateLimitPolicy};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const FIFTEEN_MINUTES: Duration = Duration::from_secs(15 * 60);
 
 pub fn auth_handler() -> Router {
+    let register_limit = RateLimitPolicy::gcra("register", 5, HOUR, RateLimitKey::Ip);
+    let verify_limit = RateLimitPolicy::gcra("verify", 20, FIFTEEN_MINUTES, RateLimitKey::Ip);
+    let forgot_password_limit = RateLimitPolicy::gcra("forgot-password", 3, HOUR, RateLimitKey::Email);
+    let forgot_password_ip_limit = RateLimitPolicy::gcra("forgot-password-ip", 10, HOUR, RateLimitKey::Ip);
+    let magic_link_limit = RateLimitPolicy::gcra("magic-link", 3, HOUR, RateLimitKey::Email);
+    let magic_link_ip_limit = RateLimitPolicy::gcra("magic-link-ip", 10, HOUR, RateLimitKey::Ip);
+    let passkey_options_limit = RateLimitPolicy::gcra("passkey-options", 30, FIFTEEN_MINUTES, RateLimitKey::Ip);
+
     Router::new()
-        .route("/register", post(register))
+        .route(
+            "/register",
+            post(register)
+            .layer(middleware::from_fn_with_state(register_limit, rate_limit))
+        )
         .route("/login", post(login))
-        .route("/verify", get(verify_email))
-        .route("/forgot-password", post(forgot_password))
+        .route("/login/mfa", post(login_mfa))
+        .route(
+            "/magic-link",
+            post(request_magic_link)
+            .layer(middleware::from_fn_with_state(magic_link_limit, rate_limit))
+            .layer(middleware::from_fn_with_state(magic_link_ip_limit, rate_limit))
+        )
+        .route("/magic-link/verify", get(verify_magic_link))
+        .route(
+            "/passkey/options",
+            post(passkey_login_options)
+            .layer(middleware::from_fn_with_state(passkey_options_limit, rate_limit))
+        )
+        .route("/passkey/login", post(passkey_login))
+        .route(
+            "/check",
+            any(check)
+            .layer(middleware::from_fn(required_role_check))
+            .layer(middleware::from_fn(auth))
+        )
+        .route("/social", get(get_providers))
+        .route("/social/{provider}", get(social_login))
+        .route("/social/{provider}/callback", get(social_callback))
+        .route(
+            "/social/{provider}/link",
+            get(social_link)
+            .layer(middleware::from_fn(session_only))
+            .layer(middleware::from_fn(auth))
+        )
+        .route("/refresh", post(refresh))
+        .route(
+            "/logout",
+            post(logout)
+            .layer(middleware::from_fn(session_only))
+            .layer(middleware::from_fn(auth))
+        )
+        .route(
+            "/logout-all",
+            post(logout_all)
+            .layer(middleware::from_fn(session_only))
+            .layer(middleware::from_fn(auth))
+        )
+        .route(
+            "/verify",
+            get(verify_email)
+            .layer(middleware::from_fn_with_state(verify_limit, rate_limit))
+        )
+        .route(
+            "/forgot-password",
+            post(forgot_password)
+            .layer(middleware::from_fn_with_state(forgot_password_limit, rate_limit))
+            .layer(middleware::from_fn_with_state(forgot_password_ip_limit, rate_limit))
+        )
         .route("/reset-password", post(reset_password))
-}
\ No newline at end of file
+}
@@ -1,5 +1,8 @@
 pub mod auth;
+pub mod oauth;
+pub mod saml;
 pub mod user;
+pub mod well_known;
 
 use std::sync::Arc;
 
@@ -10,7 +13,10 @@ use crate::{
     middleware::auth, 
     routes::{
         auth::auth_handler, 
-        user::users_handler
+        oauth::oauth_handler,
+        saml::saml_handler,
+        user::users_handler,
+        well_known::well_known_handler
     }, 
     AppState
 };
@@ -18,13 +24,20 @@ use crate::{
 pub fn create_router(app_state: Arc<AppState>) -> Router {
     let api_route = Router::new()
         .nest("/auth", auth_handler())
+        .nest("/oauth", oauth_handler())
+        .nest("/saml", saml_handler())
         .nest(
             "/users", 
             users_handler()
                 .layer(middleware::from_fn(auth))
         )
         .layer(TraceLayer::new_for_http())
+        .layer(Extension(app_state.clone()));
+
+    let well_known_route = well_known_handler()
         .layer(Extension(app_state));
 
-    Router::new().nest("/api", api_route)
+    Router::new()
+        .nest("/api", api_route)
+        .nest("/.well-known", well_known_route)
 }
\ No newline at end of file
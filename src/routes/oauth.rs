@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use axum::{middleware, routing::{delete, get, post}, Router};
+
+use crate::controller::oauth::{authorize, authorize_decision, delete_client, get_clients, introspect, register_client, revoke, token, userinfo};
+use crate::controller::service_account::{create_service_account, delete_service_account, get_service_accounts};
+use crate::middleware::{auth, role_check, session_only};
+use crate::models::UserRole;
+use crate::rate_limit::{rate_limit, RateLimitKey, RateLimitPolicy};
+
+pub fn oauth_handler() -> Router {
+    let token_limit = RateLimitPolicy::gcra("oauth-token", 30, Duration::from_secs(60), RateLimitKey::Ip);
+
+    let client_routes = Router::new()
+        .route("/clients", get(get_clients).post(register_client))
+        .route("/clients/{id}", delete(delete_client))
+        .route("/service-accounts", get(get_service_accounts).post(create_service_account))
+        .route("/service-accounts/{id}", delete(delete_service_account))
+        .route_layer(middleware::from_fn(|state, req, next| {
+            role_check(state, req, next, vec![UserRole::Admin])
+        }))
+        .route_layer(middleware::from_fn(session_only))
+        .route_layer(middleware::from_fn(auth));
+
+    Router::new()
+        .route("/authorize", get(authorize).post(authorize_decision))
+        .route(
+            "/token",
+            post(token)
+            .layer(middleware::from_fn_with_state(token_limit, rate_limit))
+        )
+        .route("/introspect", post(introspect))
+        .route("/revoke", post(revoke))
+        .route("/userinfo", get(userinfo).post(userinfo))
+        .merge(client_routes)
+}
@@ -0,0 +1,10 @@
+use axum::{routing::{get, post}, Router};
+
+use crate::controller::saml::{assertion_consumer_service, metadata, saml_login};
+
+pub fn saml_handler() -> Router {
+    Router::new()
+        .route("/metadata", get(metadata))
+        .route("/login", get(saml_login))
+        .route("/acs", post(assertion_consumer_service))
+}
@@ -1,11 +1,42 @@
-use axum::{routing::{get, put}, Router};
+use std::time::Duration;
+
+use axum::{routing::{delete, get, post, put}, Router};
 use axum::middleware;
 
-use crate::controller::user::{get_me, get_users, update_user_name, update_user_role, update_user_password};
-use crate::middleware::role_check;
+use crate::controller::access_token::{create_access_token, get_access_tokens, revoke_access_token};
+use crate::controller::user::{get_me, get_sessions, get_users, import_users, revoke_session, update_user_name, update_user_role, update_user_password};
+use crate::controller::social::{get_identities, unlink_identity};
+use crate::controller::passkey::{delete_passkey, get_passkeys, passkey_register, passkey_register_options};
+use crate::controller::two_factor::{confirm_two_factor, disable_two_factor, regenerate_recovery_codes, setup_two_factor};
+use crate::middleware::{role_check, scope_check, session_only};
 use crate::models::UserRole;
+use crate::rate_limit::{rate_limit, RateLimitKey, RateLimitPolicy};
 
 pub fn users_handler() -> Router {
+    let two_factor_limit = RateLimitPolicy::token_bucket("2fa-confirm", 5, Duration::from_secs(60), RateLimitKey::User);
+
+    // Credentials and sessions can only be managed from an interactive login.
+    let account_routes = Router::new()
+        .route("/role", put(update_user_role))
+        .route("/password", put(update_user_password))
+        .route("/me/sessions", get(get_sessions))
+        .route("/me/sessions/{session_id}", delete(revoke_session))
+        .route("/me/2fa", post(setup_two_factor).delete(disable_two_factor))
+        .route(
+            "/me/2fa/confirm",
+            post(confirm_two_factor)
+            .layer(middleware::from_fn_with_state(two_factor_limit, rate_limit))
+        )
+        .route("/me/2fa/recovery-codes", post(regenerate_recovery_codes))
+        .route("/me/passkeys", get(get_passkeys).post(passkey_register))
+        .route("/me/passkeys/options", post(passkey_register_options))
+        .route("/me/passkeys/{passkey_id}", delete(delete_passkey))
+        .route("/me/identities", get(get_identities))
+        .route("/me/identities/{identity_id}", delete(unlink_identity))
+        .route("/me/tokens", get(get_access_tokens).post(create_access_token))
+        .route("/me/tokens/{token_id}", delete(revoke_access_token))
+        .route_layer(middleware::from_fn(session_only));
+
     Router::new()
         .route(
             "/me", 
@@ -13,6 +44,7 @@ pub fn users_handler() -> Router {
             .layer(middleware::from_fn(|state, req, next| {
                 role_check(state, req, next, vec![UserRole::Admin, UserRole::User])
             }))
+            .layer(middleware::from_fn(|req, next| scope_check(req, next, "profile:read")))
         )
         .route(
             "/users", 
@@ -20,8 +52,20 @@ pub fn users_handler() -> Router {
             .layer(middleware::from_fn(|state, req, next| {
                 role_check(state, req, next, vec![UserRole::Admin])
             }))
+            .layer(middleware::from_fn(|req, next| scope_check(req, next, "users:read")))
         )
-        .route("/name", put(update_user_name))
-        .route("/role", put(update_user_role))
-        .route("/password", put(update_user_password))
-}
\ No newline at end of file
+        .route(
+            "/users/import",
+            post(import_users)
+            .layer(middleware::from_fn(|state, req, next| {
+                role_check(state, req, next, vec![UserRole::Admin])
+            }))
+            .layer(middleware::from_fn(|req, next| scope_check(req, next, "users:write")))
+        )
+        .route(
+            "/name",
+            put(update_user_name)
+            .layer(middleware::from_fn(|req, next| scope_check(req, next, "profile:write")))
+        )
+        .merge(account_routes)
+}
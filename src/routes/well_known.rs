@@ -0,0 +1,9 @@
+use axum::{routing::get, Router};
+
+use crate::controller::well_known::{jwks, openid_configuration};
+
+pub fn well_known_handler() -> Router {
+    Router::new()
+        .route("/jwks.json", get(jwks))
+        .route("/openid-configuration", get(openid_configuration))
+}
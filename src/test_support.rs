@@ -0,0 +1,160 @@
+//! Shared setup for tests that send requests through the router against a
+//! database created by `#[sqlx::test]`.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Once}
+};
+
+use axum::{
+    body::{self, Body},
+    extract::connect_info::MockConnectInfo,
+    http::{header, Method, Request},
+    response::Response,
+    Router
+};
+use p256::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{
+    config::Config,
+    database::{DBClient, UserExt},
+    models::{User, UserRole},
+    routes::create_router,
+    AppState
+};
+
+static ENV: Once = Once::new();
+
+/// Reads the config from the environment like `run` does, filling in the
+/// required variables and switching every store to its in-memory backend.
+pub fn config() -> Config {
+    ENV.call_once(|| {
+        let defaults = [
+            ("DATABASE_URL", "postgres://postgres@127.0.0.1/auth"),
+            ("JWT_SECRET", "test-secret"),
+            ("JWT_MAXAGE", "60"),
+            ("PORT", "8000"),
+        ];
+
+        for (key, value) in defaults {
+            if std::env::var_os(key).is_none() {
+                std::env::set_var(key, value);
+            }
+        }
+    });
+
+    let mut config = Config::init().unwrap();
+    config.login_throttle_backend = "memory".to_string();
+    config.rate_limit_backend = "memory".to_string();
+    config.revocation_backend = "memory".to_string();
+
+    config
+}
+
+pub fn app_state(config: Config, pool: PgPool) -> Arc<AppState> {
+    Arc::new(AppState::new(config, DBClient::new(pool)).unwrap())
+}
+
+/// Like `app_state`, but signing with an ES256 key as OpenID Connect
+/// requires. The key is passed through temporary PEM files like in
+/// production.
+pub fn es256_app_state(config: Config, pool: PgPool) -> Arc<AppState> {
+    let secret = p256::SecretKey::from_slice(&[7; 32]).unwrap();
+    let dir = std::env::temp_dir();
+    let private_key_path = dir.join(format!("es256-{}.pem", uuid::Uuid::new_v4()));
+    let public_key_path = dir.join(format!("es256-{}.pub.pem", uuid::Uuid::new_v4()));
+
+    std::fs::write(&private_key_path, secret.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    std::fs::write(&public_key_path, secret.public_key().to_public_key_pem(LineEnding::LF).unwrap()).unwrap();
+
+    let config = Config {
+        jwt_algorithm: "ES256".to_string(),
+        jwt_private_key_path: Some(private_key_path.to_string_lossy().into_owned()),
+        jwt_public_key_path: Some(public_key_path.to_string_lossy().into_owned()),
+        ..config
+    };
+    let app_state = app_state(config, pool);
+
+    std::fs::remove_file(private_key_path).unwrap();
+    std::fs::remove_file(public_key_path).unwrap();
+
+    app_state
+}
+
+pub fn router(app_state: Arc<AppState>) -> Router {
+    router_with(create_router(app_state))
+}
+
+/// Makes every request appear to come from the same client address.
+pub fn router_with(router: Router) -> Router {
+    router.layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))))
+}
+
+pub async fn send(router: &Router, request: Request<Body>) -> Response {
+    router.clone().oneshot(request).await.unwrap()
+}
+
+pub fn json_request(method: Method, uri: &str, token: Option<&str>, body: Value) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json");
+
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+pub async fn body_json(response: Response) -> Value {
+    let bytes = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    serde_json::from_slice(&bytes).unwrap_or(Value::Null)
+}
+
+/// A verified user that only exists in memory, for tests that sign tokens
+/// without going through the database.
+pub fn user(role: UserRole) -> User {
+    User {
+        id: uuid::Uuid::new_v4(),
+        name: "Test User".to_string(),
+        email: "user@example.com".to_string(),
+        password: String::new(),
+        role,
+        verified: true,
+        service_account: false,
+        created_at: None,
+        updated_at: None,
+    }
+}
+
+/// Saves a verified user with a local password.
+pub async fn create_user(app_state: &AppState, email: &str, password: &str) -> User {
+    let hash = app_state.password_hashing.hash_password(password).unwrap();
+    let user = app_state.db_client.save_user("Test User", email, &hash).await.unwrap();
+
+    app_state.db_client.verify_user(user.id).await.unwrap();
+
+    User { verified: true, ..user }
+}
+
+/// Logs in with a password and returns the access token.
+pub async fn login(router: &Router, email: &str, password: &str) -> String {
+    login_body(router, email, password).await["token"].as_str().unwrap().to_string()
+}
+
+/// Logs in with a password and returns the response body.
+pub async fn login_body(router: &Router, email: &str, password: &str) -> Value {
+    let request = json_request(
+        Method::POST,
+        "/api/auth/login",
+        None,
+        serde_json::json!({ "email": email, "password": password }),
+    );
+
+    body_json(send(router, request).await).await
+}
@@ -0,0 +1,256 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write}
+};
+
+use sha1::{Digest, Sha1};
+
+const MAGIC: &[u8; 8] = b"HIBPBF01";
+
+/// Magic, hash count and bit count.
+const HEADER_LENGTH: u64 = 8 + 4 + 8;
+
+const MAX_HASHES: u32 = 30;
+
+/// A Bloom filter over SHA-1 password hashes, built from a Have I Been
+/// Pwned style dump (`SHA1HEX:COUNT` per line) so lookups need no network.
+///
+/// False positives are possible at the rate chosen when building the
+/// filter; false negatives are not.
+#[derive(Debug)]
+pub struct BreachFilter {
+    hashes: u32,
+    bits: u64,
+    data: Vec<u8>,
+}
+
+impl BreachFilter {
+    pub fn new(expected_items: u64, false_positive_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = ((-items * false_positive_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = ((bits as f64 / items) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u32;
+
+        Self {
+            hashes,
+            bits,
+            data: vec![0; bits.div_ceil(8) as usize],
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        let file_length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{} is not a breached password filter", path).into());
+        }
+
+        let mut hashes = [0u8; 4];
+        reader.read_exact(&mut hashes)?;
+        let mut bits = [0u8; 8];
+        reader.read_exact(&mut bits)?;
+
+        let hashes = u32::from_le_bytes(hashes);
+        let bits = u64::from_le_bytes(bits);
+
+        // Check the header against the file before trusting it with an
+        // allocation.
+        let data_length = file_length.checked_sub(HEADER_LENGTH);
+
+        if !(1..=MAX_HASHES).contains(&hashes) || bits == 0 || data_length != Some(bits.div_ceil(8)) {
+            return Err(format!("{} is truncated or corrupt", path).into());
+        }
+
+        let mut data = vec![0; bits.div_ceil(8) as usize];
+        reader.read_exact(&mut data)?;
+
+        Ok(Self { hashes, bits, data })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        writer.write_all(&self.bits.to_le_bytes())?;
+        writer.write_all(&self.data)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, sha1: &[u8; 20]) {
+        for bit in self.bit_indexes(sha1) {
+            self.data[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains(&self, sha1: &[u8; 20]) -> bool {
+        self.bit_indexes(sha1)
+            .all(|bit| self.data[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn contains_password(&self, password: &str) -> bool {
+        self.contains(&Sha1::digest(password.as_bytes()).into())
+    }
+
+    /// SHA-1 output is already uniform, so the probe positions are derived
+    /// from it directly by double hashing.
+    fn bit_indexes(&self, sha1: &[u8; 20]) -> impl Iterator<Item = u64> {
+        let h1 = u64::from_le_bytes(sha1[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(sha1[8..16].try_into().unwrap()) | 1;
+        let bits = self.bits;
+
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+}
+
+/// Builds a filter from a dump of `SHA1HEX:COUNT` lines, skipping hashes
+/// seen fewer than `min_count` times. Returns the filter and the number of
+/// hashes added.
+pub fn build_from_dump(
+    dump_path: &str,
+    false_positive_rate: f64,
+    min_count: u64,
+) -> Result<(BreachFilter, u64), Box<dyn std::error::Error>> {
+    let mut items = 0;
+    for_each_hash(dump_path, min_count, |_| items += 1)?;
+
+    let mut filter = BreachFilter::new(items, false_positive_rate);
+    for_each_hash(dump_path, min_count, |hash| filter.insert(hash))?;
+
+    Ok((filter, items))
+}
+
+fn for_each_hash(
+    dump_path: &str,
+    min_count: u64,
+    mut f: impl FnMut(&[u8; 20]),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(dump_path)?);
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let (hash, count) = line.split_once(':').unwrap_or((line, "1"));
+
+        let count: u64 = count.trim().parse()
+            .map_err(|_| format!("Invalid count on line {}", number + 1))?;
+        if count < min_count {
+            continue;
+        }
+
+        let mut sha1 = [0u8; 20];
+        hex::decode_to_slice(hash, &mut sha1)
+            .map_err(|_| format!("Invalid SHA-1 hash on line {}", number + 1))?;
+
+        f(&sha1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("breach-filter-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn sha1_hex(password: &str) -> String {
+        hex::encode_upper(Sha1::digest(password.as_bytes()))
+    }
+
+    fn write_filter(name: &str, hashes: u32, bits: u64, data: &[u8]) -> String {
+        let path = temp_path(name);
+        let mut file = File::create(&path).unwrap();
+
+        file.write_all(MAGIC).unwrap();
+        file.write_all(&hashes.to_le_bytes()).unwrap();
+        file.write_all(&bits.to_le_bytes()).unwrap();
+        file.write_all(data).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let mut filter = BreachFilter::new(100, 0.001);
+        filter.insert(&Sha1::digest(b"password1").into());
+
+        let path = temp_path("round-trip");
+        filter.save(&path).unwrap();
+        let loaded = BreachFilter::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(loaded.contains_password("password1"));
+        assert!(!loaded.contains_password("correct horse battery staple"));
+    }
+
+    #[test]
+    fn builds_from_a_dump_skipping_rare_hashes() {
+        let dump_path = temp_path("dump.txt");
+        let dump = format!(
+            "{}:120\n\n{}:3\n{}\n",
+            sha1_hex("password1"),
+            sha1_hex("rarely-used"),
+            sha1_hex("no-count"),
+        );
+        std::fs::write(&dump_path, dump).unwrap();
+
+        let (filter, items) = build_from_dump(&dump_path, 0.001, 5).unwrap();
+        assert_eq!(items, 1);
+        assert!(filter.contains_password("password1"));
+        assert!(!filter.contains_password("rarely-used"));
+        assert!(!filter.contains_password("no-count"));
+
+        std::fs::write(&dump_path, "not-a-hash:1\n").unwrap();
+        let result = build_from_dump(&dump_path, 0.001, 1);
+        std::fs::remove_file(&dump_path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_filters() {
+        let path = temp_path("not-a-filter");
+        std::fs::write(&path, b"SHA1HEX:COUNT and more").unwrap();
+
+        let result = BreachFilter::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_a_header_that_does_not_match_the_file() {
+        let cases = [
+            ("huge-bits", 7, u64::MAX, vec![0; 8]),
+            ("truncated", 7, 128, vec![0; 8]),
+            ("trailing-data", 7, 64, vec![0; 16]),
+            ("no-hashes", 0, 64, vec![0; 8]),
+            ("too-many-hashes", u32::MAX, 64, vec![0; 8]),
+        ];
+
+        for (name, hashes, bits, data) in cases {
+            let path = write_filter(name, hashes, bits, &data);
+            let result = BreachFilter::load(&path);
+            std::fs::remove_file(&path).unwrap();
+
+            assert!(result.is_err(), "{} was accepted", name);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use std::{fmt, fs, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters,
+        CommonParameters,
+        EllipticCurve,
+        EllipticCurveKeyParameters,
+        EllipticCurveKeyType,
+        Jwk,
+        JwkSet,
+        KeyAlgorithm,
+        OctetKeyPairParameters,
+        OctetKeyPairType,
+        PublicKeyUse,
+        RSAKeyParameters,
+        RSAKeyType
+    },
+    Algorithm,
+    DecodingKey,
+    EncodingKey
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::traits::PublicKeyParts;
+
+use crate::config::Config;
+
+/// A JWT verification key and, when it can sign, the matching signing key.
+/// Asymmetric keys also carry the public JWK published on the JWKS endpoint.
+#[derive(Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding_key: Option<EncodingKey>,
+    pub decoding_key: DecodingKey,
+    pub jwk: Option<Jwk>,
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .field("can_sign", &self.encoding_key.is_some())
+            .finish()
+    }
+}
+
+impl JwtKey {
+    pub fn from_secret(kid: &str, secret: &[u8]) -> Self {
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding_key: Some(EncodingKey::from_secret(secret)),
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    pub fn from_pem(
+        kid: &str,
+        algorithm: Algorithm,
+        private_pem: &[u8],
+        public_pem: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let encoding_key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(private_pem)?,
+            Algorithm::ES256 => EncodingKey::from_ec_pem(private_pem)?,
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(private_pem)?,
+            _ => return Err(format!("Unsupported JWT algorithm: {:?}", algorithm).into()),
+        };
+
+        let mut key = Self::from_public_pem(kid, algorithm, public_pem)?;
+        key.encoding_key = Some(encoding_key);
+
+        Ok(key)
+    }
+
+    /// Loads a verification-only key from a PEM encoded public key.
+    pub fn from_public_pem(
+        kid: &str,
+        algorithm: Algorithm,
+        public_pem: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let decoding_key = match algorithm {
+            Algorithm::RS256 => DecodingKey::from_rsa_pem(public_pem)?,
+            Algorithm::ES256 => DecodingKey::from_ec_pem(public_pem)?,
+            Algorithm::EdDSA => DecodingKey::from_ed_pem(public_pem)?,
+            _ => return Err(format!("Unsupported JWT algorithm: {:?}", algorithm).into()),
+        };
+
+        let jwk = public_jwk(kid, algorithm, std::str::from_utf8(public_pem)?)?;
+
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm,
+            encoding_key: None,
+            decoding_key,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+/// The set of keys used for JWTs: exactly one active key that signs new
+/// tokens, plus verification-only keys that keep tokens signed by retired
+/// keys valid until they expire.
+#[derive(Debug, Clone)]
+pub struct KeyRing {
+    active: JwtKey,
+    verification_keys: Vec<JwtKey>,
+}
+
+impl KeyRing {
+    pub fn new(
+        active: JwtKey,
+        verification_keys: Vec<JwtKey>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if active.encoding_key.is_none() {
+            return Err(format!("Active JWT key {} cannot sign tokens", active.kid).into());
+        }
+
+        let mut kids = vec![active.kid.as_str()];
+        for key in &verification_keys {
+            if kids.contains(&key.kid.as_str()) {
+                return Err(format!("Duplicate JWT key id: {}", key.kid).into());
+            }
+            kids.push(&key.kid);
+        }
+
+        Ok(Self { active, verification_keys })
+    }
+
+    /// Builds the key ring from `Config`. The active key is described by
+    /// `JWT_ALGORITHM`: HS256 uses `JWT_SECRET`, asymmetric algorithms read
+    /// the PEM files from `JWT_PRIVATE_KEY_PATH` and `JWT_PUBLIC_KEY_PATH`.
+    ///
+    /// Retired keys are listed in `JWT_VERIFICATION_KEYS` as comma separated
+    /// `kid:ALGORITHM:path` entries. The file holds the public key PEM, or
+    /// the raw secret for HS256.
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let algorithm = Algorithm::from_str(&config.jwt_algorithm)?;
+
+        let active = if algorithm == Algorithm::HS256 {
+            JwtKey::from_secret(&config.jwt_key_id, config.jwt_secret.as_bytes())
+        } else {
+            let private_key_path = config.jwt_private_key_path.as_ref()
+                .ok_or("JWT_PRIVATE_KEY_PATH is required for asymmetric JWT algorithms")?;
+            let public_key_path = config.jwt_public_key_path.as_ref()
+                .ok_or("JWT_PUBLIC_KEY_PATH is required for asymmetric JWT algorithms")?;
+
+            JwtKey::from_pem(
+                &config.jwt_key_id,
+                algorithm,
+                &fs::read(private_key_path)?,
+                &fs::read(public_key_path)?,
+            )?
+        };
+
+        let mut verification_keys = Vec::new();
+
+        for entry in config.jwt_verification_keys.iter().flat_map(|keys| keys.split(',')) {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+
+            let mut parts = entry.splitn(3, ':');
+            let (Some(kid), Some(algorithm), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(format!("Invalid JWT_VERIFICATION_KEYS entry: {}", entry).into());
+            };
+
+            let algorithm = Algorithm::from_str(algorithm)?;
+            let contents = fs::read(path)?;
+
+            let mut key = if algorithm == Algorithm::HS256 {
+                JwtKey::from_secret(kid, contents.trim_ascii())
+            } else {
+                JwtKey::from_public_pem(kid, algorithm, &contents)?
+            };
+            key.encoding_key = None;
+
+            verification_keys.push(key);
+        }
+
+        Self::new(active, verification_keys)
+    }
+
+    pub fn signing_key(&self) -> &JwtKey {
+        &self.active
+    }
+
+    /// Whether relying parties can verify tokens from the JWKS. ID tokens
+    /// signed with the shared HS256 secret could only be checked by someone
+    /// able to forge them.
+    pub fn is_asymmetric(&self) -> bool {
+        self.active.algorithm != Algorithm::HS256
+    }
+
+    /// Finds the key a token was signed with. Tokens without a `kid` header
+    /// predate the key ring and are checked against the active key.
+    pub fn find(&self, kid: Option<&str>) -> Option<&JwtKey> {
+        match kid {
+            Some(kid) => std::iter::once(&self.active)
+                .chain(self.verification_keys.iter())
+                .find(|key| key.kid == kid),
+            None => Some(&self.active),
+        }
+    }
+
+    /// Public keys of every asymmetric key in the ring.
+    pub fn jwks(&self) -> JwkSet {
+        let keys = std::iter::once(&self.active)
+            .chain(self.verification_keys.iter())
+            .filter_map(|key| key.jwk.clone())
+            .collect();
+
+        JwkSet { keys }
+    }
+}
+
+/// Converts a PEM encoded public key into the JWK published on the JWKS endpoint.
+fn public_jwk(
+    kid: &str,
+    algorithm: Algorithm,
+    public_pem: &str,
+) -> Result<Jwk, Box<dyn std::error::Error>> {
+    let (key_algorithm, algorithm_parameters) = match algorithm {
+        Algorithm::RS256 => {
+            let key = rsa::RsaPublicKey::from_public_key_pem(public_pem)?;
+
+            (KeyAlgorithm::RS256, AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+            }))
+        },
+        Algorithm::ES256 => {
+            let key = p256::PublicKey::from_public_key_pem(public_pem)?;
+            let point = key.to_encoded_point(false);
+            let x = point.x().ok_or("Invalid P-256 public key")?;
+            let y = point.y().ok_or("Invalid P-256 public key")?;
+
+            (KeyAlgorithm::ES256, AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: URL_SAFE_NO_PAD.encode(x),
+                y: URL_SAFE_NO_PAD.encode(y),
+            }))
+        },
+        Algorithm::EdDSA => {
+            let key = ed25519_dalek::VerifyingKey::from_public_key_pem(public_pem)?;
+
+            (KeyAlgorithm::EdDSA, AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key.as_bytes()),
+            }))
+        },
+        _ => return Err(format!("Unsupported JWT algorithm: {:?}", algorithm).into()),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: algorithm_parameters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::pkcs8::{EncodePrivateKey, EncodePublicKey};
+    use p256::pkcs8::LineEnding;
+
+    use crate::{
+        models::UserRole,
+        test_support,
+        utils::token::{self, TokenClaims}
+    };
+
+    use super::*;
+
+    fn es256_key(kid: &str, seed: u8) -> JwtKey {
+        let secret = p256::SecretKey::from_slice(&[seed; 32]).unwrap();
+        let private_pem = secret.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let public_pem = secret.public_key().to_public_key_pem(LineEnding::LF).unwrap();
+
+        JwtKey::from_pem(kid, Algorithm::ES256, private_pem.as_bytes(), public_pem.as_bytes()).unwrap()
+    }
+
+    fn ed25519_key(kid: &str) -> JwtKey {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[9; 32]);
+        let private_pem = signing_key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let public_pem = signing_key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap();
+
+        JwtKey::from_pem(kid, Algorithm::EdDSA, private_pem.as_bytes(), public_pem.as_bytes()).unwrap()
+    }
+
+    fn create_token(key_ring: &KeyRing) -> String {
+        let user = test_support::user(UserRole::User);
+
+        token::create_token(&user, "session-id", token::DEFAULT_SCOPE, key_ring, &test_support::config()).unwrap()
+    }
+
+    fn decode_token(token: &str, key_ring: &KeyRing) -> Result<TokenClaims, crate::error::HttpError> {
+        token::decode_token(token, key_ring, &test_support::config())
+    }
+
+    fn retired(mut key: JwtKey) -> JwtKey {
+        key.encoding_key = None;
+        key
+    }
+
+    #[test]
+    fn signs_and_verifies_with_asymmetric_keys() {
+        for key in [es256_key("es-1", 7), ed25519_key("ed-1")] {
+            let key_ring = KeyRing::new(key, Vec::new()).unwrap();
+            let token = create_token(&key_ring);
+            let header = jsonwebtoken::decode_header(&token).unwrap();
+
+            assert_eq!(header.alg, key_ring.signing_key().algorithm);
+            assert_eq!(header.kid.as_deref(), Some(key_ring.signing_key().kid.as_str()));
+            assert_eq!(decode_token(&token, &key_ring).unwrap().aud, "auth-validator");
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_signed_by_another_key() {
+        let issuer = KeyRing::new(es256_key("es-1", 7), Vec::new()).unwrap();
+        let token = create_token(&issuer);
+
+        let other = KeyRing::new(es256_key("es-1", 8), Vec::new()).unwrap();
+        let symmetric = KeyRing::new(JwtKey::from_secret("es-1", b"secret"), Vec::new()).unwrap();
+
+        assert!(decode_token(&token, &other).is_err());
+        assert!(decode_token(&token, &symmetric).is_err());
+    }
+
+    #[test]
+    fn publishes_only_public_parameters() {
+        let key_ring = KeyRing::new(es256_key("es-1", 7), Vec::new()).unwrap();
+        let jwks = key_ring.jwks();
+        let jwk = &jwks.keys[0];
+
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwk.common.key_id.as_deref(), Some("es-1"));
+        assert_eq!(jwk.common.key_algorithm, Some(KeyAlgorithm::ES256));
+
+        let token = create_token(&key_ring);
+        let mut validation = jsonwebtoken::Validation::new(Algorithm::ES256);
+        validation.set_audience(&["auth-validator"]);
+
+        let claims = jsonwebtoken::decode::<TokenClaims>(
+            &token,
+            &DecodingKey::from_jwk(jwk).unwrap(),
+            &validation,
+        ).unwrap();
+
+        assert_eq!(claims.claims.role, "user");
+        assert!(KeyRing::new(JwtKey::from_secret("hs", b"secret"), Vec::new()).unwrap().jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn verifies_tokens_signed_by_retired_keys() {
+        let old_ring = KeyRing::new(es256_key("2024", 7), Vec::new()).unwrap();
+        let token = create_token(&old_ring);
+
+        let key_ring = KeyRing::new(ed25519_key("2025"), vec![retired(es256_key("2024", 7))]).unwrap();
+        let rotated = create_token(&key_ring);
+
+        assert_eq!(decode_token(&token, &key_ring).unwrap().aud, "auth-validator");
+        assert_eq!(jsonwebtoken::decode_header(&rotated).unwrap().kid.as_deref(), Some("2025"));
+        assert_eq!(key_ring.jwks().keys.len(), 2);
+    }
+
+    #[test]
+    fn rejects_tokens_with_an_unknown_kid() {
+        let old_ring = KeyRing::new(es256_key("2023", 7), Vec::new()).unwrap();
+        let token = create_token(&old_ring);
+
+        let key_ring = KeyRing::new(es256_key("2025", 7), Vec::new()).unwrap();
+
+        assert!(decode_token(&token, &key_ring).is_err());
+    }
+
+    #[test]
+    fn refuses_invalid_key_rings() {
+        assert!(KeyRing::new(retired(es256_key("es-1", 7)), Vec::new()).is_err());
+        assert!(KeyRing::new(es256_key("es-1", 7), vec![retired(es256_key("es-1", 8))]).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use ldap3::{dn_escape, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use serde::Deserialize;
+
+use crate::{config::Config, models::UserRole};
+
+pub type DirectoryError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Identity provider name of accounts provisioned from the directory.
+pub const PROVIDER: &str = "ldap";
+
+/// LDAP result code for a bind with the wrong password or an unknown DN.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// An account as the directory describes it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectoryUser {
+    /// The entry's DN, which links the account to its local user.
+    #[serde(default)]
+    pub dn: String,
+    pub email: String,
+    pub name: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// A corporate directory users can log in with. Returns `None` when the
+/// credentials don't match an account.
+#[async_trait]
+pub trait Directory: fmt::Debug + Send + Sync {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<DirectoryUser>, DirectoryError>;
+}
+
+/// Authenticates against an LDAP server or Active Directory, either by
+/// binding to a DN built from `LDAP_USER_DN_TEMPLATE`, or by searching for
+/// the user with a service account and then binding as the entry found.
+#[derive(Debug)]
+pub struct LdapDirectory {
+    config: Config,
+}
+
+impl LdapDirectory {
+    async fn connect(&self) -> Result<Ldap, DirectoryError> {
+        let timeout = Duration::from_secs(self.config.ldap_timeout);
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(self.config.ldap_starttls);
+
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.ldap_url).await?;
+        ldap3::drive!(conn);
+        ldap.with_timeout(timeout);
+
+        Ok(ldap)
+    }
+
+    async fn bind_service_account(&self, ldap: &mut Ldap) -> Result<(), DirectoryError> {
+        if let Some(bind_dn) = &self.config.ldap_bind_dn {
+            let password = self.config.ldap_bind_password.as_deref().unwrap_or_default();
+            ldap.simple_bind(bind_dn, password).await?.success()?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds the user's entry, binding as them to check the password.
+    async fn find_user(
+        &self,
+        ldap: &mut Ldap,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<SearchEntry>, DirectoryError> {
+        let attributes = [
+            self.config.ldap_email_attribute.as_str(),
+            self.config.ldap_name_attribute.as_str(),
+            self.config.ldap_group_attribute.as_str(),
+        ];
+
+        if let Some(template) = &self.config.ldap_user_dn_template {
+            let dn = user_dn(template, username);
+
+            if !bind_user(ldap, &dn, password).await? {
+                return Ok(None);
+            }
+
+            let (entries, _) = ldap
+                .search(&dn, Scope::Base, "(objectClass=*)", attributes)
+                .await?
+                .success()?;
+
+            return Ok(entries.into_iter().next().map(SearchEntry::construct));
+        }
+
+        self.bind_service_account(ldap).await?;
+
+        let filter = user_filter(&self.config.ldap_user_filter, username);
+        let (entries, _) = ldap
+            .search(&self.config.ldap_base_dn, Scope::Subtree, &filter, attributes)
+            .await?
+            .success()?;
+
+        // An ambiguous filter must not let one user log in as another.
+        if entries.len() != 1 {
+            return Ok(None);
+        }
+
+        let Some(entry) = entries.into_iter().next().map(SearchEntry::construct) else {
+            return Ok(None);
+        };
+
+        if !bind_user(ldap, &entry.dn, password).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(entry))
+    }
+
+    /// Group DNs from the entry's group attribute (`memberOf` in Active
+    /// Directory) and, when configured, a search for groups listing it.
+    async fn find_groups(&self, ldap: &mut Ldap, entry: &SearchEntry) -> Result<Vec<String>, DirectoryError> {
+        let mut groups = attribute(entry, &self.config.ldap_group_attribute).to_vec();
+
+        if let Some(group_base_dn) = &self.config.ldap_group_base_dn {
+            self.bind_service_account(ldap).await?;
+
+            let filter = self.config.ldap_group_filter.replace("{dn}", &ldap_escape(entry.dn.as_str()));
+            let (entries, _) = ldap
+                .search(group_base_dn, Scope::Subtree, &filter, ["1.1"])
+                .await?
+                .success()?;
+
+            groups.extend(entries.into_iter().map(|entry| SearchEntry::construct(entry).dn));
+        }
+
+        Ok(groups)
+    }
+}
+
+#[async_trait]
+impl Directory for LdapDirectory {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<DirectoryUser>, DirectoryError> {
+        // An empty password is an unauthenticated bind, which always succeeds.
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let mut ldap = self.connect().await?;
+
+        let result = async {
+            let Some(entry) = self.find_user(&mut ldap, username, password).await? else {
+                return Ok(None);
+            };
+
+            let email = attribute(&entry, &self.config.ldap_email_attribute)
+                .first()
+                .map(|email| email.to_lowercase())
+                .ok_or_else(|| format!("Directory entry {} has no {} attribute", entry.dn, self.config.ldap_email_attribute))?;
+
+            let name = attribute(&entry, &self.config.ldap_name_attribute)
+                .first()
+                .cloned()
+                .unwrap_or_else(|| email.to_owned());
+
+            let groups = self.find_groups(&mut ldap, &entry).await?;
+
+            Ok(Some(DirectoryUser { dn: entry.dn, email, name, groups }))
+        }.await;
+
+        let _ = ldap.unbind().await;
+
+        result
+    }
+}
+
+/// The DN `LDAP_USER_DN_TEMPLATE` gives a username, escaped so the
+/// username can't add or change RDNs.
+fn user_dn(template: &str, username: &str) -> String {
+    template.replace("{username}", &dn_escape(username))
+}
+
+/// `LDAP_USER_FILTER` for a username, escaped so the username can't widen
+/// the search.
+fn user_filter(filter: &str, username: &str) -> String {
+    filter.replace("{username}", &ldap_escape(username))
+}
+
+/// Binds as `dn`, telling wrong credentials apart from other failures.
+async fn bind_user(ldap: &mut Ldap, dn: &str, password: &str) -> Result<bool, DirectoryError> {
+    match ldap.simple_bind(dn, password).await?.success() {
+        Ok(_) => Ok(true),
+        Err(LdapError::LdapResult { result }) if result.rc == INVALID_CREDENTIALS => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Attribute names are case-insensitive, and servers don't always echo
+/// them back the way they were requested.
+fn attribute<'a>(entry: &'a SearchEntry, name: &str) -> &'a [String] {
+    entry.attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.as_slice())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct MemoryDirectoryEntry {
+    username: String,
+    password: String,
+    #[serde(flatten)]
+    user: DirectoryUser,
+}
+
+/// In-process stand-in for an LDAP server, loaded from a JSON file of
+/// `{ username, password, email, name, groups }` entries. Entries without
+/// a `dn` get `uid=<username>`. For tests only.
+#[derive(Debug)]
+pub struct MemoryDirectory {
+    entries: Vec<MemoryDirectoryEntry>,
+}
+
+#[async_trait]
+impl Directory for MemoryDirectory {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<DirectoryUser>, DirectoryError> {
+        let user = self.entries
+            .iter()
+            .find(|entry| entry.username == username && !password.is_empty() && entry.password == password)
+            .map(|entry| match entry.user.dn.is_empty() {
+                true => DirectoryUser { dn: user_dn("uid={username}", &entry.username), ..entry.user.clone() },
+                false => entry.user.clone(),
+            });
+
+        Ok(user)
+    }
+}
+
+/// The role a directory user gets from their groups, or `None` when no
+/// admin groups are configured and roles are managed locally.
+pub fn map_role(user: &DirectoryUser, config: &Config) -> Option<UserRole> {
+    if config.ldap_admin_groups.is_empty() {
+        return None;
+    }
+
+    if in_any_group(user, &config.ldap_admin_groups) {
+        Some(UserRole::Admin)
+    } else {
+        Some(UserRole::User)
+    }
+}
+
+/// With `LDAP_ALLOWED_GROUPS` set, only members of those groups or of an
+/// admin group may log in.
+pub fn is_allowed(user: &DirectoryUser, config: &Config) -> bool {
+    config.ldap_allowed_groups.is_empty()
+        || in_any_group(user, &config.ldap_allowed_groups)
+        || in_any_group(user, &config.ldap_admin_groups)
+}
+
+fn in_any_group(user: &DirectoryUser, groups: &[String]) -> bool {
+    user.groups
+        .iter()
+        .any(|group| groups.iter().any(|configured| configured.eq_ignore_ascii_case(group)))
+}
+
+pub fn directory_from_config(config: &Config) -> Result<Option<Arc<dyn Directory>>, Box<dyn std::error::Error>> {
+    if !matches!(config.ldap_order.as_str(), "before" | "after") {
+        return Err(format!("Unsupported LDAP_ORDER: {}", config.ldap_order).into());
+    }
+
+    match config.ldap_backend.as_str() {
+        "none" => Ok(None),
+        "ldap" => Ok(Some(Arc::new(LdapDirectory { config: config.clone() }))),
+        "memory" => {
+            let path = config.ldap_memory_directory
+                .as_deref()
+                .ok_or("LDAP_MEMORY_DIRECTORY must be set for the memory LDAP backend")?;
+            let entries = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+            Ok(Some(Arc::new(MemoryDirectory { entries })))
+        },
+        backend => Err(format!("Unsupported LDAP_BACKEND: {}", backend).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support;
+
+    fn directory() -> MemoryDirectory {
+        let entries = serde_json::from_value(json!([
+            {
+                "username": "alice",
+                "password": "wonderland",
+                "email": "alice@corp.com",
+                "name": "Alice",
+                "groups": ["cn=admins,ou=groups,dc=corp,dc=com"],
+            },
+            {
+                "username": "bob,ou=admins",
+                "password": "builder",
+                "dn": "uid=bob,ou=people,dc=corp,dc=com",
+                "email": "bob@corp.com",
+                "name": "Bob",
+            },
+        ]))
+        .unwrap();
+
+        MemoryDirectory { entries }
+    }
+
+    fn user(groups: &[&str]) -> DirectoryUser {
+        DirectoryUser {
+            dn: "uid=carol,ou=people,dc=corp,dc=com".to_string(),
+            email: "carol@corp.com".to_string(),
+            name: "Carol".to_string(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_directory_binds_with_the_right_password_only() {
+        let directory = directory();
+
+        let alice = directory.authenticate("alice", "wonderland").await.unwrap().unwrap();
+        assert_eq!(alice.email, "alice@corp.com");
+        assert_eq!(alice.dn, "uid=alice");
+        assert_eq!(alice.groups, ["cn=admins,ou=groups,dc=corp,dc=com"]);
+
+        let bob = directory.authenticate("bob,ou=admins", "builder").await.unwrap().unwrap();
+        assert_eq!(bob.dn, "uid=bob,ou=people,dc=corp,dc=com");
+
+        assert!(directory.authenticate("alice", "builder").await.unwrap().is_none());
+        assert!(directory.authenticate("mallory", "wonderland").await.unwrap().is_none());
+        assert!(directory.authenticate("alice", "").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_an_empty_password_before_binding() {
+        let mut config = test_support::config();
+        config.ldap_url = "ldap://127.0.0.1:1".to_string();
+
+        // An unauthenticated bind would succeed, so the server is never asked.
+        let directory = LdapDirectory { config };
+        assert!(directory.authenticate("alice", "").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn escapes_usernames_in_dns_and_filters() {
+        assert_eq!(
+            user_dn("uid={username},ou=people,dc=corp,dc=com", "bob,ou=admins"),
+            "uid=bob\\2cou\\3dadmins,ou=people,dc=corp,dc=com"
+        );
+        assert_eq!(user_dn("uid={username}", "#root "), "uid=\\23root\\20");
+
+        assert_eq!(
+            user_filter("(&(objectClass=person)(uid={username}))", "*)(uid=*"),
+            "(&(objectClass=person)(uid=\\2a\\29\\28uid=\\2a))"
+        );
+        assert_eq!(user_filter("(mail={username})", "a\\b\0"), "(mail=a\\5cb\\00)");
+    }
+
+    #[test]
+    fn maps_admin_groups_to_roles() {
+        let mut config = test_support::config();
+        config.ldap_admin_groups = Vec::new();
+
+        assert_eq!(map_role(&user(&["cn=admins,ou=groups,dc=corp,dc=com"]), &config), None);
+
+        config.ldap_admin_groups = vec!["cn=admins,ou=groups,dc=corp,dc=com".to_string()];
+
+        assert_eq!(map_role(&user(&["CN=Admins,OU=Groups,DC=corp,DC=com"]), &config), Some(UserRole::Admin));
+        assert_eq!(map_role(&user(&["cn=staff,ou=groups,dc=corp,dc=com"]), &config), Some(UserRole::User));
+        assert_eq!(map_role(&user(&[]), &config), Some(UserRole::User));
+    }
+
+    #[test]
+    fn only_allows_members_of_the_allowed_or_admin_groups() {
+        let mut config = test_support::config();
+        config.ldap_allowed_groups = Vec::new();
+        config.ldap_admin_groups = Vec::new();
+
+        assert!(is_allowed(&user(&[]), &config));
+
+        config.ldap_allowed_groups = vec!["cn=staff,ou=groups,dc=corp,dc=com".to_string()];
+        config.ldap_admin_groups = vec!["cn=admins,ou=groups,dc=corp,dc=com".to_string()];
+
+        assert!(is_allowed(&user(&["cn=staff,ou=groups,dc=corp,dc=com"]), &config));
+        assert!(is_allowed(&user(&["CN=ADMINS,OU=GROUPS,DC=CORP,DC=COM"]), &config));
+        assert!(!is_allowed(&user(&["cn=contractors,ou=groups,dc=corp,dc=com"]), &config));
+        assert!(!is_allowed(&user(&[]), &config));
+    }
+}
@@ -1,2 +1,13 @@
+pub mod breach;
+pub mod keys;
+pub mod ldap;
+pub mod oauth;
 pub mod password;
-pub mod token;
\ No newline at end of file
+pub mod password_policy;
+pub mod revocation;
+pub mod saml;
+pub mod social;
+pub mod throttle;
+pub mod token;
+pub mod totp;
+pub mod webauthn;
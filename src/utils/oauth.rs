@@ -0,0 +1,92 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Scopes OAuth clients can request. `openid` turns an authorization into
+/// an OpenID Connect sign-in and adds an ID token to the token response.
+pub const SUPPORTED_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+pub const OPENID_SCOPE: &str = "openid";
+
+/// Lifetime of an authorization code, in minutes.
+pub const AUTHORIZATION_CODE_MAXAGE: i64 = 10;
+
+/// Lifetime of the consent token handed to the consent page, in minutes.
+pub const CONSENT_TOKEN_MAXAGE: i64 = 10;
+
+pub const PKCE_METHOD: &str = "S256";
+
+pub fn generate_client_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Splits a space-delimited scope parameter, dropping duplicates but
+/// keeping the requested order.
+pub fn parse_scope(scope: Option<&str>) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+
+    for scope in scope.unwrap_or_default().split_whitespace() {
+        if !scopes.iter().any(|existing| existing == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+
+    scopes
+}
+
+/// Redirect URIs must be absolute without a fragment, and use HTTPS, HTTP on
+/// a loopback address, or a private-use scheme for native apps (RFC 8252).
+pub fn validate_redirect_uri(redirect_uri: &str) -> Result<(), String> {
+    let url = Url::parse(redirect_uri)
+        .map_err(|_| format!("{} is not an absolute URI", redirect_uri))?;
+
+    if url.fragment().is_some() {
+        return Err(format!("{} must not contain a fragment", redirect_uri));
+    }
+
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        "http" => Err(format!("{} must use HTTPS", redirect_uri)),
+        scheme if scheme.contains('.') => Ok(()),
+        _ => Err(format!("{} uses an unsupported scheme", redirect_uri)),
+    }
+}
+
+/// Appends query parameters to a redirect URI, keeping any it already has.
+pub fn redirect_uri_with(redirect_uri: &str, params: &[(&str, Option<&str>)]) -> String {
+    let Ok(mut url) = Url::parse(redirect_uri) else {
+        return redirect_uri.to_string();
+    };
+
+    {
+        let mut query = url.query_pairs_mut();
+
+        for (name, value) in params {
+            if let Some(value) = value {
+                query.append_pair(name, value);
+            }
+        }
+    }
+
+    url.to_string()
+}
+
+/// A code challenge is the base64url SHA-256 of a 43-128 character verifier.
+pub fn is_valid_code_challenge(code_challenge: &str) -> bool {
+    code_challenge.len() == 43
+        && code_challenge.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let valid_verifier = (43..=128).contains(&code_verifier.len())
+        && code_verifier.chars().all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+
+    valid_verifier && URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes())) == code_challenge
+}
@@ -6,51 +6,446 @@ use argon2::{
         PasswordVerifier,
         SaltString
     },
-    Argon2
+    Algorithm,
+    Argon2,
+    KeyId,
+    Params,
+    ParamsBuilder,
+    Version
 };
 
-use crate::error::ErrorMessage;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pbkdf2::{pbkdf2_hmac, Pbkdf2};
+use scrypt::Scrypt;
+use sha2::Sha256;
 
-const MAX_PASSWORD_LENGTH: usize = 64;
+use crate::{config::Config, error::ErrorMessage};
 
-/// Hashes a plaintext password.
-pub fn hash_password(password: impl Into<String>) -> Result<String, ErrorMessage> {
-    let password = password.into();
+/// Upper bound for `PASSWORD_MAX_LENGTH`, in characters, which keeps the
+/// cost of hashing a password bounded.
+pub const MAX_PASSWORD_LENGTH: usize = 1024;
 
-    if password.is_empty() {
-        return Err(ErrorMessage::EmptyPassword);
+/// Cost limits for stored hashes. Hashes above them, for instance from a
+/// crafted import, are rejected instead of pinning a CPU on every login.
+pub const MAX_ARGON2_MEMORY_KIB: u32 = 1024 * 1024;
+pub const MAX_ARGON2_ITERATIONS: u32 = 32;
+pub const MAX_ARGON2_PARALLELISM: u32 = 16;
+pub const MAX_BCRYPT_COST: u32 = 14;
+pub const MAX_PBKDF2_ITERATIONS: u32 = 2_000_000;
+pub const MAX_SCRYPT_MEMORY_BYTES: u64 = 256 * 1024 * 1024;
+pub const MAX_SCRYPT_PARALLELISM: u32 = 16;
+
+/// Password hash formats `compare` understands. Anything other than
+/// Argon2 comes from imported accounts and is replaced on the next login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    Argon2,
+    Bcrypt,
+    /// PHC strings such as `$pbkdf2-sha256$i=600000,l=32$salt$hash`.
+    Pbkdf2,
+    /// Django's `pbkdf2_sha256$iterations$salt$hash`.
+    DjangoPbkdf2,
+    Scrypt,
+}
+
+impl HashScheme {
+    /// Detects the scheme of a stored hash. Returns `None` for unknown
+    /// formats and for hashes whose cost exceeds the limits above.
+    pub fn detect(hashed_password: &str) -> Option<Self> {
+        let prefixes = [
+            ("$argon2", HashScheme::Argon2),
+            ("$2a$", HashScheme::Bcrypt),
+            ("$2b$", HashScheme::Bcrypt),
+            ("$2y$", HashScheme::Bcrypt),
+            ("$pbkdf2-sha256$", HashScheme::Pbkdf2),
+            ("$pbkdf2-sha512$", HashScheme::Pbkdf2),
+            ("pbkdf2_sha256$", HashScheme::DjangoPbkdf2),
+            ("$scrypt$", HashScheme::Scrypt),
+        ];
+
+        prefixes
+            .into_iter()
+            .find(|(prefix, _)| hashed_password.starts_with(prefix))
+            .map(|(_, scheme)| scheme)
+            .filter(|scheme| scheme.cost_within_limits(hashed_password))
     }
 
-    if password.len() > MAX_PASSWORD_LENGTH {
-        return Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH));
+    fn cost_within_limits(self, hashed_password: &str) -> bool {
+        let phc_decimal = |name: &str| {
+            PasswordHash::new(hashed_password)
+                .ok()
+                .and_then(|hash| hash.params.get_decimal(name))
+        };
+
+        match self {
+            HashScheme::Argon2 => PasswordHash::new(hashed_password)
+                .ok()
+                .and_then(|hash| Params::try_from(&hash).ok())
+                .is_some_and(|params| {
+                    params.m_cost() <= MAX_ARGON2_MEMORY_KIB
+                        && params.t_cost() <= MAX_ARGON2_ITERATIONS
+                        && params.p_cost() <= MAX_ARGON2_PARALLELISM
+                }),
+            HashScheme::Bcrypt => hashed_password
+                .get(4..6)
+                .and_then(|cost| cost.parse::<u32>().ok())
+                .is_some_and(|cost| cost <= MAX_BCRYPT_COST),
+            HashScheme::Pbkdf2 => PasswordHash::new(hashed_password).is_ok()
+                && phc_decimal("i").is_none_or(|iterations| iterations <= MAX_PBKDF2_ITERATIONS),
+            HashScheme::DjangoPbkdf2 => hashed_password
+                .split('$')
+                .nth(1)
+                .and_then(|iterations| iterations.parse::<u32>().ok())
+                .is_some_and(|iterations| (1..=MAX_PBKDF2_ITERATIONS).contains(&iterations)),
+            HashScheme::Scrypt => match (phc_decimal("ln"), phc_decimal("r"), phc_decimal("p")) {
+                (Some(log_n), Some(r), Some(p)) => {
+                    let memory = 128u64
+                        .checked_shl(log_n)
+                        .and_then(|n| n.checked_mul(r as u64));
+
+                    memory.is_some_and(|memory| memory <= MAX_SCRYPT_MEMORY_BYTES) && p <= MAX_SCRYPT_PARALLELISM
+                },
+                _ => false,
+            },
+        }
     }
+}
 
-    let salt = SaltString::generate(&mut OsRng);
+/// Argon2id settings for password hashing, with an optional server-side
+/// pepper.
+///
+/// The pepper's id is stored in each hash as the Argon2 `keyid`, so hashes
+/// made before a pepper was configured still verify and can be upgraded.
+#[derive(Debug, Clone)]
+pub struct PasswordHashing {
+    params: Params,
+    pepper: Option<Pepper>,
+    max_length: usize,
+}
 
-    let hashed_password = Argon2::default()
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|_| ErrorMessage::HashingError)
-        .and_then(|hash| Ok(hash.to_string()));
+#[derive(Clone)]
+struct Pepper {
+    id: KeyId,
+    secret: Vec<u8>,
+}
 
-    hashed_password
+impl std::fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pepper").field("id", &self.id).finish_non_exhaustive()
+    }
 }
 
-/// Compares a plaintext password with a hashed password.
-pub fn compare(password: &str, hashed_password: &str) -> Result<bool, ErrorMessage> {
-    if password.is_empty() || hashed_password.is_empty() {
-        return Err(ErrorMessage::EmptyPassword);
+impl PasswordHashing {
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        if !(1..=MAX_PASSWORD_LENGTH).contains(&config.password_max_length) {
+            return Err(format!("PASSWORD_MAX_LENGTH must be between 1 and {}", MAX_PASSWORD_LENGTH).into());
+        }
+
+        let pepper = match &config.password_pepper {
+            Some(secret) if !secret.is_empty() => Some(Pepper {
+                id: KeyId::new(config.password_pepper_id.as_bytes())
+                    .map_err(|e| format!("Invalid PASSWORD_PEPPER_ID: {}", e))?,
+                secret: secret.as_bytes().to_vec(),
+            }),
+            _ => None,
+        };
+
+        let mut params = ParamsBuilder::new();
+        params
+            .m_cost(config.argon2_memory_kib)
+            .t_cost(config.argon2_iterations)
+            .p_cost(config.argon2_parallelism);
+
+        if let Some(pepper) = &pepper {
+            params.keyid(pepper.id);
+        }
+
+        let params = params.build()
+            .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+
+        if params.m_cost() > MAX_ARGON2_MEMORY_KIB
+            || params.t_cost() > MAX_ARGON2_ITERATIONS
+            || params.p_cost() > MAX_ARGON2_PARALLELISM
+        {
+            return Err(format!(
+                "Argon2 parameters cannot exceed {} KiB, {} iterations and parallelism {}",
+                MAX_ARGON2_MEMORY_KIB, MAX_ARGON2_ITERATIONS, MAX_ARGON2_PARALLELISM
+            ).into());
+        }
+
+        Ok(Self { params, pepper, max_length: config.password_max_length })
     }
 
-    if password.len() > MAX_PASSWORD_LENGTH {
-        return Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH));
+    fn argon2(&self, params: Params, keyid: &[u8]) -> Result<Argon2<'_>, ErrorMessage> {
+        if keyid.is_empty() {
+            return Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params));
+        }
+
+        match &self.pepper {
+            Some(pepper) if pepper.id.as_bytes() == keyid => {
+                Argon2::new_with_secret(&pepper.secret, Algorithm::Argon2id, Version::V0x13, params)
+                    .map_err(|_| ErrorMessage::HashingError)
+            },
+            _ => Err(ErrorMessage::UnknownPepper),
+        }
     }
 
-    let parsed_hash = PasswordHash::new(hashed_password)
+    /// Hashes a plaintext password.
+    pub fn hash_password(&self, password: impl Into<String>) -> Result<String, ErrorMessage> {
+        let password = password.into();
+
+        if password.is_empty() {
+            return Err(ErrorMessage::EmptyPassword);
+        }
+
+        if password.chars().count() > self.max_length {
+            return Err(ErrorMessage::ExceededMaxPasswordLength(self.max_length));
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+
+        self.argon2(self.params.clone(), self.params.keyid())?
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|_| ErrorMessage::HashingError)
+            .map(|hash| hash.to_string())
+    }
+
+    /// Compares a plaintext password with a hashed password. Stored
+    /// passwords may predate the current length limit or come from another
+    /// system, so only `MAX_PASSWORD_LENGTH` applies here.
+    pub fn compare(&self, password: &str, hashed_password: &str) -> Result<bool, ErrorMessage> {
+        if password.is_empty() || hashed_password.is_empty() {
+            return Err(ErrorMessage::EmptyPassword);
+        }
+
+        if password.chars().count() > MAX_PASSWORD_LENGTH {
+            return Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH));
+        }
+
+        let scheme = HashScheme::detect(hashed_password)
+            .ok_or(ErrorMessage::InvalidHashFormat)?;
+
+        if scheme == HashScheme::Bcrypt {
+            return bcrypt::verify(password, hashed_password)
+                .map_err(|_| ErrorMessage::InvalidHashFormat);
+        }
+
+        if scheme == HashScheme::DjangoPbkdf2 {
+            return verify_django_pbkdf2(password, hashed_password);
+        }
+
+        let parsed_hash = PasswordHash::new(hashed_password)
+            .map_err(|_| ErrorMessage::InvalidHashFormat)?;
+
+        let result = match scheme {
+            HashScheme::Pbkdf2 => Pbkdf2.verify_password(password.as_bytes(), &parsed_hash),
+            HashScheme::Scrypt => Scrypt.verify_password(password.as_bytes(), &parsed_hash),
+            _ => {
+                let params = Params::try_from(&parsed_hash)
+                    .map_err(|_| ErrorMessage::InvalidHashFormat)?;
+
+                self.argon2(Params::default(), params.keyid())?
+                    .verify_password(password.as_bytes(), &parsed_hash)
+            },
+        };
+
+        Ok(result.is_ok())
+    }
+
+    /// Whether a hash was made with a different algorithm, cost or pepper
+    /// than the current settings and should be replaced after the next
+    /// successful `compare`.
+    pub fn needs_rehash(&self, hashed_password: &str) -> bool {
+        if HashScheme::detect(hashed_password) != Some(HashScheme::Argon2) {
+            return true;
+        }
+
+        let Ok(parsed_hash) = PasswordHash::new(hashed_password) else {
+            return true;
+        };
+
+        let Ok(params) = Params::try_from(&parsed_hash) else {
+            return true;
+        };
+
+        parsed_hash.algorithm != Algorithm::Argon2id.ident()
+            || parsed_hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+            || params.keyid() != self.params.keyid()
+    }
+}
+
+fn verify_django_pbkdf2(password: &str, hashed_password: &str) -> Result<bool, ErrorMessage> {
+    let mut parts = hashed_password.splitn(4, '$').skip(1);
+
+    let (Some(iterations), Some(salt), Some(expected)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(ErrorMessage::InvalidHashFormat);
+    };
+
+    let iterations = iterations.parse::<u32>()
+        .map_err(|_| ErrorMessage::InvalidHashFormat)?;
+    let expected = STANDARD.decode(expected)
         .map_err(|_| ErrorMessage::InvalidHashFormat)?;
 
-    let result = Argon2::default()
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .map_or(false, |_| true);
+    if expected.is_empty() || expected.len() > 64 {
+        return Err(ErrorMessage::InvalidHashFormat);
+    }
 
-    Ok(result)
-}
\ No newline at end of file
+    let mut derived = vec![0u8; expected.len()];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), iterations, &mut derived);
+
+    let difference = derived.iter()
+        .zip(&expected)
+        .fold(0u8, |difference, (a, b)| difference | (a ^ b));
+
+    Ok(difference == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn hashing(memory_kib: u32, pepper: Option<(&str, &str)>) -> PasswordHashing {
+        let mut config = test_support::config();
+        config.argon2_memory_kib = memory_kib;
+        config.argon2_iterations = 1;
+        config.argon2_parallelism = 1;
+        config.password_pepper = pepper.map(|(_, secret)| secret.to_string());
+        config.password_pepper_id = pepper.map_or("v1", |(id, _)| id).to_string();
+
+        PasswordHashing::from_config(&config).unwrap()
+    }
+
+    fn hashing_with_max_length(max_length: usize) -> PasswordHashing {
+        let mut config = test_support::config();
+        config.password_max_length = max_length;
+        config.argon2_memory_kib = 1024;
+        config.argon2_iterations = 1;
+
+        PasswordHashing::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn verifies_hashes_made_with_other_costs() {
+        let hash = hashing(1024, None).hash_password("violet lantern orbit").unwrap();
+        let current = hashing(2048, None);
+
+        assert_eq!(current.compare("violet lantern orbit", &hash), Ok(true));
+        assert_eq!(current.compare("violet lantern orbiT", &hash), Ok(false));
+        assert_eq!(current.compare("violet lantern orbit", "not a hash"), Err(ErrorMessage::InvalidHashFormat));
+    }
+
+    #[test]
+    fn flags_hashes_made_with_outdated_settings() {
+        let current = hashing(2048, Some(("v2", "pepper-two")));
+        let hash = current.hash_password("violet lantern orbit").unwrap();
+
+        assert!(!current.needs_rehash(&hash));
+        assert!(current.needs_rehash(&hashing(1024, Some(("v2", "pepper-two"))).hash_password("x").unwrap()));
+        assert!(current.needs_rehash(&hashing(2048, None).hash_password("x").unwrap()));
+        assert!(current.needs_rehash(&hashing(2048, Some(("v1", "pepper-one"))).hash_password("x").unwrap()));
+        assert!(current.needs_rehash("not a hash"));
+    }
+
+    #[test]
+    fn peppered_hashes_need_the_matching_pepper() {
+        let hash = hashing(1024, Some(("v1", "pepper-one"))).hash_password("violet lantern orbit").unwrap();
+
+        assert_eq!(hashing(1024, Some(("v1", "pepper-one"))).compare("violet lantern orbit", &hash), Ok(true));
+        assert_eq!(hashing(1024, None).compare("violet lantern orbit", &hash), Err(ErrorMessage::UnknownPepper));
+        assert_eq!(
+            hashing(1024, Some(("v2", "pepper-two"))).compare("violet lantern orbit", &hash),
+            Err(ErrorMessage::UnknownPepper)
+        );
+
+        // Hashes made before the pepper was introduced keep working.
+        let unpeppered = hashing(1024, None).hash_password("violet lantern orbit").unwrap();
+        assert_eq!(hashing(1024, Some(("v1", "pepper-one"))).compare("violet lantern orbit", &unpeppered), Ok(true));
+    }
+
+    #[test]
+    fn honours_a_configured_max_length_above_64() {
+        let hashing = hashing_with_max_length(128);
+        let password = "p".repeat(128);
+
+        let hash = hashing.hash_password(password.as_str()).unwrap();
+        assert_eq!(hashing.compare(&password, &hash), Ok(true));
+
+        assert_eq!(
+            hashing.hash_password("p".repeat(129)),
+            Err(ErrorMessage::ExceededMaxPasswordLength(128))
+        );
+    }
+
+    fn django_pbkdf2(password: &str, iterations: u32) -> String {
+        let mut derived = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(password.as_bytes(), b"salt", iterations, &mut derived);
+
+        format!("pbkdf2_sha256${}$salt${}", iterations, STANDARD.encode(derived))
+    }
+
+    #[test]
+    fn verifies_legacy_passwords_longer_than_the_policy_limit() {
+        let hashing = hashing_with_max_length(64);
+        let password = "legacy-".repeat(15);
+
+        let bcrypt_hash = bcrypt::hash(&password, 4).unwrap();
+        assert_eq!(hashing.compare(&password, &bcrypt_hash), Ok(true));
+
+        let django_hash = django_pbkdf2(&password, 1000);
+        assert_eq!(hashing.compare(&password, &django_hash), Ok(true));
+        assert_eq!(hashing.compare("legacy-", &django_hash), Ok(false));
+
+        assert_eq!(
+            hashing.compare(&"p".repeat(MAX_PASSWORD_LENGTH + 1), &django_hash),
+            Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH))
+        );
+    }
+
+    #[test]
+    fn rejects_hashes_above_the_cost_limits() {
+        let accepted = [
+            "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW",
+            "$pbkdf2-sha256$i=600000,l=32$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+            "pbkdf2_sha256$870000$salt$ZmFrZQ==",
+            "$scrypt$ln=17,r=8,p=1$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+            "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+        ];
+
+        for hash in accepted {
+            assert!(HashScheme::detect(hash).is_some(), "{} was rejected", hash);
+        }
+
+        let rejected = [
+            "$2b$15$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW",
+            "$2b$31$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW",
+            "$pbkdf2-sha256$i=4294967295,l=32$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+            "pbkdf2_sha256$4294967295$salt$ZmFrZQ==",
+            "pbkdf2_sha256$0$salt$ZmFrZQ==",
+            "$scrypt$ln=30,r=8,p=1$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+            "$scrypt$ln=17,r=8,p=64$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+            "$argon2id$v=19$m=4194304,t=2,p=1$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+            "$argon2id$v=19$m=19456,t=1000,p=1$c2FsdHNhbHQ$ZmFrZWhhc2hmYWtlaGFzaGZha2VoYXNoZmFrZWhhc2g",
+        ];
+
+        let hashing = hashing_with_max_length(64);
+
+        for hash in rejected {
+            assert_eq!(HashScheme::detect(hash), None, "{} was accepted", hash);
+            assert_eq!(hashing.compare("password", hash), Err(ErrorMessage::InvalidHashFormat));
+        }
+    }
+
+    #[test]
+    fn rejects_an_out_of_range_max_length_at_startup() {
+        let mut config = test_support::config();
+
+        for max_length in [0, MAX_PASSWORD_LENGTH + 1] {
+            config.password_max_length = max_length;
+            assert!(PasswordHashing::from_config(&config).is_err());
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use std::sync::Arc;
+
+use crate::{
+    config::Config,
+    error::{ErrorMessage, HttpError}
+};
+
+use super::{
+    breach::BreachFilter,
+    password::PasswordHashing
+};
+
+/// Frequently used passwords, rejected outright and treated as predictable
+/// when they appear inside a longer password.
+const COMMON_PASSWORDS: [&str; 60] = [
+    "123456", "password", "12345678", "qwerty", "123456789", "12345", "1234", "111111",
+    "1234567", "dragon", "123123", "baseball", "abc123", "football", "monkey", "letmein",
+    "696969", "shadow", "master", "666666", "qwertyuiop", "123321", "mustang", "1234567890",
+    "michael", "654321", "superman", "1qaz2wsx", "7777777", "121212", "000000", "qazwsx",
+    "123qwe", "killer", "trustno1", "jordan", "jennifer", "zxcvbnm", "asdfgh", "hunter",
+    "buster", "soccer", "harley", "batman", "andrew", "tigger", "sunshine", "iloveyou",
+    "2000", "charlie", "robert", "thomas", "hockey", "ranger", "daniel", "starwars",
+    "welcome", "admin", "login", "passw0rd",
+];
+
+/// Character runs that are cheap to guess in either direction.
+const SEQUENCES: [&str; 5] = [
+    "abcdefghijklmnopqrstuvwxyz",
+    "01234567890",
+    "qwertyuiop",
+    "asdfghjkl",
+    "zxcvbnm",
+];
+
+/// Password requirements applied when a password is set. The maximum
+/// length never exceeds what `hash_password` accepts.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Minimum strength score, from 0 (trivial) to 4 (very strong).
+    pub min_strength: u8,
+    pub forbid_personal_info: bool,
+    /// Known breached passwords, loaded from `BREACHED_PASSWORDS_FILTER`.
+    pub breach_filter: Option<Arc<BreachFilter>>,
+    /// Number of previous passwords remembered and refused on change or reset.
+    pub history_size: i64,
+}
+
+impl PasswordPolicy {
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        if config.password_min_length > config.password_max_length {
+            return Err("PASSWORD_MIN_LENGTH cannot be greater than PASSWORD_MAX_LENGTH".into());
+        }
+
+        let breach_filter = match &config.breached_passwords_filter {
+            Some(path) => Some(Arc::new(BreachFilter::load(path)?)),
+            None => None,
+        };
+
+        Ok(Self {
+            min_length: config.password_min_length,
+            max_length: config.password_max_length,
+            require_lowercase: config.password_require_lowercase,
+            require_uppercase: config.password_require_uppercase,
+            require_digit: config.password_require_digit,
+            require_symbol: config.password_require_symbol,
+            min_strength: config.password_min_strength,
+            forbid_personal_info: config.password_forbid_personal_info,
+            breach_filter,
+            history_size: config.password_history_size,
+        })
+    }
+
+    /// Returns every rule `password` breaks. `user_inputs` are values such
+    /// as the user's name and email that must not appear in the password.
+    pub fn violations(&self, password: &str, user_inputs: &[&str]) -> Vec<ErrorMessage> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(ErrorMessage::PasswordTooShort(self.min_length));
+        }
+
+        if password.len() > self.max_length {
+            violations.push(ErrorMessage::ExceededMaxPasswordLength(self.max_length));
+        }
+
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violations.push(ErrorMessage::PasswordMissingLowercase);
+        }
+
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push(ErrorMessage::PasswordMissingUppercase);
+        }
+
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(ErrorMessage::PasswordMissingDigit);
+        }
+
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+            violations.push(ErrorMessage::PasswordMissingSymbol);
+        }
+
+        let personal_tokens = personal_tokens(user_inputs);
+        let lowercase = password.to_lowercase();
+
+        if self.forbid_personal_info && personal_tokens.iter().any(|token| lowercase.contains(token.as_str())) {
+            violations.push(ErrorMessage::PasswordContainsPersonalInfo);
+        }
+
+        if strength_score(password, &personal_tokens) < self.min_strength {
+            violations.push(ErrorMessage::PasswordTooWeak);
+        }
+
+        if self.breach_filter.as_ref().is_some_and(|filter| filter.contains_password(password)) {
+            violations.push(ErrorMessage::PasswordBreached);
+        }
+
+        violations
+    }
+
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), HttpError> {
+        let violations = self.violations(password, user_inputs);
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(HttpError::bad_request(ErrorMessage::PasswordPolicyViolation.to_string())
+            .with_errors(violations.iter().map(ToString::to_string).collect()))
+    }
+
+    /// Rejects `password` if it matches the current hash or any of the
+    /// previous ones.
+    pub fn check_reuse(
+        &self,
+        password: &str,
+        current_hash: &str,
+        history: &[String],
+        hashing: &PasswordHashing,
+    ) -> Result<(), HttpError> {
+        let reused = std::iter::once(current_hash)
+            .chain(history.iter().map(String::as_str))
+            .any(|hash| hashing.compare(password, hash).unwrap_or(false));
+
+        if !reused {
+            return Ok(());
+        }
+
+        Err(HttpError::bad_request(ErrorMessage::PasswordPolicyViolation.to_string())
+            .with_errors(vec![ErrorMessage::PasswordReused.to_string()]))
+    }
+}
+
+/// Estimates how hard `password` is to guess on zxcvbn's 0-4 scale.
+///
+/// Each character adds the entropy of its character set, except characters
+/// that extend a repeat, a sequence, a keyboard run, a common password or
+/// one of `personal_tokens`, which add almost nothing.
+pub fn strength_score(password: &str, personal_tokens: &[String]) -> u8 {
+    let lowercase = password.to_lowercase();
+
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        return 0;
+    }
+
+    let chars: Vec<char> = lowercase.chars().collect();
+    let mut predictable = vec![false; chars.len()];
+
+    for i in 1..chars.len() {
+        if chars[i] == chars[i - 1] {
+            predictable[i] = true;
+        }
+    }
+
+    for sequence in SEQUENCES {
+        let reversed: String = sequence.chars().rev().collect();
+
+        for run in [sequence, reversed.as_str()] {
+            mark_substrings(&chars, run, 3, &mut predictable);
+        }
+    }
+
+    for word in COMMON_PASSWORDS.iter().copied().chain(personal_tokens.iter().map(String::as_str)) {
+        mark_matches(&chars, word, &mut predictable);
+    }
+
+    let charset = charset_size(password) as f64;
+    let log_guesses: f64 = predictable
+        .iter()
+        .map(|&predictable| if predictable { 2f64.log10() } else { charset.log10() })
+        .sum();
+
+    match log_guesses {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Marks every character after the first of each run of at least
+/// `min_length` characters that also appears in `run`.
+fn mark_substrings(chars: &[char], run: &str, min_length: usize, predictable: &mut [bool]) {
+    let run: Vec<char> = run.chars().collect();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut length = 0;
+
+        if let Some(position) = run.iter().position(|c| *c == chars[start]) {
+            while start + length < chars.len()
+                && position + length < run.len()
+                && chars[start + length] == run[position + length]
+            {
+                length += 1;
+            }
+        }
+
+        if length >= min_length {
+            predictable[start + 1..start + length].iter_mut().for_each(|p| *p = true);
+            start += length;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+/// Marks every character after the first of each occurrence of `word`.
+fn mark_matches(chars: &[char], word: &str, predictable: &mut [bool]) {
+    let word: Vec<char> = word.chars().collect();
+
+    if word.len() < 3 || word.len() > chars.len() {
+        return;
+    }
+
+    for start in 0..=chars.len() - word.len() {
+        if chars[start..start + word.len()] == word[..] {
+            predictable[start + 1..start + word.len()].iter_mut().for_each(|p| *p = true);
+        }
+    }
+}
+
+fn charset_size(password: &str) -> usize {
+    let mut size = 0;
+
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        size += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        size += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        size += 10;
+    }
+    if password.chars().any(|c| c.is_ascii_punctuation() || c == ' ') {
+        size += 33;
+    }
+    if !password.is_ascii() {
+        size += 100;
+    }
+
+    size.max(1)
+}
+
+/// Splits names and email addresses into lowercase words of three or more
+/// characters.
+fn personal_tokens(user_inputs: &[&str]) -> Vec<String> {
+    user_inputs
+        .iter()
+        .flat_map(|input| {
+            let input = input.to_lowercase();
+            let local_part = input.split('@').next().unwrap_or_default().to_string();
+
+            local_part
+                .split(|c: char| !c.is_alphanumeric())
+                .chain(input.split_whitespace())
+                .map(str::to_string)
+                .chain(std::iter::once(local_part.clone()))
+                .collect::<Vec<_>>()
+        })
+        .filter(|token| token.chars().count() >= 3)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::Digest;
+
+    use super::*;
+    use crate::test_support;
+
+    fn policy(configure: impl FnOnce(&mut Config)) -> PasswordPolicy {
+        let mut config = test_support::config();
+        config.password_min_length = 8;
+        config.password_require_lowercase = false;
+        config.password_require_uppercase = false;
+        config.password_require_digit = false;
+        config.password_require_symbol = false;
+        config.password_min_strength = 0;
+        config.password_forbid_personal_info = false;
+        configure(&mut config);
+
+        PasswordPolicy::from_config(&config).unwrap()
+    }
+
+    fn tokens(inputs: &[&str]) -> Vec<String> {
+        personal_tokens(inputs)
+    }
+
+    #[test]
+    fn scores_common_and_patterned_passwords_low() {
+        for password in ["password", "Password", "qwerty", "letmein"] {
+            assert_eq!(strength_score(password, &[]), 0, "{}", password);
+        }
+
+        for password in ["aaaaaaaaaaaa", "abcdefghijkl", "zyxwvutsrqpo", "password1"] {
+            assert!(strength_score(password, &[]) <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn scores_long_varied_passwords_high() {
+        assert_eq!(strength_score("x7#Kq9!mZ2", &[]), 4);
+        assert_eq!(strength_score("violet lantern orbit", &[]), 4);
+        assert!(strength_score("kd8wmq3z", &[]) >= 2);
+    }
+
+    #[test]
+    fn discounts_personal_information() {
+        let personal = tokens(&["Marguerite Okonkwo", "marguerite.okonkwo@example.com"]);
+
+        assert!(strength_score("margueriteokonkwo", &personal) < strength_score("margueriteokonkwo", &[]));
+        assert!(personal.contains(&"marguerite".to_string()));
+        assert!(personal.contains(&"marguerite.okonkwo".to_string()));
+    }
+
+    #[test]
+    fn enforces_the_minimum_length_in_characters() {
+        let policy = policy(|config| config.password_min_length = 10);
+
+        assert_eq!(policy.violations("short", &[]), vec![ErrorMessage::PasswordTooShort(10)]);
+        assert_eq!(policy.violations("ñññññññññ", &[]), vec![ErrorMessage::PasswordTooShort(10)]);
+        assert!(policy.violations("ññññññññññ", &[]).is_empty());
+    }
+
+    #[test]
+    fn enforces_the_maximum_length() {
+        let policy = policy(|config| config.password_max_length = 16);
+
+        assert_eq!(policy.violations(&"k".repeat(17), &[]), vec![ErrorMessage::ExceededMaxPasswordLength(16)]);
+        assert!(policy.violations(&"k".repeat(16), &[]).is_empty());
+    }
+
+    #[test]
+    fn enforces_each_character_class() {
+        let policy = policy(|config| {
+            config.password_require_lowercase = true;
+            config.password_require_uppercase = true;
+            config.password_require_digit = true;
+            config.password_require_symbol = true;
+        });
+
+        assert_eq!(policy.violations("ABCDEFG1!", &[]), vec![ErrorMessage::PasswordMissingLowercase]);
+        assert_eq!(policy.violations("abcdefg1!", &[]), vec![ErrorMessage::PasswordMissingUppercase]);
+        assert_eq!(policy.violations("Abcdefgh!", &[]), vec![ErrorMessage::PasswordMissingDigit]);
+        assert_eq!(policy.violations("Abcdefgh1", &[]), vec![ErrorMessage::PasswordMissingSymbol]);
+        assert!(policy.violations("Abcdefg1!", &[]).is_empty());
+    }
+
+    #[test]
+    fn enforces_the_strength_threshold() {
+        let policy = policy(|config| config.password_min_strength = 3);
+
+        assert_eq!(policy.violations("password1", &[]), vec![ErrorMessage::PasswordTooWeak]);
+        assert!(policy.violations("x7#Kq9!mZ2", &[]).is_empty());
+    }
+
+    #[test]
+    fn refuses_passwords_containing_the_name_or_email() {
+        let policy = policy(|config| config.password_forbid_personal_info = true);
+        let user_inputs = ["Marguerite Okonkwo", "mokonkwo@example.com"];
+
+        for password in ["Marguerite!42", "x7#okonkwo", "MOKONKWO-2024"] {
+            assert_eq!(
+                policy.violations(password, &user_inputs),
+                vec![ErrorMessage::PasswordContainsPersonalInfo],
+                "{}",
+                password
+            );
+        }
+
+        assert!(policy.violations("x7#Kq9!mZ2", &user_inputs).is_empty());
+        assert!(policy.violations("x7#Kq9!mZ2", &[]).is_empty());
+    }
+
+    #[test]
+    fn refuses_breached_passwords() {
+        let mut filter = BreachFilter::new(10, 0.001);
+        filter.insert(&sha1::Sha1::digest(b"x7#Kq9!mZ2").into());
+
+        let policy = PasswordPolicy {
+            breach_filter: Some(Arc::new(filter)),
+            ..policy(|_| {})
+        };
+
+        assert_eq!(policy.violations("x7#Kq9!mZ2", &[]), vec![ErrorMessage::PasswordBreached]);
+        assert!(policy.violations("violet lantern orbit", &[]).is_empty());
+    }
+
+    #[test]
+    fn reports_every_violation_together() {
+        let policy = policy(|config| {
+            config.password_min_length = 12;
+            config.password_require_digit = true;
+            config.password_min_strength = 2;
+        });
+
+        let error = policy.check("qwerty", &[]).unwrap_err();
+        assert_eq!(error.message, ErrorMessage::PasswordPolicyViolation.to_string());
+        assert_eq!(error.errors, vec![
+            ErrorMessage::PasswordTooShort(12).to_string(),
+            ErrorMessage::PasswordMissingDigit.to_string(),
+            ErrorMessage::PasswordTooWeak.to_string(),
+        ]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex}
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{config::Config, database::DBClient, utils::throttle::StoreError};
+
+/// Storage backend for the ids (`jti`) of revoked access tokens. An entry
+/// only has to outlive the token it revokes.
+#[async_trait]
+pub trait RevocationStore: fmt::Debug + Send + Sync {
+    /// Revokes `jti` until `expires_at`, returning `false` if it already was.
+    async fn revoke(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, StoreError>;
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, StoreError>;
+}
+
+/// Keeps revoked token ids in this process. A restart makes revoked tokens
+/// valid again until they expire, so only use it where tokens are short-lived
+/// or with a single instance that rarely restarts. Expired entries are
+/// dropped whenever a token is revoked.
+#[derive(Debug, Default)]
+pub struct MemoryRevocationStore {
+    revoked: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+    async fn revoke(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, StoreError> {
+        let now = Utc::now();
+        let mut revoked = self.revoked.lock().unwrap();
+
+        revoked.retain(|_, expires_at| *expires_at > now);
+        let previous = revoked.insert(jti.to_string(), expires_at);
+
+        Ok(previous.is_none())
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, StoreError> {
+        let revoked = self.revoked.lock().unwrap();
+
+        Ok(revoked.get(jti).is_some_and(|expires_at| *expires_at > Utc::now()))
+    }
+}
+
+pub fn store_from_config(
+    config: &Config,
+    db_client: DBClient,
+) -> Result<Arc<dyn RevocationStore>, Box<dyn std::error::Error>> {
+    match config.revocation_backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryRevocationStore::default())),
+        "postgres" => Ok(Arc::new(db_client)),
+        backend => Err(format!("Unsupported REVOCATION_BACKEND: {}", backend).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    async fn revokes_once(store: &dyn RevocationStore) {
+        let expires_at = Utc::now() + Duration::minutes(5);
+
+        assert!(store.revoke("jti", expires_at).await.unwrap());
+        assert!(!store.revoke("jti", expires_at).await.unwrap());
+        assert!(store.is_revoked("jti").await.unwrap());
+
+        assert!(store.revoke("other", expires_at).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn memory_store_reports_whether_a_token_was_newly_revoked() {
+        revokes_once(&MemoryRevocationStore::default()).await;
+    }
+
+    #[sqlx::test]
+    async fn postgres_store_reports_whether_a_token_was_newly_revoked(pool: PgPool) {
+        revokes_once(&DBClient::new(pool)).await;
+    }
+}
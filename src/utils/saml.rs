@@ -0,0 +1,892 @@
+use std::{collections::{HashMap, HashSet}, io::Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use flate2::{write::DeflateEncoder, Compression};
+use roxmltree::{Document, Node, NodeType};
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    signature::Verifier,
+    RsaPublicKey
+};
+use sha2::{Digest, Sha256};
+use x509_cert::{der::{DecodePem, Encode}, Certificate};
+
+use crate::{config::Config, utils::{oauth::redirect_uri_with, token}};
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const EMAIL_NAME_ID_FORMAT: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+
+/// Tolerated clock difference between us and the IdP, in seconds.
+pub const CLOCK_SKEW: i64 = 120;
+
+/// Identity provider name of accounts provisioned from SAML assertions.
+pub const PROVIDER: &str = "saml";
+
+/// Cookie that ties the IdP's response to the AuthnRequest this browser
+/// was sent with.
+pub const SAML_REQUEST_COOKIE: &str = "saml_request";
+
+/// Lifetime of a pending AuthnRequest, in minutes.
+pub const SAML_REQUEST_MAXAGE: i64 = 10;
+
+pub fn acs_url() -> String {
+    "http://localhost:8000/api/saml/acs".to_string()
+}
+
+/// The SAML identity provider users sign in with. Only its certificate
+/// is trusted for signatures; keys sent along in `KeyInfo` are ignored.
+#[derive(Debug, Clone)]
+pub struct IdentityProvider {
+    pub entity_id: String,
+    pub sso_url: String,
+    public_key: RsaPublicKey,
+}
+
+impl IdentityProvider {
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let Some(certificate_path) = &config.saml_idp_certificate_path else {
+            return Ok(None);
+        };
+
+        let entity_id = config.saml_idp_entity_id
+            .clone()
+            .ok_or("SAML_IDP_ENTITY_ID must be set when SAML is enabled")?;
+        let sso_url = config.saml_idp_sso_url
+            .clone()
+            .ok_or("SAML_IDP_SSO_URL must be set when SAML is enabled")?;
+
+        let certificate = Certificate::from_pem(std::fs::read(certificate_path)?)?;
+        let public_key = RsaPublicKey::from_public_key_der(
+            &certificate.tbs_certificate.subject_public_key_info.to_der()?
+        )?;
+
+        Ok(Some(Self { entity_id, sso_url, public_key }))
+    }
+}
+
+/// The parts of a validated assertion the SP acts on.
+#[derive(Debug, Clone)]
+pub struct SamlAssertion {
+    pub id: String,
+    pub name_id: Option<String>,
+    pub attributes: HashMap<String, Vec<String>>,
+    pub not_on_or_after: DateTime<Utc>,
+}
+
+impl SamlAssertion {
+    pub fn attribute(&self, name: &str) -> &[String] {
+        self.attributes
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// What a response must match besides the IdP's signature.
+pub struct ResponseExpectations<'a> {
+    pub sp_entity_id: &'a str,
+    /// The id of the AuthnRequest this browser was sent with, if any.
+    pub request_id: Option<&'a str>,
+    pub allow_idp_initiated: bool,
+}
+
+pub struct AuthnRequest {
+    pub id: String,
+    pub url: String,
+}
+
+/// Builds an AuthnRequest for the HTTP-Redirect binding.
+pub fn authn_request(idp: &IdentityProvider, sp_entity_id: &str) -> Result<AuthnRequest, String> {
+    let id = format!("_{}", token::generate_opaque_token());
+    let issue_instant = Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+
+    let request = format!(
+        concat!(
+            r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" IssueInstant="{}" "#,
+            r#"Destination="{}" AssertionConsumerServiceURL="{}" ProtocolBinding="{}">"#,
+            r#"<saml:Issuer>{}</saml:Issuer>"#,
+            r#"<samlp:NameIDPolicy Format="{}" AllowCreate="true"/>"#,
+            r#"</samlp:AuthnRequest>"#
+        ),
+        PROTOCOL_NS,
+        ASSERTION_NS,
+        id,
+        issue_instant,
+        escape_attribute(&idp.sso_url),
+        escape_attribute(&acs_url()),
+        HTTP_POST_BINDING,
+        escape_text(sp_entity_id),
+        EMAIL_NAME_ID_FORMAT,
+    );
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let deflated = encoder.finish().map_err(|e| e.to_string())?;
+
+    let url = redirect_uri_with(&idp.sso_url, &[("SAMLRequest", Some(&STANDARD.encode(deflated)))]);
+
+    Ok(AuthnRequest { id, url })
+}
+
+/// SP metadata to register with the IdP.
+pub fn sp_metadata(sp_entity_id: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{}">"#,
+            r#"<md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}">"#,
+            r#"<md:NameIDFormat>{}</md:NameIDFormat>"#,
+            r#"<md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>"#,
+            r#"</md:SPSSODescriptor>"#,
+            r#"</md:EntityDescriptor>"#
+        ),
+        escape_attribute(sp_entity_id),
+        PROTOCOL_NS,
+        EMAIL_NAME_ID_FORMAT,
+        HTTP_POST_BINDING,
+        escape_attribute(&acs_url()),
+    )
+}
+
+/// Decodes and validates a base64 `SAMLResponse` from the HTTP-POST
+/// binding: the IdP's signature over the response or its assertion, the
+/// request it answers, the audience, recipient and validity window.
+pub fn parse_response(
+    encoded: &str,
+    idp: &IdentityProvider,
+    expected: &ResponseExpectations<'_>,
+) -> Result<SamlAssertion, String> {
+    let encoded: String = encoded.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let xml = STANDARD.decode(encoded)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or("SAMLResponse is not valid base64 UTF-8")?;
+
+    // roxmltree refuses DTDs, so entity expansion attacks can't get through.
+    let document = Document::parse(&xml).map_err(|e| format!("Malformed XML: {}", e))?;
+    let response = document.root_element();
+
+    if !is(response, PROTOCOL_NS, "Response") {
+        return Err("Document is not a SAML Response".to_string());
+    }
+
+    // Signatures reference elements by ID, so duplicates would let a signed
+    // element vouch for a forged one.
+    let mut ids = HashSet::new();
+    for id in document.descendants().filter_map(|node| node.attribute("ID")) {
+        if !ids.insert(id) {
+            return Err(format!("Duplicate ID {}", id));
+        }
+    }
+
+    if let Some(destination) = response.attribute("Destination") {
+        if destination != acs_url() {
+            return Err(format!("Response is addressed to {}", destination));
+        }
+    }
+
+    let in_response_to = response.attribute("InResponseTo");
+
+    match (in_response_to, expected.request_id) {
+        (Some(in_response_to), Some(request_id)) if in_response_to == request_id => {},
+        (None, _) if expected.allow_idp_initiated => {},
+        (None, _) => return Err("IdP-initiated sign-in is not allowed".to_string()),
+        _ => return Err("Response does not answer this browser's request".to_string()),
+    }
+
+    if let Some(issuer) = child(response, ASSERTION_NS, "Issuer") {
+        check_issuer(issuer, idp)?;
+    }
+
+    let status_code = child(response, PROTOCOL_NS, "Status")
+        .and_then(|status| child(status, PROTOCOL_NS, "StatusCode"))
+        .and_then(|code| code.attribute("Value"))
+        .ok_or("Response has no status")?;
+
+    if status_code != STATUS_SUCCESS {
+        return Err(format!("IdP returned status {}", status_code));
+    }
+
+    if child(response, ASSERTION_NS, "EncryptedAssertion").is_some() {
+        return Err("Encrypted assertions are not supported".to_string());
+    }
+
+    let assertions: Vec<Node> = children(response, ASSERTION_NS, "Assertion").collect();
+    let [assertion] = assertions.as_slice() else {
+        return Err("Response must contain exactly one assertion".to_string());
+    };
+    let assertion = *assertion;
+
+    let response_signed = verify_signature(response, idp)?;
+    let assertion_signed = verify_signature(assertion, idp)?;
+
+    if !response_signed && !assertion_signed {
+        return Err("Neither the response nor the assertion is signed".to_string());
+    }
+
+    check_issuer(child(assertion, ASSERTION_NS, "Issuer").ok_or("Assertion has no issuer")?, idp)?;
+
+    let now = Utc::now();
+    let skew = Duration::seconds(CLOCK_SKEW);
+
+    let subject = child(assertion, ASSERTION_NS, "Subject").ok_or("Assertion has no subject")?;
+
+    let confirmation_expiry = children(subject, ASSERTION_NS, "SubjectConfirmation")
+        .filter(|confirmation| confirmation.attribute("Method") == Some(BEARER))
+        .filter_map(|confirmation| child(confirmation, ASSERTION_NS, "SubjectConfirmationData"))
+        .find_map(|data| {
+            let not_on_or_after = timestamp(data.attribute("NotOnOrAfter")?)?;
+
+            let valid = data.attribute("Recipient") == Some(acs_url().as_str())
+                && not_on_or_after + skew > now
+                && data.attribute("InResponseTo") == in_response_to;
+
+            valid.then_some(not_on_or_after)
+        })
+        .ok_or("Assertion has no valid bearer subject confirmation")?;
+
+    let conditions = child(assertion, ASSERTION_NS, "Conditions").ok_or("Assertion has no conditions")?;
+
+    if let Some(not_before) = conditions.attribute("NotBefore") {
+        if timestamp(not_before).ok_or("Invalid NotBefore")? - skew > now {
+            return Err("Assertion is not yet valid".to_string());
+        }
+    }
+
+    let mut not_on_or_after = confirmation_expiry;
+
+    if let Some(conditions_expiry) = conditions.attribute("NotOnOrAfter") {
+        let conditions_expiry = timestamp(conditions_expiry).ok_or("Invalid NotOnOrAfter")?;
+
+        if conditions_expiry + skew <= now {
+            return Err("Assertion has expired".to_string());
+        }
+
+        not_on_or_after = not_on_or_after.min(conditions_expiry);
+    }
+
+    let audience_restrictions: Vec<Node> = children(conditions, ASSERTION_NS, "AudienceRestriction").collect();
+    let mut intended_for_us = !audience_restrictions.is_empty();
+
+    for restriction in audience_restrictions {
+        let mut audiences = Vec::new();
+        for audience in children(restriction, ASSERTION_NS, "Audience") {
+            audiences.push(text(audience)?);
+        }
+
+        intended_for_us &= audiences.contains(&expected.sp_entity_id);
+    }
+
+    if !intended_for_us {
+        return Err("Assertion is not intended for this service provider".to_string());
+    }
+
+    let name_id = match child(subject, ASSERTION_NS, "NameID") {
+        Some(name_id) => Some(text(name_id)?.to_string()).filter(|name_id| !name_id.is_empty()),
+        None => None,
+    };
+
+    let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+
+    for statement in children(assertion, ASSERTION_NS, "AttributeStatement") {
+        for attribute in children(statement, ASSERTION_NS, "Attribute") {
+            let Some(name) = attribute.attribute("Name") else {
+                continue;
+            };
+
+            let values = attributes.entry(name.to_string()).or_default();
+
+            for value in children(attribute, ASSERTION_NS, "AttributeValue") {
+                let value = text(value)?;
+
+                if !value.is_empty() {
+                    values.push(value.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(SamlAssertion {
+        id: assertion.attribute("ID").ok_or("Assertion has no ID")?.to_string(),
+        name_id,
+        attributes,
+        not_on_or_after,
+    })
+}
+
+fn check_issuer(issuer: Node, idp: &IdentityProvider) -> Result<(), String> {
+    match text(issuer)? {
+        issuer if issuer == idp.entity_id => Ok(()),
+        issuer => Err(format!("Unexpected issuer {}", issuer)),
+    }
+}
+
+/// The trimmed text of an element that holds nothing but text. The
+/// signature covers the text without comments, so `a<!---->b` is signed as
+/// `ab` while `Node::text` would read `a`; such values are refused rather
+/// than read differently from how they were signed.
+fn text<'a>(element: Node<'a, '_>) -> Result<&'a str, String> {
+    let mut nodes = element.children();
+
+    match (nodes.next(), nodes.next()) {
+        (None, _) => Ok(""),
+        (Some(node), None) if node.is_text() => Ok(node.text().unwrap_or_default().trim()),
+        _ => Err(format!("{} must contain only text", element.tag_name().name())),
+    }
+}
+
+/// Verifies the enveloped XML signature of `element`, if it has one.
+/// Only exclusive canonicalization with RSA-SHA256 and SHA-256 digests is
+/// accepted, and the signature must reference `element` itself.
+fn verify_signature(element: Node, idp: &IdentityProvider) -> Result<bool, String> {
+    let Some(signature) = child(element, DSIG_NS, "Signature") else {
+        return Ok(false);
+    };
+
+    let signed_info = child(signature, DSIG_NS, "SignedInfo").ok_or("Signature has no SignedInfo")?;
+
+    let canonicalization = child(signed_info, DSIG_NS, "CanonicalizationMethod")
+        .filter(|method| method.attribute("Algorithm") == Some(EXC_C14N))
+        .ok_or("Unsupported canonicalization method")?;
+
+    if child(signed_info, DSIG_NS, "SignatureMethod").and_then(|method| method.attribute("Algorithm")) != Some(RSA_SHA256) {
+        return Err("Unsupported signature method".to_string());
+    }
+
+    let references: Vec<Node> = children(signed_info, DSIG_NS, "Reference").collect();
+    let [reference] = references.as_slice() else {
+        return Err("Signature must have exactly one reference".to_string());
+    };
+
+    let id = element.attribute("ID").ok_or("Signed element has no ID")?;
+
+    if reference.attribute("URI") != Some(format!("#{}", id).as_str()) {
+        return Err("Signature does not reference the signed element".to_string());
+    }
+
+    let mut inclusive_prefixes = Vec::new();
+
+    if let Some(transforms) = child(*reference, DSIG_NS, "Transforms") {
+        for transform in children(transforms, DSIG_NS, "Transform") {
+            match transform.attribute("Algorithm") {
+                Some(ENVELOPED_SIGNATURE) => {},
+                Some(EXC_C14N) => inclusive_prefixes = inclusive_namespaces(transform),
+                algorithm => return Err(format!("Unsupported transform {}", algorithm.unwrap_or_default())),
+            }
+        }
+    }
+
+    if child(*reference, DSIG_NS, "DigestMethod").and_then(|method| method.attribute("Algorithm")) != Some(SHA256) {
+        return Err("Unsupported digest method".to_string());
+    }
+
+    let digest_value = decode_base64_text(child(*reference, DSIG_NS, "DigestValue"))?;
+    let digest = Sha256::digest(canonicalize(element, Some(signature), &inclusive_prefixes).as_bytes());
+
+    if digest.as_slice() != digest_value.as_slice() {
+        return Err("Digest of the signed element does not match".to_string());
+    }
+
+    let signature_value = decode_base64_text(child(signature, DSIG_NS, "SignatureValue"))?;
+    let signature_value = Signature::try_from(signature_value.as_slice()).map_err(|e| e.to_string())?;
+
+    let signed_info = canonicalize(signed_info, None, &inclusive_namespaces(canonicalization));
+
+    VerifyingKey::<Sha256>::new(idp.public_key.clone())
+        .verify(signed_info.as_bytes(), &signature_value)
+        .map_err(|_| "Signature is not valid for the IdP's certificate".to_string())?;
+
+    Ok(true)
+}
+
+/// The `PrefixList` of an exclusive canonicalization's `InclusiveNamespaces`.
+fn inclusive_namespaces(method: Node) -> Vec<String> {
+    child(method, EXC_C14N, "InclusiveNamespaces")
+        .and_then(|namespaces| namespaces.attribute("PrefixList"))
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|prefix| if prefix == "#default" { String::new() } else { prefix.to_string() })
+        .collect()
+}
+
+fn decode_base64_text(node: Option<Node>) -> Result<Vec<u8>, String> {
+    let text: String = node
+        .and_then(|node| node.text())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+
+    STANDARD.decode(text).map_err(|e| e.to_string())
+}
+
+/// Exclusive XML canonicalization without comments
+/// (https://www.w3.org/TR/xml-exc-c14n/) of `element`, leaving out
+/// `exclude` for the enveloped-signature transform.
+fn canonicalize(element: Node, exclude: Option<Node>, inclusive_prefixes: &[String]) -> String {
+    let mut output = String::new();
+    write_canonical(element, exclude, inclusive_prefixes, &HashMap::new(), &mut output);
+
+    output
+}
+
+fn write_canonical(
+    element: Node,
+    exclude: Option<Node>,
+    inclusive_prefixes: &[String],
+    rendered: &HashMap<String, String>,
+    output: &mut String,
+) {
+    let source = element.document().input_text();
+    let name = qualified_name(&source[element.range().start + 1..]);
+
+    // Namespaces are rendered where they are visibly used, unless an
+    // output ancestor already declared the same one.
+    let mut prefixes = vec![prefix(name).to_string()];
+
+    for attribute in element.attributes() {
+        let attribute_prefix = prefix(&source[attribute.range_qname()]);
+
+        if !attribute_prefix.is_empty() && attribute_prefix != "xml" {
+            prefixes.push(attribute_prefix.to_string());
+        }
+    }
+
+    for inclusive_prefix in inclusive_prefixes {
+        let in_scope = element.namespaces().any(|ns| ns.name().unwrap_or_default() == inclusive_prefix);
+
+        if in_scope {
+            prefixes.push(inclusive_prefix.to_owned());
+        }
+    }
+
+    prefixes.sort();
+    prefixes.dedup();
+
+    let mut rendered = rendered.clone();
+    let mut declarations = Vec::new();
+
+    for prefix in prefixes {
+        let uri = element.namespaces()
+            .find(|ns| ns.name().unwrap_or_default() == prefix)
+            .map(|ns| ns.uri())
+            .unwrap_or_default();
+
+        let already_rendered = rendered.get(&prefix).map(String::as_str).unwrap_or_default() == uri;
+
+        if !already_rendered {
+            declarations.push(if prefix.is_empty() {
+                format!(r#" xmlns="{}""#, escape_attribute(uri))
+            } else {
+                format!(r#" xmlns:{}="{}""#, prefix, escape_attribute(uri))
+            });
+            rendered.insert(prefix, uri.to_string());
+        }
+    }
+
+    let mut attributes: Vec<_> = element.attributes().collect();
+    attributes.sort_by(|a, b| (a.namespace().unwrap_or_default(), a.name()).cmp(&(b.namespace().unwrap_or_default(), b.name())));
+
+    output.push('<');
+    output.push_str(name);
+
+    for declaration in declarations {
+        output.push_str(&declaration);
+    }
+
+    for attribute in attributes {
+        output.push(' ');
+        output.push_str(&source[attribute.range_qname()]);
+        output.push_str("=\"");
+        output.push_str(&escape_attribute(attribute.value()));
+        output.push('"');
+    }
+
+    output.push('>');
+
+    for node in element.children() {
+        if Some(node) == exclude {
+            continue;
+        }
+
+        match node.node_type() {
+            NodeType::Element => write_canonical(node, exclude, inclusive_prefixes, &rendered, output),
+            NodeType::Text => output.push_str(&escape_text(node.text().unwrap_or_default())),
+            NodeType::PI => {
+                if let Some(pi) = node.pi() {
+                    output.push_str("<?");
+                    output.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        output.push(' ');
+                        output.push_str(value);
+                    }
+                    output.push_str("?>");
+                }
+            },
+            NodeType::Comment | NodeType::Root => {},
+        }
+    }
+
+    output.push_str("</");
+    output.push_str(name);
+    output.push('>');
+}
+
+/// The element name at the start of its source text, as written.
+fn qualified_name(tag: &str) -> &str {
+    let end = tag
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .unwrap_or(tag.len());
+
+    &tag[..end]
+}
+
+fn prefix(name: &str) -> &str {
+    name.split_once(':').map(|(prefix, _)| prefix).unwrap_or_default()
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+fn timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn is(node: Node, namespace: &str, name: &str) -> bool {
+    node.is_element() && node.tag_name().namespace() == Some(namespace) && node.tag_name().name() == name
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, namespace: &'static str, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, namespace, name).next()
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &'static str,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| is(*child, namespace, name))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::OnceLock;
+
+    use argon2::password_hash::rand_core::OsRng;
+    use rsa::{
+        pkcs1v15::SigningKey,
+        signature::{SignatureEncoding, Signer},
+        RsaPrivateKey
+    };
+
+    use super::*;
+
+    pub(crate) const IDP_ENTITY_ID: &str = "https://idp.example.com";
+    pub(crate) const SP_ENTITY_ID: &str = "http://localhost:8000/api/saml/metadata";
+    const REQUEST_ID: &str = "_request";
+
+    fn idp_key() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut OsRng, 1024).unwrap())
+    }
+
+    /// The IdP whose key signs every `TestAssertion`.
+    pub(crate) fn identity_provider() -> IdentityProvider {
+        IdentityProvider {
+            entity_id: IDP_ENTITY_ID.to_string(),
+            sso_url: "https://idp.example.com/sso".to_string(),
+            public_key: idp_key().to_public_key(),
+        }
+    }
+
+    /// An assertion as a well-behaved IdP would send it, with the fields
+    /// tests change.
+    pub(crate) struct TestAssertion {
+        pub id: String,
+        pub in_response_to: Option<String>,
+        pub audience: String,
+        pub name_id: String,
+        pub role: String,
+        pub not_on_or_after: DateTime<Utc>,
+    }
+
+    impl Default for TestAssertion {
+        fn default() -> Self {
+            Self {
+                id: "_assertion".to_string(),
+                in_response_to: Some(REQUEST_ID.to_string()),
+                audience: SP_ENTITY_ID.to_string(),
+                name_id: "alice@corp.com".to_string(),
+                role: "user".to_string(),
+                not_on_or_after: Utc::now() + Duration::minutes(5),
+            }
+        }
+    }
+
+    impl TestAssertion {
+        fn assertion_xml(&self) -> String {
+            let now = Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+            let not_on_or_after = self.not_on_or_after.format("%Y-%m-%dT%H:%M:%SZ");
+            let in_response_to = self.in_response_to
+                .as_ref()
+                .map(|id| format!(r#" InResponseTo="{}""#, id))
+                .unwrap_or_default();
+
+            format!(
+                concat!(
+                    r#"<saml:Assertion xmlns:saml="{ns}" ID="{id}" Version="2.0" IssueInstant="{now}">"#,
+                    r#"<saml:Issuer>{issuer}</saml:Issuer>"#,
+                    r#"<saml:Subject><saml:NameID Format="{format}">{name_id}</saml:NameID>"#,
+                    r#"<saml:SubjectConfirmation Method="{bearer}">"#,
+                    r#"<saml:SubjectConfirmationData{in_response_to} Recipient="{acs}" NotOnOrAfter="{not_on_or_after}"/>"#,
+                    r#"</saml:SubjectConfirmation></saml:Subject>"#,
+                    r#"<saml:Conditions NotBefore="{now}" NotOnOrAfter="{not_on_or_after}">"#,
+                    r#"<saml:AudienceRestriction><saml:Audience>{audience}</saml:Audience></saml:AudienceRestriction>"#,
+                    r#"</saml:Conditions>"#,
+                    r#"<saml:AttributeStatement>"#,
+                    r#"<saml:Attribute Name="email"><saml:AttributeValue>{name_id}</saml:AttributeValue></saml:Attribute>"#,
+                    r#"<saml:Attribute Name="role"><saml:AttributeValue>{role}</saml:AttributeValue></saml:Attribute>"#,
+                    r#"</saml:AttributeStatement>"#,
+                    r#"</saml:Assertion>"#
+                ),
+                ns = ASSERTION_NS,
+                id = self.id,
+                now = now,
+                issuer = IDP_ENTITY_ID,
+                format = EMAIL_NAME_ID_FORMAT,
+                name_id = self.name_id,
+                bearer = BEARER,
+                in_response_to = in_response_to,
+                acs = acs_url(),
+                not_on_or_after = not_on_or_after,
+                audience = self.audience,
+                role = self.role,
+            )
+        }
+
+        /// The assertion with an enveloped signature by the IdP's key.
+        pub(crate) fn signed(&self) -> String {
+            sign(&self.assertion_xml(), &self.id)
+        }
+
+        /// A response carrying `assertion`, answering `in_response_to`.
+        pub(crate) fn response(&self, assertion: &str) -> String {
+            let in_response_to = self.in_response_to
+                .as_ref()
+                .map(|id| format!(r#" InResponseTo="{}""#, id))
+                .unwrap_or_default();
+
+            format!(
+                concat!(
+                    r#"<samlp:Response xmlns:samlp="{}" xmlns:saml="{}" ID="_response" Version="2.0"{} Destination="{}">"#,
+                    r#"<saml:Issuer>{}</saml:Issuer>"#,
+                    r#"<samlp:Status><samlp:StatusCode Value="{}"/></samlp:Status>"#,
+                    r#"{}"#,
+                    r#"</samlp:Response>"#
+                ),
+                PROTOCOL_NS,
+                ASSERTION_NS,
+                in_response_to,
+                acs_url(),
+                IDP_ENTITY_ID,
+                STATUS_SUCCESS,
+                assertion,
+            )
+        }
+
+        /// The base64 `SAMLResponse` an IdP would post for this assertion.
+        pub(crate) fn encoded_response(&self) -> String {
+            STANDARD.encode(self.response(&self.signed()))
+        }
+    }
+
+    /// Inserts an enveloped signature over the element with `id` after its
+    /// `Issuer`, the way IdPs place it.
+    fn sign(xml: &str, id: &str) -> String {
+        let document = Document::parse(xml).unwrap();
+        let element = document.descendants().find(|node| node.attribute("ID") == Some(id)).unwrap();
+        let digest = STANDARD.encode(Sha256::digest(canonicalize(element, None, &[]).as_bytes()));
+
+        let signature = format!(
+            concat!(
+                r#"<ds:Signature xmlns:ds="{dsig}"><ds:SignedInfo>"#,
+                r#"<ds:CanonicalizationMethod Algorithm="{c14n}"/>"#,
+                r#"<ds:SignatureMethod Algorithm="{rsa}"/>"#,
+                r#"<ds:Reference URI="{uri}"><ds:Transforms>"#,
+                r#"<ds:Transform Algorithm="{enveloped}"/><ds:Transform Algorithm="{c14n}"/>"#,
+                r#"</ds:Transforms><ds:DigestMethod Algorithm="{sha256}"/>"#,
+                r#"<ds:DigestValue>{digest}</ds:DigestValue></ds:Reference>"#,
+                r#"</ds:SignedInfo><ds:SignatureValue>SIGNATURE</ds:SignatureValue></ds:Signature>"#
+            ),
+            dsig = DSIG_NS,
+            c14n = EXC_C14N,
+            rsa = RSA_SHA256,
+            uri = format!("#{}", id),
+            enveloped = ENVELOPED_SIGNATURE,
+            sha256 = SHA256,
+            digest = digest,
+        );
+
+        let element_start = xml.find(&format!(r#"ID="{}""#, id)).unwrap();
+        let issuer_end = element_start + xml[element_start..].find("</saml:Issuer>").unwrap() + "</saml:Issuer>".len();
+        let unsigned = format!("{}{}{}", &xml[..issuer_end], signature, &xml[issuer_end..]);
+
+        let document = Document::parse(&unsigned).unwrap();
+        let signed_info = document.descendants().find(|node| is(*node, DSIG_NS, "SignedInfo")).unwrap();
+
+        let signature_value = SigningKey::<Sha256>::new(idp_key().clone())
+            .sign(canonicalize(signed_info, None, &[]).as_bytes())
+            .to_bytes();
+
+        unsigned.replace("SIGNATURE", &STANDARD.encode(signature_value))
+    }
+
+    fn expectations() -> ResponseExpectations<'static> {
+        ResponseExpectations {
+            sp_entity_id: SP_ENTITY_ID,
+            request_id: Some(REQUEST_ID),
+            allow_idp_initiated: false,
+        }
+    }
+
+    fn parse(xml: &str) -> Result<SamlAssertion, String> {
+        parse_response(&STANDARD.encode(xml), &identity_provider(), &expectations())
+    }
+
+    #[test]
+    fn accepts_a_signed_assertion() {
+        let fixture = TestAssertion::default();
+
+        let assertion = parse(&fixture.response(&fixture.signed())).unwrap();
+
+        assert_eq!(assertion.id, "_assertion");
+        assert_eq!(assertion.name_id.as_deref(), Some("alice@corp.com"));
+        assert_eq!(assertion.attribute("email"), ["alice@corp.com"]);
+        assert_eq!(assertion.attribute("role"), ["user"]);
+    }
+
+    #[test]
+    fn rejects_a_tampered_attribute() {
+        let fixture = TestAssertion::default();
+        let signed = fixture.signed();
+
+        let tampered = signed.replace("<saml:AttributeValue>user<", "<saml:AttributeValue>admin<");
+        assert_ne!(tampered, signed);
+
+        let error = parse(&fixture.response(&tampered)).unwrap_err();
+        assert_eq!(error, "Digest of the signed element does not match");
+
+        let unsigned = fixture.response(&fixture.assertion_xml());
+        assert_eq!(parse(&unsigned).unwrap_err(), "Neither the response nor the assertion is signed");
+    }
+
+    #[test]
+    fn rejects_signature_wrapping() {
+        let fixture = TestAssertion::default();
+        let signed = fixture.signed();
+        let forged = TestAssertion { role: "admin".to_string(), ..TestAssertion::default() }.assertion_xml();
+
+        // A forged assertion next to the signed one.
+        let response = fixture.response(&format!("{}{}", forged.replace("_assertion", "_forged"), signed));
+        assert!(parse(&response).is_err());
+
+        // The signed assertion hidden inside a forged one with the same ID.
+        let wrapped = forged.replace("</saml:Assertion>", &format!("{}</saml:Assertion>", signed));
+        assert!(parse(&fixture.response(&wrapped)).unwrap_err().starts_with("Duplicate ID"));
+
+        // The signature moved to a forged assertion, still pointing at the original.
+        let signature_start = signed.find("<ds:Signature").unwrap();
+        let signature_end = signed.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+        let signature = signed[signature_start..signature_end].replace(
+            "</ds:Signature>",
+            &format!("<ds:Object>{}</ds:Object></ds:Signature>", fixture.assertion_xml()),
+        );
+        let moved = forged
+            .replace("_assertion", "_forged")
+            .replacen("</saml:Issuer>", &format!("</saml:Issuer>{}", signature), 1);
+
+        let error = parse(&fixture.response(&moved)).unwrap_err();
+        assert_eq!(error, "Signature does not reference the signed element");
+    }
+
+    #[test]
+    fn rejects_comments_inside_signed_values() {
+        let fixture = TestAssertion { name_id: "admin@corp.com<!---->.evil.com".to_string(), ..TestAssertion::default() };
+
+        let error = parse(&fixture.response(&fixture.signed())).unwrap_err();
+        assert_eq!(error, "NameID must contain only text");
+
+        let fixture = TestAssertion { role: "user<!-- -->admin".to_string(), ..TestAssertion::default() };
+        let error = parse(&fixture.response(&fixture.signed())).unwrap_err();
+        assert_eq!(error, "AttributeValue must contain only text");
+
+        let fixture = TestAssertion::default();
+        let response = fixture.response(&fixture.signed()).replacen(
+            &format!("<saml:Issuer>{}</saml:Issuer>", IDP_ENTITY_ID),
+            &format!("<saml:Issuer>{}<!---->.evil.com</saml:Issuer>", IDP_ENTITY_ID),
+            1,
+        );
+        assert_eq!(parse(&response).unwrap_err(), "Issuer must contain only text");
+    }
+
+    #[test]
+    fn rejects_the_wrong_audience_or_request() {
+        let fixture = TestAssertion { audience: "https://other-sp.example.com".to_string(), ..TestAssertion::default() };
+        assert_eq!(
+            parse(&fixture.response(&fixture.signed())).unwrap_err(),
+            "Assertion is not intended for this service provider"
+        );
+
+        let fixture = TestAssertion { in_response_to: Some("_another_request".to_string()), ..TestAssertion::default() };
+        assert_eq!(
+            parse(&fixture.response(&fixture.signed())).unwrap_err(),
+            "Response does not answer this browser's request"
+        );
+
+        // An unsolicited response is refused unless IdP-initiated sign-in is allowed.
+        let fixture = TestAssertion { in_response_to: None, ..TestAssertion::default() };
+        assert_eq!(
+            parse(&fixture.response(&fixture.signed())).unwrap_err(),
+            "IdP-initiated sign-in is not allowed"
+        );
+    }
+
+    #[test]
+    fn rejects_an_expired_assertion() {
+        let fixture = TestAssertion { not_on_or_after: Utc::now() - Duration::minutes(10), ..TestAssertion::default() };
+
+        assert_eq!(
+            parse(&fixture.response(&fixture.signed())).unwrap_err(),
+            "Assertion has no valid bearer subject confirmation"
+        );
+    }
+}
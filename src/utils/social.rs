@@ -0,0 +1,225 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::config::SocialProviderConfig;
+use crate::utils::oauth::{redirect_uri_with, PKCE_METHOD};
+
+/// Cookie that carries the state of a sign-in with an external provider
+/// while the browser is away at the provider.
+pub const SOCIAL_STATE_COOKIE: &str = "social_state";
+
+/// Lifetime of the social sign-in state, in minutes.
+pub const SOCIAL_STATE_MAXAGE: i64 = 10;
+
+/// What the provider told us about the account that signed in.
+#[derive(Debug, Clone)]
+pub struct SocialProfile {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+/// What the token endpoint returned for an authorization code.
+#[derive(Debug, Clone)]
+pub struct SocialTokens {
+    pub access_token: String,
+    pub id_token: Option<String>,
+}
+
+/// The claims of a verified OpenID Connect ID token this flow relies on.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+}
+
+pub fn callback_url(provider: &SocialProviderConfig) -> String {
+    format!("http://localhost:8000/api/auth/social/{}/callback", provider.name)
+}
+
+/// Generates a PKCE code verifier (RFC 7636) and its S256 challenge.
+pub fn generate_pkce() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    let code_verifier = URL_SAFE_NO_PAD.encode(bytes);
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    (code_verifier, code_challenge)
+}
+
+pub fn authorization_url(
+    provider: &SocialProviderConfig,
+    state: &str,
+    code_challenge: &str,
+    nonce: Option<&str>,
+) -> String {
+    let redirect_uri = callback_url(provider);
+
+    redirect_uri_with(&provider.authorize_url, &[
+        ("response_type", Some("code")),
+        ("client_id", Some(&provider.client_id)),
+        ("redirect_uri", Some(&redirect_uri)),
+        ("scope", Some(&provider.scopes)),
+        ("state", Some(state)),
+        ("code_challenge", Some(code_challenge)),
+        ("code_challenge_method", Some(PKCE_METHOD)),
+        ("nonce", nonce),
+    ])
+}
+
+/// Redeems an authorization code at the provider's token endpoint.
+pub async fn exchange_code(
+    http_client: &reqwest::Client,
+    provider: &SocialProviderConfig,
+    code: &str,
+    code_verifier: &str,
+) -> Result<SocialTokens, String> {
+    let redirect_uri = callback_url(provider);
+
+    let response: Value = http_client
+        .post(&provider.token_url)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &redirect_uri),
+            ("client_id", &provider.client_id),
+            ("client_secret", &provider.client_secret),
+            ("code_verifier", code_verifier),
+        ])
+        .send()
+        .await
+        .map_err(|e| format!("Token request to {} failed: {}", provider.name, e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid token response from {}: {}", provider.name, e))?;
+
+    if let Some(error) = response.get("error").and_then(Value::as_str) {
+        return Err(format!("{} rejected the authorization code: {}", provider.name, error));
+    }
+
+    let access_token = response
+        .get("access_token")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| format!("{} did not return an access token", provider.name))?;
+
+    Ok(SocialTokens {
+        access_token,
+        id_token: response.get("id_token").and_then(Value::as_str).map(str::to_string),
+    })
+}
+
+/// Verifies an OpenID Connect ID token against the provider's JWKS: it
+/// must be signed with one of its asymmetric keys, issued by its issuer to
+/// our client id, unexpired, and carry the nonce the sign-in started with.
+pub async fn verify_id_token(
+    http_client: &reqwest::Client,
+    provider: &SocialProviderConfig,
+    id_token: &str,
+    nonce: Option<&str>,
+) -> Result<IdTokenClaims, String> {
+    let (Some(issuer), Some(jwks_url)) = (&provider.issuer, &provider.jwks_url) else {
+        return Err(format!("{} is not an OpenID Connect provider", provider.name));
+    };
+
+    let header = jsonwebtoken::decode_header(id_token)
+        .map_err(|e| format!("Invalid ID token from {}: {}", provider.name, e))?;
+
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        return Err(format!("{} signed the ID token with an unsupported algorithm", provider.name));
+    }
+
+    let jwks: JwkSet = http_client
+        .get(jwks_url)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await
+        .map_err(|e| format!("JWKS request to {} failed: {}", provider.name, e))?
+        .error_for_status()
+        .map_err(|e| format!("JWKS request to {} failed: {}", provider.name, e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid JWKS from {}: {}", provider.name, e))?;
+
+    // Without a kid the provider must publish a single key.
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| format!("{} signed the ID token with an unknown key", provider.name))?;
+
+    let decoding_key = DecodingKey::from_jwk(jwk)
+        .map_err(|e| format!("Invalid JWKS from {}: {}", provider.name, e))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+        .map_err(|e| format!("Invalid ID token from {}: {}", provider.name, e))?
+        .claims;
+
+    if claims.nonce.as_deref() != nonce {
+        return Err(format!("The ID token from {} is not for this sign-in", provider.name));
+    }
+
+    Ok(claims)
+}
+
+/// Fetches the signed-in account from the provider's userinfo endpoint.
+pub async fn fetch_profile(
+    http_client: &reqwest::Client,
+    provider: &SocialProviderConfig,
+    access_token: &str,
+) -> Result<SocialProfile, String> {
+    let response = http_client
+        .get(&provider.userinfo_url)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .bearer_auth(access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Userinfo request to {} failed: {}", provider.name, e))?
+        .error_for_status()
+        .map_err(|e| format!("Userinfo request to {} failed: {}", provider.name, e))?;
+
+    let claims: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid userinfo response from {}: {}", provider.name, e))?;
+
+    let subject = claim(&claims, &provider.subject_claim)
+        .ok_or_else(|| format!("{} did not return a {} claim", provider.name, provider.subject_claim))?;
+
+    // Some providers send booleans as strings.
+    let email_verified = match claims.get(&provider.email_verified_claim) {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+
+    Ok(SocialProfile {
+        subject,
+        email: claim(&claims, &provider.email_claim).map(|email| email.to_lowercase()),
+        email_verified,
+        name: claim(&claims, &provider.name_claim),
+    })
+}
+
+/// Reads a claim as a string. Numeric ids, as GitHub returns, are kept in
+/// their decimal form.
+fn claim(claims: &Value, name: &str) -> Option<String> {
+    match claims.get(name)? {
+        Value::String(value) if !value.is_empty() => Some(value.to_owned()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
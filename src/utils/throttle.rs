@@ -0,0 +1,446 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex}
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    config::Config,
+    database::DBClient,
+    error::{ErrorMessage, HttpError}
+};
+
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The attempts recorded for a key inside the current window.
+#[derive(Debug, Clone, Copy)]
+pub struct AttemptWindow {
+    pub count: i64,
+    pub oldest: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lockout {
+    pub locked_until: DateTime<Utc>,
+    pub lockout_count: i32,
+}
+
+/// Storage backend for login attempts and account lockouts.
+#[async_trait]
+pub trait AttemptStore: fmt::Debug + Send + Sync {
+    /// Records an attempt for `key`, forgets attempts made before
+    /// `window_start` and returns what is left in the window.
+    async fn record_attempt(
+        &self,
+        key: &str,
+        window_start: DateTime<Utc>,
+    ) -> Result<AttemptWindow, StoreError>;
+
+    async fn clear_attempts(&self, key: &str) -> Result<(), StoreError>;
+
+    async fn get_lockout(&self, key: &str) -> Result<Option<Lockout>, StoreError>;
+
+    async fn save_lockout(&self, key: &str, lockout: Lockout) -> Result<(), StoreError>;
+
+    async fn clear_lockout(&self, key: &str) -> Result<(), StoreError>;
+}
+
+/// Keeps attempts and lockouts in this process. A restart unlocks every
+/// account and forgets spent MFA challenges, and each instance counts on its
+/// own. A key's old attempts are dropped when it records its next one.
+/// Lockouts stay until the account logs in successfully and spent MFA
+/// challenges until the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryAttemptStore {
+    attempts: Mutex<HashMap<String, VecDeque<DateTime<Utc>>>>,
+    lockouts: Mutex<HashMap<String, Lockout>>,
+}
+
+#[async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn record_attempt(
+        &self,
+        key: &str,
+        window_start: DateTime<Utc>,
+    ) -> Result<AttemptWindow, StoreError> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let entry = attempts.entry(key.to_string()).or_default();
+
+        while entry.front().is_some_and(|attempted_at| *attempted_at < window_start) {
+            entry.pop_front();
+        }
+        entry.push_back(Utc::now());
+
+        Ok(AttemptWindow {
+            count: entry.len() as i64,
+            oldest: entry.front().copied(),
+        })
+    }
+
+    async fn clear_attempts(&self, key: &str) -> Result<(), StoreError> {
+        self.attempts.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+
+    async fn get_lockout(&self, key: &str) -> Result<Option<Lockout>, StoreError> {
+        Ok(self.lockouts.lock().unwrap().get(key).copied())
+    }
+
+    async fn save_lockout(&self, key: &str, lockout: Lockout) -> Result<(), StoreError> {
+        self.lockouts.lock().unwrap().insert(key.to_string(), lockout);
+
+        Ok(())
+    }
+
+    async fn clear_lockout(&self, key: &str) -> Result<(), StoreError> {
+        self.lockouts.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+}
+
+/// Sliding-window login limits per IP address and per account, with an
+/// account lockout that doubles in length each time it is triggered.
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    store: Arc<dyn AttemptStore>,
+    window: Duration,
+    max_attempts_per_ip: i64,
+    max_failures_per_email: i64,
+    lockout_seconds: i64,
+    lockout_max_seconds: i64,
+    max_challenge_attempts: i64,
+}
+
+impl LoginThrottle {
+    pub fn new(store: Arc<dyn AttemptStore>, config: &Config) -> Self {
+        Self {
+            store,
+            window: Duration::seconds(config.login_attempt_window),
+            max_attempts_per_ip: config.login_max_attempts_per_ip,
+            max_failures_per_email: config.login_max_failures_per_email,
+            lockout_seconds: config.login_lockout_seconds,
+            lockout_max_seconds: config.login_lockout_max_seconds,
+            max_challenge_attempts: config.mfa_max_attempts,
+        }
+    }
+
+    pub fn from_config(
+        config: &Config,
+        db_client: DBClient,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let store: Arc<dyn AttemptStore> = match config.login_throttle_backend.as_str() {
+            "postgres" => Arc::new(db_client),
+            "memory" => Arc::new(MemoryAttemptStore::default()),
+            backend => return Err(format!("Unsupported LOGIN_THROTTLE_BACKEND: {}", backend).into()),
+        };
+
+        Ok(Self::new(store, config))
+    }
+
+    /// Counts a login attempt from `ip` and rejects it once the limit for
+    /// the window is exceeded.
+    pub async fn check_ip(&self, ip: IpAddr) -> Result<(), HttpError> {
+        let window = self.store
+            .record_attempt(&format!("ip:{}", ip), Utc::now() - self.window)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if window.count > self.max_attempts_per_ip {
+            let oldest = window.oldest.unwrap_or_else(Utc::now);
+
+            return Err(HttpError::too_many_requests(
+                ErrorMessage::TooManyLoginAttempts.to_string(),
+                retry_after(oldest + self.window),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn check_account(&self, email: &str) -> Result<(), HttpError> {
+        let lockout = self.store
+            .get_lockout(&email_key(email))
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        match lockout {
+            Some(lockout) if lockout.locked_until > Utc::now() => Err(HttpError::too_many_requests(
+                ErrorMessage::AccountLocked.to_string(),
+                retry_after(lockout.locked_until),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a failed login and locks the account once it reaches the
+    /// failure limit for the window.
+    pub async fn record_failure(&self, email: &str) -> Result<(), HttpError> {
+        let key = email_key(email);
+
+        let window = self.store
+            .record_attempt(&key, Utc::now() - self.window)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if window.count < self.max_failures_per_email {
+            return Ok(());
+        }
+
+        let previous = self.store
+            .get_lockout(&key)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let lockout_count = previous.map_or(1, |lockout| lockout.lockout_count.saturating_add(1));
+        let lockout_seconds = self.lockout_seconds
+            .saturating_mul(1 << (lockout_count - 1).clamp(0, 30))
+            .min(self.lockout_max_seconds);
+
+        let lockout = Lockout {
+            locked_until: Utc::now() + Duration::seconds(lockout_seconds),
+            lockout_count,
+        };
+
+        self.store
+            .save_lockout(&key, lockout)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        self.store
+            .clear_attempts(&key)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    pub async fn record_success(&self, email: &str) -> Result<(), HttpError> {
+        let key = email_key(email);
+
+        self.store
+            .clear_attempts(&key)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        self.store
+            .clear_lockout(&key)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    /// Rejects an MFA challenge that was already completed or ran out of
+    /// attempts.
+    pub async fn check_challenge(&self, challenge_id: &str) -> Result<(), HttpError> {
+        let spent = self.store
+            .get_lockout(&challenge_key(challenge_id))
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        match spent {
+            Some(spent) if spent.locked_until > Utc::now() => {
+                Err(HttpError::unauthorized(ErrorMessage::MfaChallengeSpent.to_string()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a wrong code entered for an MFA challenge issued at
+    /// `issued_at`, and spends the challenge once it reaches the limit.
+    pub async fn record_challenge_failure(
+        &self,
+        challenge_id: &str,
+        issued_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), HttpError> {
+        let window = self.store
+            .record_attempt(&challenge_key(challenge_id), issued_at)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if window.count < self.max_challenge_attempts {
+            return Ok(());
+        }
+
+        self.spend_challenge(challenge_id, expires_at).await
+    }
+
+    /// Marks an MFA challenge as used until its token expires.
+    pub async fn spend_challenge(&self, challenge_id: &str, expires_at: DateTime<Utc>) -> Result<(), HttpError> {
+        let key = challenge_key(challenge_id);
+
+        self.store
+            .save_lockout(&key, Lockout { locked_until: expires_at, lockout_count: 1 })
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        self.store
+            .clear_attempts(&key)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+}
+
+fn challenge_key(challenge_id: &str) -> String {
+    format!("mfa:{}", challenge_id)
+}
+
+fn email_key(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+fn retry_after(until: DateTime<Utc>) -> u64 {
+    (until - Utc::now()).num_seconds().max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use axum::http::StatusCode;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_support;
+
+    fn throttle(max_failures: i64, lockout_seconds: i64, lockout_max_seconds: i64) -> LoginThrottle {
+        let mut config = test_support::config();
+        config.login_attempt_window = 600;
+        config.login_max_attempts_per_ip = 3;
+        config.login_max_failures_per_email = max_failures;
+        config.login_lockout_seconds = lockout_seconds;
+        config.login_lockout_max_seconds = lockout_max_seconds;
+
+        LoginThrottle::new(Arc::new(MemoryAttemptStore::default()), &config)
+    }
+
+    /// The lockout an account is under, or `None` when it may log in.
+    async fn locked_for(throttle: &LoginThrottle, email: &str) -> Option<u64> {
+        let error = throttle.check_account(email).await.err()?;
+
+        assert_eq!(error.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.message, ErrorMessage::AccountLocked.to_string());
+
+        error.retry_after
+    }
+
+    async fn fail(throttle: &LoginThrottle, email: &str, times: usize) {
+        for _ in 0..times {
+            throttle.record_failure(email).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_store_forgets_attempts_outside_the_window() {
+        let store = MemoryAttemptStore::default();
+        let long_ago = Utc::now() - Duration::hours(1);
+
+        assert_eq!(store.record_attempt("key", long_ago).await.unwrap().count, 1);
+        assert_eq!(store.record_attempt("key", long_ago).await.unwrap().count, 2);
+        assert_eq!(store.record_attempt("other", long_ago).await.unwrap().count, 1);
+
+        let window = store.record_attempt("key", Utc::now() + Duration::seconds(1)).await.unwrap();
+        assert_eq!(window.count, 1);
+    }
+
+    #[sqlx::test]
+    async fn postgres_store_forgets_old_attempts_of_the_recorded_key_only(pool: PgPool) {
+        let store = DBClient::new(pool);
+        let long_ago = Utc::now() - Duration::hours(1);
+
+        store.record_attempt("key", long_ago).await.unwrap();
+        store.record_attempt("key", long_ago).await.unwrap();
+
+        let window = store.record_attempt("other", Utc::now() + Duration::seconds(1)).await.unwrap();
+        assert_eq!(window.count, 1);
+
+        assert_eq!(store.record_attempt("key", long_ago).await.unwrap().count, 3);
+
+        let window = store.record_attempt("key", Utc::now() + Duration::seconds(1)).await.unwrap();
+        assert_eq!(window.count, 1);
+    }
+
+    #[tokio::test]
+    async fn limits_attempts_per_ip_within_the_window() {
+        let throttle = throttle(5, 60, 3600);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        for _ in 0..3 {
+            throttle.check_ip(ip).await.unwrap();
+        }
+
+        let error = throttle.check_ip(ip).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.message, ErrorMessage::TooManyLoginAttempts.to_string());
+        assert!((599..=600).contains(&error.retry_after.unwrap()));
+
+        throttle.check_ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn locks_the_account_once_failures_reach_the_threshold() {
+        let throttle = throttle(3, 60, 3600);
+
+        fail(&throttle, "user@example.com", 2).await;
+        assert_eq!(locked_for(&throttle, "user@example.com").await, None);
+
+        fail(&throttle, "User@Example.com ", 1).await;
+        let retry_after = locked_for(&throttle, "user@example.com").await.unwrap();
+        assert!((59..=60).contains(&retry_after));
+
+        assert_eq!(locked_for(&throttle, "other@example.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn doubles_each_lockout_up_to_the_maximum() {
+        let throttle = throttle(2, 60, 200);
+
+        for expected in [60, 120, 200, 200] {
+            fail(&throttle, "user@example.com", 2).await;
+
+            let retry_after = locked_for(&throttle, "user@example.com").await.unwrap();
+            assert!((expected - 1..=expected).contains(&retry_after), "{} != {}", retry_after, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn success_clears_failures_and_lockouts() {
+        let throttle = throttle(2, 60, 3600);
+
+        fail(&throttle, "user@example.com", 4).await;
+        assert!(locked_for(&throttle, "user@example.com").await.is_some());
+
+        throttle.record_success("user@example.com").await.unwrap();
+        assert_eq!(locked_for(&throttle, "user@example.com").await, None);
+
+        // The next lockout starts over at the base length.
+        fail(&throttle, "user@example.com", 1).await;
+        assert_eq!(locked_for(&throttle, "user@example.com").await, None);
+
+        fail(&throttle, "user@example.com", 1).await;
+        let retry_after = locked_for(&throttle, "user@example.com").await.unwrap();
+        assert!((59..=60).contains(&retry_after));
+    }
+
+    #[tokio::test]
+    async fn spends_mfa_challenges_after_too_many_wrong_codes() {
+        let mut config = test_support::config();
+        config.mfa_max_attempts = 2;
+        let throttle = LoginThrottle::new(Arc::new(MemoryAttemptStore::default()), &config);
+
+        let issued_at = Utc::now() - Duration::seconds(10);
+        let expires_at = Utc::now() + Duration::minutes(5);
+
+        throttle.record_challenge_failure("challenge", issued_at, expires_at).await.unwrap();
+        throttle.check_challenge("challenge").await.unwrap();
+
+        throttle.record_challenge_failure("challenge", issued_at, expires_at).await.unwrap();
+        let error = throttle.check_challenge("challenge").await.unwrap_err();
+        assert_eq!(error.message, ErrorMessage::MfaChallengeSpent.to_string());
+
+        throttle.check_challenge("other").await.unwrap();
+    }
+}
@@ -1,62 +1,516 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use axum::http::StatusCode;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{
     encode,
     decode,
-    Algorithm,
-    EncodingKey, 
-    DecodingKey, 
+    decode_header,
     Header, 
     Validation
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::error::{ErrorMessage, HttpError};
+use crate::{
+    config::Config,
+    error::{ErrorMessage, HttpError},
+    models::User
+};
+
+use super::{keys::KeyRing, oauth::CONSENT_TOKEN_MAXAGE, saml::SAML_REQUEST_MAXAGE, social::SOCIAL_STATE_MAXAGE};
+
+/// Scope granted to tokens issued for an interactive login.
+pub const DEFAULT_SCOPE: &str = "profile email";
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Lifetime of an MFA challenge token, in minutes.
+pub const MFA_TOKEN_MAXAGE: i64 = 5;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TokenClaims {
+    pub iss: String,
+    pub aud: String,
     pub sub: String,
+    pub jti: String,
+    pub sid: String,
+    pub role: String,
+    pub email_verified: bool,
+    pub scope: String,
+    /// The OAuth client a token was issued to. Set on service account
+    /// tokens, which have no login session behind them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
     pub iat: i64,
     pub exp: i64,
 }
 
 pub fn create_token(
-    user_id: &str,
-    secret: &[u8],
-    expires_in_sec: i64,
+    user: &User,
+    session_id: &str,
+    scope: &str,
+    key_ring: &KeyRing,
+    config: &Config,
 ) -> Result<String, jsonwebtoken::errors::Error> {
-    if user_id.is_empty() {
-        return Err(jsonwebtoken::errors::ErrorKind::InvalidSubject.into());
-    }
-
     let now = Utc::now();
     let iat = now.timestamp();
-    let exp = (now + Duration::minutes(expires_in_sec)).timestamp();
+    let exp = (now + Duration::minutes(config.jwt_maxage)).timestamp();
     let claims = TokenClaims {
-        sub: user_id.to_string(),
+        iss: config.jwt_issuer.to_owned(),
+        aud: config.jwt_audience.to_owned(),
+        sub: user.id.to_string(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: session_id.to_string(),
+        role: user.role.to_str().to_string(),
+        email_verified: user.verified,
+        scope: scope.to_string(),
+        client_id: None,
         iat,
         exp,
     };
 
-    encode(
-        &Header::default(), 
-        &claims, 
-        &EncodingKey::from_secret(secret)
-    )
+    encode_claims(&claims, key_ring)
 }
 
 pub fn decode_token<T: Into<String>>(
     token: T,
-    secret: &[u8]
-) -> Result<String, HttpError> {
-    let decode = decode::<TokenClaims>(
-        &token.into(), 
-        &DecodingKey::from_secret(secret), 
-        &Validation::new(Algorithm::HS256)
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<TokenClaims, HttpError> {
+    decode_claims(&token.into(), key_ring, &config.jwt_issuer, Some(&config.jwt_audience))
+}
+
+/// Creates an access token for an OAuth client. Its audience is the client,
+/// so it is never accepted by this service's own `auth` middleware.
+pub fn create_oauth_token(
+    user: &User,
+    grant_id: &str,
+    client_id: &str,
+    scope: &str,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = TokenClaims {
+        iss: config.jwt_issuer.to_owned(),
+        aud: client_id.to_string(),
+        sub: user.id.to_string(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: grant_id.to_string(),
+        role: user.role.to_str().to_string(),
+        email_verified: user.verified,
+        scope: scope.to_string(),
+        client_id: Some(client_id.to_string()),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(config.jwt_maxage)).timestamp(),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+/// Creates an access token for a service account from the
+/// client_credentials grant. It is addressed to this service, like a login
+/// token, but carries the client id in place of a session.
+pub fn create_service_account_token(
+    user: &User,
+    client_id: &str,
+    scope: &str,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = TokenClaims {
+        iss: config.jwt_issuer.to_owned(),
+        aud: config.jwt_audience.to_owned(),
+        sub: user.id.to_string(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: client_id.to_string(),
+        role: user.role.to_str().to_string(),
+        email_verified: user.verified,
+        scope: scope.to_string(),
+        client_id: Some(client_id.to_string()),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(config.jwt_maxage)).timestamp(),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+/// Decodes an access token issued to an OAuth client. The audience is the
+/// client, so the caller must check it belongs to a registered one.
+pub fn decode_oauth_token<T: Into<String>>(
+    token: T,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<TokenClaims, HttpError> {
+    decode_claims(&token.into(), key_ring, &config.jwt_issuer, None)
+}
+
+/// OpenID Connect ID token. Profile claims are only included when their
+/// scope was granted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub email_verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+pub fn create_id_token(
+    user: &User,
+    client_id: &str,
+    scope: &str,
+    nonce: Option<&str>,
+    auth_time: Option<DateTime<Utc>>,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let granted = |name: &str| scope.split_whitespace().any(|scope| scope == name);
+
+    let claims = IdTokenClaims {
+        iss: config.jwt_issuer.to_owned(),
+        sub: user.id.to_string(),
+        aud: client_id.to_string(),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(config.jwt_maxage)).timestamp(),
+        auth_time: auth_time.map(|auth_time| auth_time.timestamp()),
+        nonce: nonce.map(str::to_string),
+        email_verified: user.verified,
+        email: granted("email").then(|| user.email.to_owned()),
+        name: granted("profile").then(|| user.name.to_owned()),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+/// Claims of the token that carries a pending authorization request to the
+/// consent page and back, so the decision can't be forged cross-site.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConsentClaims {
+    pub iss: String,
+    pub aud: String,
+    pub sub: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub state: Option<String>,
+    pub code_challenge: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// The validated parameters of an authorization request awaiting consent.
+pub struct ConsentRequest<'a> {
+    pub client_id: &'a str,
+    pub redirect_uri: &'a str,
+    pub scope: &'a str,
+    pub state: Option<&'a str>,
+    pub code_challenge: &'a str,
+    pub nonce: Option<&'a str>,
+}
+
+pub fn create_consent_token(
+    user_id: &str,
+    request: ConsentRequest<'_>,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = ConsentClaims {
+        iss: config.jwt_issuer.to_owned(),
+        aud: consent_audience(config),
+        sub: user_id.to_string(),
+        client_id: request.client_id.to_string(),
+        redirect_uri: request.redirect_uri.to_string(),
+        scope: request.scope.to_string(),
+        state: request.state.map(str::to_string),
+        code_challenge: request.code_challenge.to_string(),
+        nonce: request.nonce.map(str::to_string),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(CONSENT_TOKEN_MAXAGE)).timestamp(),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+pub fn decode_consent_token<T: Into<String>>(
+    token: T,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<ConsentClaims, HttpError> {
+    decode_claims(&token.into(), key_ring, &config.jwt_issuer, Some(&consent_audience(config)))
+}
+
+fn consent_audience(config: &Config) -> String {
+    format!("{}/consent", config.jwt_audience)
+}
+
+/// Claims of the cookie that carries a sign-in with an external identity
+/// provider there and back. `link_user_id` is set when a logged-in user is
+/// linking the provider to their account instead of signing in.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SocialStateClaims {
+    pub iss: String,
+    pub aud: String,
+    pub provider: String,
+    pub state: String,
+    pub code_verifier: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_user_id: Option<String>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+pub fn create_social_state_token(
+    provider: &str,
+    state: &str,
+    code_verifier: &str,
+    nonce: Option<&str>,
+    link_user_id: Option<&str>,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = SocialStateClaims {
+        iss: config.jwt_issuer.to_owned(),
+        aud: social_state_audience(config),
+        provider: provider.to_string(),
+        state: state.to_string(),
+        code_verifier: code_verifier.to_string(),
+        nonce: nonce.map(str::to_string),
+        link_user_id: link_user_id.map(str::to_string),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(SOCIAL_STATE_MAXAGE)).timestamp(),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+pub fn decode_social_state_token<T: Into<String>>(
+    token: T,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<SocialStateClaims, HttpError> {
+    decode_claims(&token.into(), key_ring, &config.jwt_issuer, Some(&social_state_audience(config)))
+}
+
+fn social_state_audience(config: &Config) -> String {
+    format!("{}/social", config.jwt_audience)
+}
+
+/// Claims of the cookie holding the id of the AuthnRequest a browser was
+/// sent to the SAML IdP with.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SamlRequestClaims {
+    pub iss: String,
+    pub aud: String,
+    pub request_id: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+pub fn create_saml_request_token(
+    request_id: &str,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = SamlRequestClaims {
+        iss: config.jwt_issuer.to_owned(),
+        aud: saml_request_audience(config),
+        request_id: request_id.to_string(),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(SAML_REQUEST_MAXAGE)).timestamp(),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+pub fn decode_saml_request_token<T: Into<String>>(
+    token: T,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<SamlRequestClaims, HttpError> {
+    decode_claims(&token.into(), key_ring, &config.jwt_issuer, Some(&saml_request_audience(config)))
+}
+
+fn saml_request_audience(config: &Config) -> String {
+    format!("{}/saml", config.jwt_audience)
+}
+
+/// Claims of the short-lived challenge token returned by `login` when the
+/// user still has to pass a second factor.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaClaims {
+    pub iss: String,
+    pub aud: String,
+    pub sub: String,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+pub fn create_mfa_token(
+    user_id: &str,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = MfaClaims {
+        iss: config.jwt_issuer.to_owned(),
+        aud: mfa_audience(config),
+        sub: user_id.to_string(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(MFA_TOKEN_MAXAGE)).timestamp(),
+    };
+
+    encode_claims(&claims, key_ring)
+}
+
+pub fn decode_mfa_token<T: Into<String>>(
+    token: T,
+    key_ring: &KeyRing,
+    config: &Config,
+) -> Result<MfaClaims, HttpError> {
+    decode_claims(&token.into(), key_ring, &config.jwt_issuer, Some(&mfa_audience(config)))
+}
+
+/// MFA challenge tokens use their own audience so they can never be
+/// accepted as access tokens.
+fn mfa_audience(config: &Config) -> String {
+    format!("{}/mfa", config.jwt_audience)
+}
+
+fn encode_claims<C: Serialize>(
+    claims: &C,
+    key_ring: &KeyRing,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let key = key_ring.signing_key();
+    let encoding_key = key.encoding_key.as_ref()
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    encode(
+        &header, 
+        claims, 
+        encoding_key
+    )
+}
+
+fn decode_claims<C: DeserializeOwned>(
+    token: &str,
+    key_ring: &KeyRing,
+    issuer: &str,
+    audience: Option<&str>,
+) -> Result<C, HttpError> {
+    let invalid_token = || HttpError::new(ErrorMessage::InvalidToken.to_string(), StatusCode::UNAUTHORIZED);
+
+    let header = decode_header(token).map_err(|_| invalid_token())?;
+    let key = key_ring.find(header.kid.as_deref()).ok_or_else(invalid_token)?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[issuer]);
+    match audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+
+    let decode = decode::<C>(
+        token, 
+        &key.decoding_key, 
+        &validation
     );
 
     match decode {
-        Ok(token) => Ok(token.claims.sub),
-        Err(_) => Err(HttpError::new(ErrorMessage::InvalidToken.to_string(), StatusCode::UNAUTHORIZED))
+        Ok(token) => Ok(token.claims),
+        Err(_) => Err(invalid_token())
+    }
+}
+
+/// Prefix that marks a personal access token, so it is recognisable in
+/// logs and secret scanners and never confused with a JWT.
+pub const ACCESS_TOKEN_PREFIX: &str = "pat_";
+
+/// Scopes a personal access token can be granted.
+pub const ACCESS_TOKEN_SCOPES: [&str; 4] = ["profile:read", "profile:write", "users:read", "users:write"];
+
+/// Generates a personal access token. Only its hash is stored; the first
+/// characters are kept so users can tell their tokens apart.
+pub fn generate_access_token() -> (String, String) {
+    let token = format!("{}{}", ACCESS_TOKEN_PREFIX, generate_opaque_token());
+    let token_prefix = token[..ACCESS_TOKEN_PREFIX.len() + 8].to_string();
+
+    (token, token_prefix)
+}
+
+/// Generates a random opaque token suitable for refresh tokens.
+pub fn generate_opaque_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Hashes an opaque token so only its digest is ever stored.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::UserRole, test_support, utils::keys::JwtKey};
+
+    use super::*;
+
+    fn key_ring() -> KeyRing {
+        KeyRing::new(JwtKey::from_secret("default", b"test-secret"), Vec::new()).unwrap()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn carries_identity_and_authorization_claims() {
+        let config = test_support::config();
+        let user = test_support::user(UserRole::Admin);
+
+        let token = create_token(&user, "session-id", DEFAULT_SCOPE, &key_ring(), &config).unwrap();
+        let claims = decode_token(token, &key_ring(), &config).unwrap();
+
+        assert_eq!(claims.iss, config.jwt_issuer);
+        assert_eq!(claims.aud, config.jwt_audience);
+        assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.sid, "session-id");
+        assert_eq!(claims.role, "admin");
+        assert_eq!(claims.scope, DEFAULT_SCOPE);
+        assert!(claims.email_verified);
+        assert_ne!(claims.jti, claims.sid);
+    }
+
+    #[test]
+    fn rejects_tokens_for_another_issuer_or_audience() {
+        let config = test_support::config();
+        let user = test_support::user(UserRole::User);
+
+        let other_audience = Config { jwt_audience: "other-service".to_string(), ..config.clone() };
+        let token = create_token(&user, "session-id", DEFAULT_SCOPE, &key_ring(), &other_audience).unwrap();
+        assert!(decode_token(token, &key_ring(), &config).is_err());
+
+        let other_issuer = Config { jwt_issuer: "other-issuer".to_string(), ..config.clone() };
+        let token = create_token(&user, "session-id", DEFAULT_SCOPE, &key_ring(), &other_issuer).unwrap();
+        assert!(decode_token(token, &key_ring(), &config).is_err());
+    }
+}
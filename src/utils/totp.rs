@@ -0,0 +1,111 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::Utc;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::error::ErrorMessage;
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+const TOTP_SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn build_totp(secret: &str, issuer: &str, account_name: &str) -> Result<TOTP, ErrorMessage> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_| ErrorMessage::InvalidTwoFactorSecret)?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP,
+        secret,
+        Some(issuer.replace(':', "")),
+        account_name.replace(':', ""),
+    )
+    .map_err(|_| ErrorMessage::InvalidTwoFactorSecret)
+}
+
+/// Builds the `otpauth://` URI that authenticator apps import, usually via a QR code.
+pub fn provisioning_uri(secret: &str, issuer: &str, account_name: &str) -> Result<String, ErrorMessage> {
+    Ok(build_totp(secret, issuer, account_name)?.get_url())
+}
+
+/// Checks `code` against the current time step and one step either side.
+/// Returns the matching step so callers can reject replays of a code
+/// from a step that was already used.
+pub fn verify_code(secret: &str, code: &str) -> Result<Option<i64>, ErrorMessage> {
+    let totp = build_totp(secret, "", "")?;
+    let current_step = Utc::now().timestamp() / TOTP_STEP as i64;
+
+    let matched_step = (-TOTP_SKEW_STEPS..=TOTP_SKEW_STEPS)
+        .map(|offset| current_step + offset)
+        .find(|step| totp.check(code, *step as u64 * TOTP_STEP));
+
+    Ok(matched_step)
+}
+
+/// Whether `code` has the `xxxxx-xxxxx` shape of a recovery code, so
+/// other input never reaches the Argon2 comparisons.
+pub fn is_recovery_code(code: &str) -> bool {
+    let code = code.as_bytes();
+
+    code.len() == 11
+        && code[5] == b'-'
+        && code[..5].iter().chain(&code[6..]).all(u8::is_ascii_hexdigit)
+}
+
+/// Generates single-use recovery codes formatted as `xxxxx-xxxxx`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_current_code_only() {
+        let secret = generate_secret();
+        let totp = build_totp(&secret, "", "").unwrap();
+        let step = Utc::now().timestamp() / TOTP_STEP as i64;
+        let code = totp.generate(step as u64 * TOTP_STEP);
+
+        assert!(verify_code(&secret, &code).unwrap().is_some_and(|matched| (matched - step).abs() <= 1));
+
+        let stale = totp.generate((step - 5) as u64 * TOTP_STEP);
+        assert_eq!(verify_code(&secret, &stale).unwrap(), None);
+        assert!(verify_code("not base32!", &code).is_err());
+    }
+
+    #[test]
+    fn builds_provisioning_uris_for_authenticator_apps() {
+        let uri = provisioning_uri(&generate_secret(), "Auth: Validator", "user@example.com").unwrap();
+
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains("user%40example.com"));
+    }
+
+    #[test]
+    fn generates_distinct_recovery_codes() {
+        let codes = generate_recovery_codes();
+        let unique: std::collections::HashSet<_> = codes.iter().collect();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(unique.len(), codes.len());
+        assert!(codes.iter().all(|code| code.len() == 11 && code.as_bytes()[5] == b'-'));
+    }
+}
@@ -0,0 +1,534 @@
+use std::io::Cursor;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::ErrorMessage;
+
+/// COSE algorithm identifiers accepted for new credentials.
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_EDDSA: i64 = -8;
+pub const COSE_ALG_RS256: i64 = -257;
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256];
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// The relying party a ceremony is performed for.
+pub struct RelyingParty<'a> {
+    pub id: &'a str,
+    pub origin: &'a str,
+}
+
+/// A credential accepted by a registration ceremony.
+pub struct RegisteredCredential {
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    attested_credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Generates a random base64url encoded challenge.
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>, ErrorMessage> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| ErrorMessage::InvalidPasskey)
+}
+
+/// Verifies the response to `navigator.credentials.create()`.
+///
+/// Attestation statements are not checked against trust anchors: the
+/// options request `attestation: "none"`, so only the authenticator data
+/// and the client data are verified.
+pub fn verify_registration(
+    rp: &RelyingParty,
+    expected_challenge: &str,
+    client_data_json: &str,
+    attestation_object: &str,
+) -> Result<RegisteredCredential, ErrorMessage> {
+    verify_client_data(rp, "webauthn.create", expected_challenge, &decode(client_data_json)?)?;
+
+    let attestation_object: Value = ciborium::from_reader(decode(attestation_object)?.as_slice())
+        .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+    let auth_data = map_get(&attestation_object, &Value::Text("authData".to_string()))
+        .and_then(Value::as_bytes)
+        .ok_or(ErrorMessage::InvalidPasskey)?;
+
+    let auth_data = parse_authenticator_data(auth_data)?;
+    verify_authenticator_data(rp, &auth_data)?;
+
+    if auth_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err(ErrorMessage::InvalidPasskey);
+    }
+
+    let (credential_id, public_key) = auth_data.attested_credential
+        .ok_or(ErrorMessage::InvalidPasskey)?;
+
+    let algorithm = cose_algorithm(&public_key)?;
+    if !SUPPORTED_ALGORITHMS.contains(&algorithm) {
+        return Err(ErrorMessage::InvalidPasskey);
+    }
+
+    Ok(RegisteredCredential {
+        credential_id: encode(&credential_id),
+        public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Verifies the response to `navigator.credentials.get()` and returns the
+/// authenticator's new signature counter.
+pub fn verify_authentication(
+    rp: &RelyingParty,
+    expected_challenge: &str,
+    client_data_json: &str,
+    authenticator_data: &str,
+    signature: &str,
+    public_key: &[u8],
+    stored_sign_count: u32,
+) -> Result<u32, ErrorMessage> {
+    let client_data_json = decode(client_data_json)?;
+    verify_client_data(rp, "webauthn.get", expected_challenge, &client_data_json)?;
+
+    let raw_auth_data = decode(authenticator_data)?;
+    let auth_data = parse_authenticator_data(&raw_auth_data)?;
+    verify_authenticator_data(rp, &auth_data)?;
+
+    let mut signed_data = raw_auth_data.clone();
+    signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+
+    verify_signature(public_key, &signed_data, &decode(signature)?)?;
+
+    // Authenticators that do not implement a counter always report zero.
+    // Otherwise the counter must increase, or the credential may have been cloned.
+    if (auth_data.sign_count != 0 || stored_sign_count != 0) && auth_data.sign_count <= stored_sign_count {
+        return Err(ErrorMessage::PasskeyCounterRegression);
+    }
+
+    Ok(auth_data.sign_count)
+}
+
+fn verify_client_data(
+    rp: &RelyingParty,
+    ceremony_type: &str,
+    expected_challenge: &str,
+    client_data_json: &[u8],
+) -> Result<(), ErrorMessage> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+    if client_data.ceremony_type != ceremony_type
+        || client_data.challenge.trim_end_matches('=') != expected_challenge
+        || client_data.origin != rp.origin
+    {
+        return Err(ErrorMessage::InvalidPasskey);
+    }
+
+    Ok(())
+}
+
+fn verify_authenticator_data(
+    rp: &RelyingParty,
+    auth_data: &AuthenticatorData,
+) -> Result<(), ErrorMessage> {
+    if auth_data.rp_id_hash[..] != Sha256::digest(rp.id.as_bytes())[..] {
+        return Err(ErrorMessage::InvalidPasskey);
+    }
+
+    if auth_data.flags & FLAG_USER_PRESENT == 0 || auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(ErrorMessage::InvalidPasskey);
+    }
+
+    Ok(())
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData, ErrorMessage> {
+    if data.len() < 37 {
+        return Err(ErrorMessage::InvalidPasskey);
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // aaguid (16 bytes) followed by a big-endian u16 credential id length.
+        let rest = data.get(37 + 16..).ok_or(ErrorMessage::InvalidPasskey)?;
+        let id_length = rest.get(..2)
+            .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+            .ok_or(ErrorMessage::InvalidPasskey)?;
+        let credential_id = rest.get(2..2 + id_length).ok_or(ErrorMessage::InvalidPasskey)?;
+
+        // The COSE key is the next CBOR item; extensions may follow it.
+        let mut cursor = Cursor::new(&rest[2 + id_length..]);
+        let public_key: Value = ciborium::from_reader(&mut cursor)
+            .map_err(|_| ErrorMessage::InvalidPasskey)?;
+        let public_key_length = cursor.position() as usize;
+
+        if !public_key.is_map() {
+            return Err(ErrorMessage::InvalidPasskey);
+        }
+
+        Some((
+            credential_id.to_vec(),
+            rest[2 + id_length..2 + id_length + public_key_length].to_vec(),
+        ))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: data[..32].to_vec(),
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+fn map_get<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(entry_key, _)| entry_key == key)
+        .map(|(_, value)| value)
+}
+
+fn cose_get(key: &Value, label: i64) -> Option<&Value> {
+    map_get(key, &Value::Integer(label.into()))
+}
+
+fn cose_bytes(key: &Value, label: i64) -> Result<&[u8], ErrorMessage> {
+    cose_get(key, label)
+        .and_then(Value::as_bytes)
+        .map(Vec::as_slice)
+        .ok_or(ErrorMessage::InvalidPasskey)
+}
+
+fn cose_algorithm(public_key: &[u8]) -> Result<i64, ErrorMessage> {
+    let key: Value = ciborium::from_reader(public_key)
+        .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+    cose_get(&key, 3)
+        .and_then(Value::as_integer)
+        .and_then(|alg| i64::try_from(alg).ok())
+        .ok_or(ErrorMessage::InvalidPasskey)
+}
+
+fn verify_signature(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), ErrorMessage> {
+    let key: Value = ciborium::from_reader(public_key)
+        .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+    match cose_algorithm(public_key)? {
+        COSE_ALG_ES256 => {
+            use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            let mut point = vec![0x04];
+            point.extend_from_slice(cose_bytes(&key, -2)?);
+            point.extend_from_slice(cose_bytes(&key, -3)?);
+
+            let verifying_key = VerifyingKey::from_sec1_bytes(&point)
+                .map_err(|_| ErrorMessage::InvalidPasskey)?;
+            let signature = Signature::from_der(signature)
+                .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+            verifying_key.verify(message, &signature)
+                .map_err(|_| ErrorMessage::InvalidPasskey)
+        },
+        COSE_ALG_EDDSA => {
+            use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+            let public_key: [u8; 32] = cose_bytes(&key, -2)?
+                .try_into()
+                .map_err(|_| ErrorMessage::InvalidPasskey)?;
+            let verifying_key = VerifyingKey::from_bytes(&public_key)
+                .map_err(|_| ErrorMessage::InvalidPasskey)?;
+            let signature = Signature::from_slice(signature)
+                .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+            verifying_key.verify(message, &signature)
+                .map_err(|_| ErrorMessage::InvalidPasskey)
+        },
+        COSE_ALG_RS256 => {
+            use rsa::{pkcs1v15, signature::Verifier, BigUint, RsaPublicKey};
+
+            let public_key = RsaPublicKey::new(
+                BigUint::from_bytes_be(cose_bytes(&key, -1)?),
+                BigUint::from_bytes_be(cose_bytes(&key, -2)?),
+            )
+            .map_err(|_| ErrorMessage::InvalidPasskey)?;
+            let verifying_key = pkcs1v15::VerifyingKey::<Sha256>::new(public_key);
+            let signature = pkcs1v15::Signature::try_from(signature)
+                .map_err(|_| ErrorMessage::InvalidPasskey)?;
+
+            verifying_key.verify(message, &signature)
+                .map_err(|_| ErrorMessage::InvalidPasskey)
+        },
+        _ => Err(ErrorMessage::InvalidPasskey),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ciborium::Value;
+    use p256::ecdsa::{signature::Signer, DerSignature, SigningKey};
+
+    use super::*;
+
+    pub(crate) const RP_ID: &str = "localhost";
+    pub(crate) const ORIGIN: &str = "http://localhost:3000";
+    pub(crate) const FLAGS: u8 = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+
+    /// The browser-side response to a ceremony, base64url encoded.
+    pub(crate) struct ClientResponse {
+        pub client_data_json: String,
+        pub attestation_object: String,
+        pub authenticator_data: String,
+        pub signature: String,
+    }
+
+    /// An in-memory ES256 passkey that stands in for a hardware authenticator.
+    pub(crate) struct SoftwareAuthenticator {
+        pub credential_id: Vec<u8>,
+        pub sign_count: u32,
+        signing_key: SigningKey,
+    }
+
+    impl SoftwareAuthenticator {
+        pub fn new() -> Self {
+            let mut credential_id = vec![0u8; 16];
+            OsRng.fill_bytes(&mut credential_id);
+
+            Self {
+                credential_id,
+                sign_count: 0,
+                signing_key: SigningKey::random(&mut OsRng),
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.signing_key.verifying_key().to_encoded_point(false);
+            let key = Value::Map(vec![
+                (Value::Integer(1.into()), Value::Integer(2.into())),
+                (Value::Integer(3.into()), Value::Integer(COSE_ALG_ES256.into())),
+                (Value::Integer((-1).into()), Value::Integer(1.into())),
+                (Value::Integer((-2).into()), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::Integer((-3).into()), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&key, &mut bytes).unwrap();
+            bytes
+        }
+
+        fn authenticator_data(&self, rp_id: &str, flags: u8, attested: bool) -> Vec<u8> {
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(if attested { flags | FLAG_ATTESTED_CREDENTIAL_DATA } else { flags });
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+
+            if attested {
+                data.extend_from_slice(&[0u8; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                data.extend_from_slice(&self.cose_key());
+            }
+
+            data
+        }
+
+        /// Answers `navigator.credentials.create()`.
+        pub fn register(&self, rp_id: &str, origin: &str, challenge: &str, flags: u8) -> ClientResponse {
+            let attestation_object = Value::Map(vec![
+                (Value::Text("fmt".to_string()), Value::Text("none".to_string())),
+                (Value::Text("attStmt".to_string()), Value::Map(Vec::new())),
+                (Value::Text("authData".to_string()), Value::Bytes(self.authenticator_data(rp_id, flags, true))),
+            ]);
+
+            let mut attestation_bytes = Vec::new();
+            ciborium::into_writer(&attestation_object, &mut attestation_bytes).unwrap();
+
+            ClientResponse {
+                client_data_json: encode(&client_data("webauthn.create", challenge, origin)),
+                attestation_object: encode(&attestation_bytes),
+                authenticator_data: String::new(),
+                signature: String::new(),
+            }
+        }
+
+        /// Answers `navigator.credentials.get()`, bumping the counter first.
+        pub fn authenticate(&mut self, rp_id: &str, origin: &str, challenge: &str, flags: u8) -> ClientResponse {
+            self.sign_count += 1;
+
+            let client_data_json = client_data("webauthn.get", challenge, origin);
+            let authenticator_data = self.authenticator_data(rp_id, flags, false);
+
+            let mut signed_data = authenticator_data.clone();
+            signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+            let signature: DerSignature = self.signing_key.sign(&signed_data);
+
+            ClientResponse {
+                client_data_json: encode(&client_data_json),
+                attestation_object: String::new(),
+                authenticator_data: encode(&authenticator_data),
+                signature: encode(signature.as_bytes()),
+            }
+        }
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::json!({
+            "type": ceremony_type,
+            "challenge": challenge,
+            "origin": origin,
+            "crossOrigin": false,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn rp() -> RelyingParty<'static> {
+        RelyingParty { id: RP_ID, origin: ORIGIN }
+    }
+
+    fn register(authenticator: &SoftwareAuthenticator, challenge: &str) -> RegisteredCredential {
+        let response = authenticator.register(RP_ID, ORIGIN, challenge, FLAGS);
+
+        verify_registration(&rp(), challenge, &response.client_data_json, &response.attestation_object)
+            .ok()
+            .unwrap()
+    }
+
+    fn authenticate(
+        public_key: &[u8],
+        stored_sign_count: u32,
+        challenge: &str,
+        response: &ClientResponse,
+    ) -> Result<u32, ErrorMessage> {
+        verify_authentication(
+            &rp(),
+            challenge,
+            &response.client_data_json,
+            &response.authenticator_data,
+            &response.signature,
+            public_key,
+            stored_sign_count,
+        )
+    }
+
+    #[test]
+    fn accepts_registration_and_authentication() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let challenge = generate_challenge();
+
+        let credential = register(&authenticator, &challenge);
+        assert_eq!(credential.credential_id, encode(&authenticator.credential_id));
+        assert_eq!(credential.sign_count, 0);
+
+        let challenge = generate_challenge();
+        let response = authenticator.authenticate(RP_ID, ORIGIN, &challenge, FLAGS);
+
+        assert_eq!(authenticate(&credential.public_key, 0, &challenge, &response), Ok(1));
+    }
+
+    #[test]
+    fn rejects_wrong_origin() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let challenge = generate_challenge();
+
+        let response = authenticator.register(RP_ID, "https://evil.example", &challenge, FLAGS);
+        let result = verify_registration(&rp(), &challenge, &response.client_data_json, &response.attestation_object);
+        assert_eq!(result.err(), Some(ErrorMessage::InvalidPasskey));
+
+        let credential = register(&authenticator, &challenge);
+        let response = authenticator.authenticate(RP_ID, "https://evil.example", &challenge, FLAGS);
+        assert_eq!(authenticate(&credential.public_key, 0, &challenge, &response), Err(ErrorMessage::InvalidPasskey));
+    }
+
+    #[test]
+    fn rejects_wrong_rp_id_hash() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let challenge = generate_challenge();
+
+        let response = authenticator.register("evil.example", ORIGIN, &challenge, FLAGS);
+        let result = verify_registration(&rp(), &challenge, &response.client_data_json, &response.attestation_object);
+        assert_eq!(result.err(), Some(ErrorMessage::InvalidPasskey));
+
+        let credential = register(&authenticator, &challenge);
+        let response = authenticator.authenticate("evil.example", ORIGIN, &challenge, FLAGS);
+        assert_eq!(authenticate(&credential.public_key, 0, &challenge, &response), Err(ErrorMessage::InvalidPasskey));
+    }
+
+    #[test]
+    fn rejects_missing_user_presence_or_verification() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let challenge = generate_challenge();
+        let credential = register(&authenticator, &challenge);
+
+        for flags in [FLAG_USER_PRESENT, FLAG_USER_VERIFIED] {
+            let response = authenticator.register(RP_ID, ORIGIN, &challenge, flags);
+            let result = verify_registration(&rp(), &challenge, &response.client_data_json, &response.attestation_object);
+            assert_eq!(result.err(), Some(ErrorMessage::InvalidPasskey));
+
+            let response = authenticator.authenticate(RP_ID, ORIGIN, &challenge, flags);
+            assert_eq!(authenticate(&credential.public_key, 0, &challenge, &response), Err(ErrorMessage::InvalidPasskey));
+        }
+    }
+
+    #[test]
+    fn rejects_sign_count_that_does_not_increase() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let challenge = generate_challenge();
+        let credential = register(&authenticator, &challenge);
+
+        authenticator.sign_count = 4;
+        let response = authenticator.authenticate(RP_ID, ORIGIN, &challenge, FLAGS);
+
+        assert_eq!(authenticate(&credential.public_key, 4, &challenge, &response), Ok(5));
+        assert_eq!(authenticate(&credential.public_key, 5, &challenge, &response), Err(ErrorMessage::PasskeyCounterRegression));
+        assert_eq!(authenticate(&credential.public_key, 9, &challenge, &response), Err(ErrorMessage::PasskeyCounterRegression));
+    }
+
+    #[test]
+    fn rejects_wrong_challenge_or_key() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let challenge = generate_challenge();
+        let credential = register(&authenticator, &challenge);
+
+        let response = authenticator.authenticate(RP_ID, ORIGIN, &challenge, FLAGS);
+        assert_eq!(authenticate(&credential.public_key, 0, &generate_challenge(), &response), Err(ErrorMessage::InvalidPasskey));
+
+        let other_key = register(&SoftwareAuthenticator::new(), &challenge).public_key;
+        assert_eq!(authenticate(&other_key, 0, &challenge, &response), Err(ErrorMessage::InvalidPasskey));
+    }
+}